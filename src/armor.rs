@@ -0,0 +1,212 @@
+/**
+ * This file is part of snailcrypt-cli. For more information visit
+ * https://www snailcrypt.com
+ * Copyright (C) 2022-2023  Richard Bäck <richard.baeck@icloud.com>
+ *
+ * This program is free software; you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation; either version 2 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along
+ * with this program; if not, write to the Free Software Foundation, Inc.,
+ * 51 Franklin Street, Fifth Floor, Boston, MA 02110-1301 USA.
+ */
+
+
+use crate::error::Error;
+
+/// First line of an armored message.
+const BEGIN_MARKER: &str = "-----BEGIN SNAILCRYPT MESSAGE-----";
+
+/// Last line of an armored message.
+const END_MARKER: &str = "-----END SNAILCRYPT MESSAGE-----";
+
+/// Length of the lines the ciphertext is wrapped at.
+const LINE_LEN: usize = 64;
+
+/// Initial value of the CRC-24 checksum (as used by OpenPGP).
+const CRC24_INIT: u32 = 0xb704ce;
+
+/// Generator polynomial of the CRC-24 checksum.
+const CRC24_POLY: u32 = 0x1864cfb;
+
+fn crc24(data: &[u8]) -> u32 {
+	let mut crc: u32 = CRC24_INIT;
+	for byte in data {
+		crc ^= (*byte as u32) << 16;
+		for _ in 0..8 {
+			crc <<= 1;
+			if crc & 0x1000000 != 0 {
+				crc ^= CRC24_POLY;
+			}
+		}
+	}
+
+	return crc & 0xffffff;
+}
+
+/// Format the checksum line of a ciphertext.
+fn checksum_line(ciphertext: &str) -> String {
+	let crc: u32 = crc24(ciphertext.as_bytes());
+
+	return format!("={}", base64::encode(&crc.to_be_bytes()[1..]));
+}
+
+/// Wrap a ciphertext in BEGIN and END markers. The header lines are
+/// informational only, decoding relies on the ciphertext alone.
+pub fn encode(ciphertext: &str, headers: &[(&str, String)]) -> String {
+	let mut armored: String = String::new();
+	armored.push_str(BEGIN_MARKER);
+	armored.push('\n');
+
+	//=========================================================================
+	// Write the header lines followed by an empty line
+	for (key, value) in headers {
+		let value: String = value.split_whitespace().collect::<Vec<&str>>().join(" ");
+		armored.push_str(format!("{}: {}\n", key, value).as_str());
+	}
+	armored.push('\n');
+
+	//=========================================================================
+	// Write the wrapped ciphertext and its checksum
+	let chars: Vec<char> = ciphertext.chars().collect();
+	for line in chars.chunks(LINE_LEN) {
+		armored.extend(line.iter());
+		armored.push('\n');
+	}
+	armored.push_str(checksum_line(ciphertext).as_str());
+	armored.push('\n');
+
+	armored.push_str(END_MARKER);
+	armored.push('\n');
+
+	return armored;
+}
+
+/// Remove quoting prefixes (e.g. "> " or "> > ") and surrounding whitespace
+/// from a line.
+fn unquote(line: &str) -> &str {
+	return line.trim_start_matches(|c: char| c == '>' || c.is_whitespace()).trim_end();
+}
+
+/// Check whether a text contains an armored message.
+pub fn is_armored(text: &str) -> bool {
+	return text.lines().any(|line| unquote(line) == BEGIN_MARKER);
+}
+
+/// Armored message found within a text.
+pub struct Block {
+	/// Byte offset of the BEGIN line.
+	pub start: usize,
+	/// Byte offset after the END line, excluding its line break.
+	pub end: usize,
+	/// Quoting prefix of the BEGIN line (e.g. "> ").
+	pub prefix: String,
+}
+
+/// Find all armored messages within a text.
+pub fn find_blocks(text: &str) -> Vec<Block> {
+	let mut blocks: Vec<Block> = Vec::new();
+	let mut current: Option<(usize, String)> = None;
+	let mut offset: usize = 0;
+
+	for line in text.split_inclusive('\n') {
+		let content: &str = line.trim_end_matches(['\r', '\n']);
+		if unquote(content) == BEGIN_MARKER {
+			let prefix: &str = &content[..content.find(BEGIN_MARKER).unwrap_or(0)];
+			current = Some((offset, String::from(prefix)));
+		} else if unquote(content) == END_MARKER {
+			if let Some((start, prefix)) = current.take() {
+				blocks.push(Block {
+					start,
+					end: offset + content.len(),
+					prefix,
+				});
+			}
+		}
+		offset += line.len();
+	}
+
+	return blocks;
+}
+
+/// Check whether a line is a header line like "Lock-Date: ...". A ciphertext
+/// never contains a space, so it cannot be mistaken for one.
+fn is_header(line: &str) -> bool {
+	return match line.split_once(": ") {
+		Some((key, _value)) => !key.is_empty()
+			&& key.chars().all(|c| c.is_ascii_alphanumeric() || c == '-'),
+		None => false,
+	};
+}
+
+/// Check whether a line is a checksum line ("=" followed by 4 base64
+/// characters).
+fn is_checksum(line: &str) -> bool {
+	return line.len() == 5
+		&& line.starts_with('=')
+		&& line[1..].chars().all(|c| c.is_ascii_alphanumeric() || c == '+' || c == '/');
+}
+
+/// Get the value of a header line of the first armored message within a
+/// text.
+pub fn header(text: &str, key: &str) -> Option<String> {
+	return text.lines()
+		.map(unquote)
+		.skip_while(|line| *line != BEGIN_MARKER)
+		.skip(1)
+		.take_while(|line| *line != END_MARKER && is_header(line))
+		.filter_map(|line| line.split_once(": "))
+		.find(|(line_key, _value)| line_key.eq_ignore_ascii_case(key))
+		.map(|(_key, value)| String::from(value.trim()));
+}
+
+/// Extract the ciphertext of the first armored message within a text. Line
+/// endings, whitespace and quoting prefixes are tolerated. The checksum is
+/// verified if present.
+pub fn decode(text: &str) -> Result<String, Error> {
+	let lines: Vec<&str> = text.lines().map(unquote).collect();
+
+	let begin: usize = lines.iter()
+		.position(|line| *line == BEGIN_MARKER)
+		.ok_or_else(|| Error::Malformed(String::from("the armored message has no BEGIN line")))?;
+	let end: usize = lines.iter()
+		.skip(begin)
+		.position(|line| *line == END_MARKER)
+		.map(|offset| begin + offset)
+		.ok_or_else(|| Error::Malformed(String::from("the armored message is truncated")))?;
+
+	//=========================================================================
+	// Separate the ciphertext from the header lines and the checksum
+	let mut body: Vec<&str> = lines[begin + 1..end].iter()
+		.copied()
+		.filter(|line| !line.is_empty() && !is_header(line))
+		.collect();
+
+	let mut checksum: Option<&str> = None;
+	if let Some(last) = body.last() {
+		if is_checksum(last) {
+			checksum = Some(last);
+			body.pop();
+		}
+	}
+
+	let ciphertext: String = body.concat().split_whitespace().collect();
+	if ciphertext.is_empty() {
+		return Err(Error::Malformed(String::from("the armored message is empty")));
+	}
+
+	if let Some(checksum) = checksum {
+		if checksum != checksum_line(ciphertext.as_str()) {
+			return Err(Error::Malformed(String::from("the checksum of the armored message does not match")));
+		}
+	}
+
+	return Ok(ciphertext);
+}
@@ -0,0 +1,243 @@
+/**
+ * This file is part of snailcrypt-cli. For more information visit
+ * https://www snailcrypt.com
+ * Copyright (C) 2022-2023  Richard Bäck <richard.baeck@icloud.com>
+ *
+ * This program is free software; you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation; either version 2 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along
+ * with this program; if not, write to the Free Software Foundation, Inc.,
+ * 51 Franklin Street, Fifth Floor, Boston, MA 02110-1301 USA.
+ */
+
+
+use std::{
+	io::{
+		BufRead,
+		BufReader,
+		Read,
+		Write,
+	},
+	rc::Rc,
+};
+
+use chrono::{
+	DateTime,
+	FixedOffset,
+	Local,
+};
+use serde_json::{
+	json,
+	Map,
+	Value,
+};
+use snailcrypt::{
+	client,
+	factory,
+	util,
+};
+
+use crate::{
+	ciphertext,
+	error::Error,
+	payload,
+	settings,
+};
+
+/// Get an optional string member of a request.
+fn get_str<'a>(request: &'a Map<String, Value>,
+			   name: &str)
+	-> Result<Option<&'a str>, Error> {
+	match request.get(name) {
+		None | Some(Value::Null) => {
+			return Ok(None);
+		},
+		Some(Value::String(value)) => {
+			return Ok(Some(value.as_str()));
+		},
+		Some(_) => {
+			return Err(Error::Argument(format!("the member \"{}\" must be a string", name)));
+		},
+	}
+}
+
+/// Get an optional boolean member of a request.
+fn get_bool(request: &Map<String, Value>,
+			name: &str)
+	-> Result<Option<bool>, Error> {
+	match request.get(name) {
+		None | Some(Value::Null) => {
+			return Ok(None);
+		},
+		Some(Value::Bool(value)) => {
+			return Ok(Some(*value));
+		},
+		Some(_) => {
+			return Err(Error::Argument(format!("the member \"{}\" must be a boolean", name)));
+		},
+	}
+}
+
+/// Get a mandatory string member of a request.
+fn require_str<'a>(request: &'a Map<String, Value>,
+				   name: &str)
+	-> Result<&'a str, Error> {
+	return get_str(request, name)?.ok_or_else(|| {
+		Error::Argument(format!("the member \"{}\" is missing", name))
+	});
+}
+
+/// Handle an encryption request. The plaintext may be base64 encoded binary
+/// data if the member "encoding" is "base64".
+fn encrypt(client: &Rc<dyn client::Client>,
+		   request: &Map<String, Value>)
+	-> Result<Value, Error> {
+	let plaintext: &str = require_str(request, "plaintext")?;
+	let lockdate_str: &str = require_str(request, "lockdate")?;
+	let hint: &str = get_str(request, "hint")?.unwrap_or_default();
+	let force: bool = get_bool(request, "force")?.unwrap_or(false);
+
+	let data: Vec<u8>;
+	let binary: bool;
+	match get_str(request, "encoding")?.unwrap_or("utf-8") {
+		"utf-8" => {
+			data = plaintext.as_bytes().to_vec();
+			binary = false;
+		},
+		"base64" => {
+			data = base64::decode(plaintext).map_err(|error| {
+				Error::Parse(format!("unable to decode the base64 plaintext: {}", error))
+			})?;
+			binary = true;
+		},
+		encoding => {
+			return Err(Error::Argument(format!("unknown encoding \"{}\" (expected utf-8 or base64)", encoding)));
+		},
+	}
+
+	let lockdate: DateTime<FixedOffset> = ciphertext::resolve_lockdate(client, lockdate_str, None, force, false)?;
+	let ciphertext: String = ciphertext::encrypt(client, data, binary, lockdate, hint)?;
+
+	return Ok(json!({
+		"ciphertext": ciphertext,
+		"lockdate": lockdate.format(client.get_datetime_format()).to_string(),
+		"hint": hint,
+	}));
+}
+
+/// Handle a decryption request. The ciphertext may be given in any form
+/// accepted by -d, e.g. as timer URL or armored message.
+fn decrypt(analyzer: &Rc<dyn util::Analyzer>,
+		   client: &Rc<dyn client::Client>,
+		   request: &Map<String, Value>)
+	-> Result<Value, Error> {
+	let ciphertext: String = ciphertext::from_input(require_str(request, "ciphertext")?)?;
+	let lockdate: DateTime<FixedOffset> = ciphertext::lockdate(analyzer, client, ciphertext.as_str())?;
+	if lockdate > Local::now() {
+		return Err(Error::Locked(format!("the message is locked until {}",
+										 lockdate.format(client.get_datetime_format()))));
+	}
+
+	let success: client::ClientDecryptResultSuccess =
+		ciphertext::decrypt(analyzer, client, ciphertext.as_str())
+		.map_err(|failure| failure.error)?;
+	let (text, encoding) = payload::to_text(payload::decode(success.plaintext)?);
+
+	return Ok(json!({
+		"plaintext": text,
+		"encoding": encoding,
+		"hint": success.hint,
+		"lockdate": lockdate.format(client.get_datetime_format()).to_string(),
+	}));
+}
+
+/// Handle a single request line and build its response. The id of the request
+/// is echoed; requests without an id are identified by their line number.
+fn handle(analyzer: &Rc<dyn util::Analyzer>,
+		  client: &Rc<dyn client::Client>,
+		  line: &str,
+		  line_number: usize)
+	-> Value {
+	let mut id: Value = json!(line_number);
+	let result: Result<Value, Error> = match serde_json::from_str::<Value>(line) {
+		Ok(Value::Object(request)) => {
+			if let Some(request_id) = request.get("id") {
+				id = request_id.clone();
+			}
+
+			match get_str(&request, "op") {
+				Ok(Some("encrypt")) => encrypt(client, &request),
+				Ok(Some("decrypt")) => decrypt(analyzer, client, &request),
+				Ok(Some(op)) => Err(Error::Argument(format!("unknown operation \"{}\" (expected encrypt or decrypt)", op))),
+				Ok(None) => Err(Error::Argument(String::from("the member \"op\" is missing"))),
+				Err(error) => Err(error),
+			}
+		},
+		Ok(_) => Err(Error::Parse(String::from("the request is not a JSON object"))),
+		Err(error) => Err(Error::Parse(format!("unable to parse the request: {}", error))),
+	};
+
+	let mut response: Map<String, Value> = Map::new();
+	response.insert(String::from("id"), id);
+	match result {
+		Ok(Value::Object(fields)) => {
+			response.insert(String::from("ok"), Value::Bool(true));
+			response.extend(fields);
+		},
+		Ok(_) => {
+			response.insert(String::from("ok"), Value::Bool(true));
+		},
+		Err(error) => {
+			response.insert(String::from("ok"), Value::Bool(false));
+			response.insert(String::from("error"), error.to_json());
+		},
+	}
+
+	return Value::Object(response);
+}
+
+/// Process newline delimited JSON requests until the end of the input. Every
+/// request line is answered by exactly one response line. A failing request
+/// is reported in its response and does not abort the batch; only failures
+/// to read or write the streams do.
+pub fn batch(in_descriptor: Box<dyn Read>,
+			 mut out_descriptor: Box<dyn Write>)
+	-> Result<(), Error> {
+	//=========================================================================
+	// Setup client object. It is shared by all requests.
+	let analyzer_factory: factory::AnalyzerFactory = 
+		factory::AnalyzerFactory::new();
+	let analyzer = analyzer_factory.create();
+	
+	let config = settings::client_config();
+	
+	let client_factory: factory::ClientFactory = 
+		factory::ClientFactory::new(Rc::clone(&analyzer),
+									Rc::clone(&config));
+	let client = client_factory.create();
+
+	//=========================================================================
+	// Answer the requests. Every response is flushed right away, so the batch
+	// can be driven interactively through a pipe.
+	let reader: BufReader<Box<dyn Read>> = BufReader::new(in_descriptor);
+	for (index, line) in reader.lines().enumerate() {
+		let line: String = line?;
+		if line.trim().is_empty() {
+			continue;
+		}
+
+		let response: Value = handle(&analyzer, &client, line.as_str(), index + 1);
+		writeln!(out_descriptor, "{}", response)?;
+		out_descriptor.flush()?;
+	}
+
+	return Ok(());
+}
@@ -0,0 +1,366 @@
+/**
+ * This file is part of snailcrypt-cli. For more information visit
+ * https://www snailcrypt.com
+ * Copyright (C) 2022-2023  Richard Bäck <richard.baeck@icloud.com>
+ *
+ * This program is free software; you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation; either version 2 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along
+ * with this program; if not, write to the Free Software Foundation, Inc.,
+ * 51 Franklin Street, Fifth Floor, Boston, MA 02110-1301 USA.
+ */
+
+
+use std::{
+	io::{
+		BufRead,
+		BufReader,
+		Cursor,
+		Read,
+	},
+	rc::Rc,
+};
+
+use chrono::{
+	DateTime,
+	FixedOffset,
+	Local,
+	Timelike,
+};
+use snailcrypt::{
+	client,
+	util,
+};
+use url::Url;
+
+use crate::{
+	armor,
+	envelope,
+	error::{
+		catch_client,
+		Error,
+	},
+	icsparse,
+	lockdate,
+	payload,
+	qrdecode,
+	settings,
+};
+
+/// Name of the query parameter holding the ciphertext in a timer URL of the
+/// snailcrypt webapp.
+const URL_CIPHERTEXT_PARAM: &str = "c";
+
+/// Base URL of the timer of the public snailcrypt webapp.
+pub const DEFAULT_URL_BASE: &str = "https://webapp.snailcrypt.com/timer.php";
+
+/// Extract the ciphertext from an input. The input is either the ciphertext
+/// itself, an armored message or a timer URL of the snailcrypt webapp hosted
+/// anywhere. The ciphertext parameter of a URL may or may not be
+/// percent-encoded.
+pub fn from_input(input: &str) -> Result<String, Error> {
+	let trimmed: &str = input.trim();
+
+	if armor::is_armored(trimmed) {
+		return armor::decode(trimmed);
+	}
+
+	if let Ok(url) = Url::parse(trimmed) {
+		if url.scheme() == "http" || url.scheme() == "https" {
+			for (key, value) in url.query_pairs() {
+				if key == URL_CIPHERTEXT_PARAM {
+					//=========================================================
+					// A ciphertext never contains spaces, but the '+' of a
+					// ciphertext which has not been percent-encoded is
+					// decoded to one
+					return Ok(value.replace(' ', "+"));
+				}
+			}
+
+			return Err(Error::Malformed(format!("the URL does not contain a \"{}\" parameter holding the ciphertext",
+												URL_CIPHERTEXT_PARAM)));
+		}
+	}
+
+	return Ok(String::from(trimmed));
+}
+
+/// Parse the base URL of a timer of the snailcrypt webapp.
+pub fn parse_url_base(url_base_str: &str) -> Result<Url, Error> {
+	let url_base: Url = Url::parse(url_base_str.trim()).map_err(|error| {
+		Error::Parse(format!("unable to parse the URL base \"{}\": {}", url_base_str, error))
+	})?;
+	if url_base.scheme() != "http" && url_base.scheme() != "https" {
+		return Err(Error::Argument(format!("the URL base \"{}\" must use http or https", url_base_str)));
+	}
+
+	return Ok(url_base);
+}
+
+/// Build a timer URL of the snailcrypt webapp containing the ciphertext. The
+/// query parameters of the base URL are preserved, except for a ciphertext
+/// parameter, which is replaced.
+pub fn to_url(url_base: &Url, ciphertext: &str) -> String {
+	let mut url: Url = url_base.clone();
+	let query_pairs: Vec<(String, String)> = url_base.query_pairs()
+		.filter(|(key, _value)| key != URL_CIPHERTEXT_PARAM)
+		.map(|(key, value)| (key.into_owned(), value.into_owned()))
+		.collect();
+
+	url.query_pairs_mut()
+		.clear()
+		.extend_pairs(query_pairs)
+		.append_pair(URL_CIPHERTEXT_PARAM, ciphertext);
+
+	return String::from(url.as_str());
+}
+
+/// Get the client version of a ciphertext.
+pub fn version(analyzer: &Rc<dyn util::Analyzer>,
+			   ciphertext: &str)
+	-> Result<client::ClientVersion, Error> {
+	if ciphertext.is_empty() {
+		return Err(Error::Malformed(String::from("the ciphertext is empty")));
+	}
+
+	return analyzer.get_version(ciphertext).map_err(Error::Malformed);
+}
+
+/// Extract the lock date from a ciphertext of any client version. Contrary to
+/// client::Client::lockdate_from_snailcrypt_cipher this reports a malformed
+/// ciphertext as an error.
+pub fn lockdate(analyzer: &Rc<dyn util::Analyzer>,
+				client: &Rc<dyn client::Client>,
+				ciphertext: &str)
+	-> Result<DateTime<FixedOffset>, Error> {
+	let component_count: usize = match version(analyzer, ciphertext)? {
+		client::ClientVersion::V1 => 3,
+		client::ClientVersion::V2 => 4,
+	};
+
+	let cipher_comp_vec: Vec<&str> = ciphertext.split_terminator(':').collect();
+	if cipher_comp_vec.len() != component_count {
+		return Err(Error::Malformed(format!("the ciphertext must consist of {} components separated by a colon",
+											component_count)));
+	}
+
+	let lockdate_bytes: Vec<u8> = base64::decode(cipher_comp_vec[1]).map_err(|_error| {
+		Error::Malformed(String::from("the lock date of the ciphertext is malformed"))
+	})?;
+	let lockdate_str: String = String::from_utf8(lockdate_bytes).map_err(|_error| {
+		Error::Malformed(String::from("the lock date of the ciphertext is malformed"))
+	})?;
+
+	return DateTime::parse_from_str(lockdate_str.as_str(), client.get_datetime_format())
+		.map_err(|_error| {
+		Error::Malformed(format!("the lock date \"{}\" of the ciphertext is malformed", lockdate_str))
+	});
+}
+
+/// Extract the hint from a ciphertext of any client version. Ciphertexts of
+/// version 1 do not carry a hint.
+pub fn hint(analyzer: &Rc<dyn util::Analyzer>,
+			ciphertext: &str)
+	-> Result<String, Error> {
+	if version(analyzer, ciphertext)? == client::ClientVersion::V1 {
+		return Ok(String::new());
+	}
+
+	let cipher_comp_vec: Vec<&str> = ciphertext.split_terminator(':').collect();
+	if cipher_comp_vec.len() != 4 {
+		return Err(Error::Malformed(String::from("the ciphertext must consist of 4 components separated by a colon")));
+	}
+
+	let hint_bytes: Vec<u8> = base64::decode(cipher_comp_vec[3]).map_err(|_error| {
+		Error::Malformed(String::from("the hint of the ciphertext is malformed"))
+	})?;
+
+	return String::from_utf8(hint_bytes).map_err(|_error| {
+		Error::Malformed(String::from("the hint of the ciphertext is malformed"))
+	});
+}
+
+/// Resolve the lock date of a message to encrypt. The lock date is either a
+/// calendar event (optionally picking an occurrence of a recurring one) or any
+/// date accepted by lockdate::resolve. If note is true a lock date which is
+/// not given in the date time format of the client is noted on stderr. Unless
+/// force is true a lock date in the past is rejected.
+pub fn resolve_lockdate(client: &Rc<dyn client::Client>,
+						lockdate_str: &str,
+						occurrence_str: Option<&str>,
+						force: bool,
+						note: bool)
+	-> Result<DateTime<FixedOffset>, Error> {
+	//=========================================================================
+	// Retrieve the current date
+	let date_now: DateTime<FixedOffset> = Local::now()
+									.with_timezone(
+										&FixedOffset::east_opt(0)
+										.unwrap_or_else(|| {
+											panic!("Error: unexpected error during conversion of current date time.");
+										}))
+									.with_nanosecond(0)
+									.unwrap_or_else(|| {
+										panic!("Error: unexpected error during conversion of current date time.");
+									});
+
+	//=========================================================================
+	// Parse lock date
+	let lockdate: DateTime<FixedOffset>;
+	if icsparse::is_reference(lockdate_str) {
+		lockdate = icsparse::resolve(lockdate_str, occurrence_str)?;
+	} else if occurrence_str.is_some() {
+		return Err(Error::Argument(String::from("--occurrence requires the lock date to be a calendar event")));
+	} else {
+		lockdate = lockdate::resolve(lockdate_str,
+									 date_now,
+									 client.get_datetime_format())?;
+	}
+	if note == true
+		&& DateTime::parse_from_str(lockdate_str, client.get_datetime_format()).is_err() {
+		eprintln!("Lock date: {}", lockdate.format(client.get_datetime_format()));
+	}
+
+	//=========================================================================
+	// Exit on lockdate in the past
+	if force == false && lockdate <= date_now {
+		return Err(Error::Argument(format!("lock date \"{}\" is in the past.",
+										   lockdate.format(client.get_datetime_format()))));
+	}
+
+	return Ok(lockdate);
+}
+
+/// Encrypt data using the client. Binary data is encoded such that it can be
+/// told apart from text when the message is decrypted.
+pub fn encrypt(client: &Rc<dyn client::Client>,
+			   data: Vec<u8>,
+			   binary: bool,
+			   lockdate: DateTime<FixedOffset>,
+			   hint: &str)
+	-> Result<String, Error> {
+	return catch_client(|| {
+		client.encrypt(&client::ClientEncryptArg {
+			plaintext: payload::encode(data, binary),
+			lockdate,
+			hint: String::from(hint),
+		})
+	})?.map_err(Error::Server);
+}
+
+/// Failure of a decryption together with the hint of the ciphertext (if it
+/// could be retrieved).
+pub struct DecryptFailure {
+	pub error: Error,
+	pub hint: String,
+}
+
+/// Decrypt a ciphertext using the client. Contrary to client::Client::decrypt
+/// this validates the ciphertext first and tells a message which is still
+/// locked apart from other failures.
+pub fn decrypt(analyzer: &Rc<dyn util::Analyzer>,
+			   client: &Rc<dyn client::Client>,
+			   ciphertext: &str)
+	-> Result<client::ClientDecryptResultSuccess, DecryptFailure> {
+	//=========================================================================
+	// Validate the ciphertext
+	let lockdate: DateTime<FixedOffset> = lockdate(analyzer, client, ciphertext)
+		.map_err(|error| DecryptFailure { error, hint: String::new() })?;
+	let hint: String = hint(analyzer, ciphertext)
+		.map_err(|error| DecryptFailure { error, hint: String::new() })?;
+
+	//=========================================================================
+	// Decrypt the ciphertext
+	let failure: client::ClientDecryptResultFailure =
+		match catch_client(|| client.decrypt(ciphertext)) {
+		Ok(Ok(success)) => {
+			return Ok(success);
+		},
+		Ok(Err(failure)) => failure,
+		Err(error) => {
+			return Err(DecryptFailure { error, hint });
+		},
+	};
+
+	//=========================================================================
+	// Tell a message which is still locked apart from other failures
+	let error: Error;
+	if lockdate > Local::now() || failure.error_message.contains("not been yet released") {
+		error = Error::Locked(format!("the message is locked until {}",
+									  lockdate.format(client.get_datetime_format())));
+	} else {
+		error = Error::Server(failure.error_message);
+	}
+
+	return Err(DecryptFailure {
+		error,
+		hint: failure.hint,
+	});
+}
+
+/// Ciphertext read from the input of a decryption.
+pub struct DecryptInput {
+	/// Whether the input is an envelope container. Its reader is positioned
+	/// at the encrypted stream then.
+	pub is_envelope: bool,
+	pub reader: Box<dyn BufRead>,
+	pub ciphertext: String,
+	/// Server the message has been encrypted with according to the header of
+	/// an armored message. Armored messages without the header have been
+	/// encrypted with the public server.
+	pub server: Option<String>,
+}
+
+/// Read the ciphertext of a decryption or an inspection. The ciphertext of an
+/// envelope container is its time-locked key. A timer URL of the webapp is
+/// accepted as well, also from a QR code image.
+pub fn read(mut in_descriptor: Box<dyn Read>) -> Result<DecryptInput, Error> {
+	//=========================================================================
+	// Detect an envelope container
+	let (is_envelope, prefix) = envelope::detect(in_descriptor.as_mut())?;
+	let is_image: bool = qrdecode::is_image(prefix.as_slice());
+	let mut reader: Box<dyn BufRead>;
+	if is_envelope == true {
+		reader = Box::new(BufReader::new(in_descriptor));
+	} else {
+		reader = Box::new(BufReader::new(Cursor::new(prefix).chain(in_descriptor)));
+	}
+	
+	//=========================================================================
+	// Retrieve ciphertext
+	let ciphertext: String;
+	let mut server: Option<String> = None;
+	if is_envelope == true {
+		ciphertext = envelope::read_locked_key(reader.as_mut())?;
+	} else if is_image == true {
+		let mut image: Vec<u8> = Vec::new();
+		reader.read_to_end(&mut image)?;
+		ciphertext = qrdecode::decode_image(image.as_slice())?;
+	} else {
+		let mut data: Vec<u8> = Vec::new();
+		reader.read_to_end(&mut data)?;
+		ciphertext = String::from_utf8(data).map_err(|_error| {
+			Error::Malformed(String::from("the input is neither an encrypted message nor a QR code image"))
+		})?;
+		if armor::is_armored(ciphertext.trim()) {
+			server = Some(armor::header(ciphertext.as_str(), "Server")
+						  .map(|server| settings::parse_server(server.as_str()).unwrap_or(server))
+						  .unwrap_or_else(|| String::from(settings::DEFAULT_SERVER)));
+		}
+	}
+	
+	return Ok(DecryptInput {
+		is_envelope,
+		reader,
+		ciphertext: from_input(ciphertext.as_str())?,
+		server,
+	});
+}
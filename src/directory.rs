@@ -0,0 +1,310 @@
+/**
+ * This file is part of snailcrypt-cli. For more information visit
+ * https://www snailcrypt.com
+ * Copyright (C) 2022-2023  Richard Bäck <richard.baeck@icloud.com>
+ *
+ * This program is free software; you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation; either version 2 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along
+ * with this program; if not, write to the Free Software Foundation, Inc.,
+ * 51 Franklin Street, Fifth Floor, Boston, MA 02110-1301 USA.
+ */
+
+
+use std::{
+	fs::{
+		self,
+		File,
+	},
+	io::Write,
+	path::{
+		Path,
+		PathBuf,
+	},
+	rc::Rc,
+};
+
+use chrono::{
+	DateTime,
+	FixedOffset,
+	Local,
+};
+use serde_json::{
+	json,
+	Value,
+};
+use snailcrypt::factory;
+
+use crate::{
+	ciphertext,
+	decrypt,
+	error::Error,
+	lockdate,
+	settings,
+	table,
+	DecryptArg,
+};
+
+/// Suffix of encrypted files which is removed from the name of the plaintext
+/// file.
+const ENCRYPTED_SUFFIX: &str = ".snail";
+
+/// Suffix appended to the name of the plaintext file of an encrypted file
+/// without ENCRYPTED_SUFFIX.
+const DECRYPTED_SUFFIX: &str = ".decrypted";
+
+/// Suffix of the temporary file a plaintext is written to. It is renamed once
+/// the decryption succeeded.
+const PARTIAL_SUFFIX: &str = ".part";
+
+/// Characters making an input a glob pattern.
+const GLOB_CHARS: [char; 3] = ['*', '?', '['];
+
+/// Options of the decryption of several files.
+pub struct DirectoryArg {
+	/// Directory or glob pattern naming the encrypted files.
+	pub input: String,
+	/// Directory the plaintexts are written to. If it is None, then every
+	/// plaintext is written next to its encrypted file.
+	pub output_dir: Option<PathBuf>,
+	pub json: bool,
+}
+
+/// A file which is still locked.
+struct Locked {
+	file: PathBuf,
+	lockdate: DateTime<FixedOffset>,
+	hint: String,
+}
+
+/// Whether the input of -d names several files, i.e. it is a directory or a
+/// glob pattern.
+pub fn is_collection(input: &str) -> bool {
+	let path: &Path = Path::new(input);
+	if path.is_dir() {
+		return true;
+	}
+
+	return path.exists() == false && input.contains(GLOB_CHARS);
+}
+
+/// List the files of a directory or the files matching a glob pattern. Hidden
+/// files and partially written plaintexts are ignored.
+pub fn list_files(input: &str) -> Result<Vec<PathBuf>, Error> {
+	let mut files: Vec<PathBuf> = Vec::new();
+	if Path::new(input).is_dir() {
+		for entry in fs::read_dir(input).map_err(|error| {
+			Error::Io(format!("unable to read the directory \"{}\": {}", input, error))
+		})? {
+			files.push(entry?.path());
+		}
+	} else {
+		let paths = glob::glob(input).map_err(|error| {
+			Error::Argument(format!("unable to parse the pattern \"{}\": {}", input, error))
+		})?;
+		for path in paths {
+			files.push(path.map_err(|error| Error::Io(error.to_string()))?);
+		}
+		if files.is_empty() {
+			return Err(Error::Argument(format!("no file matches the pattern \"{}\"", input)));
+		}
+	}
+
+	files.retain(|file| {
+		let name: String = file.file_name().unwrap_or_default().to_string_lossy().into_owned();
+		return file.is_file()
+			&& !name.starts_with('.')
+			&& !name.ends_with(PARTIAL_SUFFIX);
+	});
+	files.sort();
+
+	return Ok(files);
+}
+
+/// Get the path of the plaintext of an encrypted file.
+pub fn output_path(file: &Path, output_dir: &Option<PathBuf>) -> PathBuf {
+	let name: String = file.file_name().unwrap_or_default().to_string_lossy().into_owned();
+	let output_name: String = match name.strip_suffix(ENCRYPTED_SUFFIX) {
+		Some(stem) if !stem.is_empty() => String::from(stem),
+		_ => format!("{}{}", name, DECRYPTED_SUFFIX),
+	};
+
+	match output_dir {
+		Some(output_dir) => {
+			return output_dir.join(output_name);
+		},
+		None => {
+			return file.with_file_name(output_name);
+		},
+	}
+}
+
+/// Decrypt a file into its plaintext file using the regular decryption.
+pub fn decrypt_file(file: &Path, output: &Path) -> Result<(), Error> {
+	let in_descriptor: File = File::open(file)?;
+
+	let mut partial: PathBuf = output.to_path_buf();
+	partial.as_mut_os_string().push(PARTIAL_SUFFIX);
+	let out_descriptor: File = File::create(&partial).map_err(|error| {
+		Error::Io(format!("unable to create the output file \"{}\": {}", partial.display(), error))
+	})?;
+
+	let result: Result<(), Error> = decrypt(&DecryptArg {
+												extract_hint: false,
+												wait: false,
+												max_wait_str: None,
+												json: false,
+												json_default: false,
+												on_unlock: None,
+												on_unlock_timeout_str: None,
+												input_filename: None,
+												output_filename: None,
+											},
+											Box::new(in_descriptor),
+											Box::new(out_descriptor))
+		.and_then(|()| fs::rename(&partial, output).map_err(Error::from));
+	if result.is_err() {
+		let _ = fs::remove_file(&partial);
+	}
+
+	return result;
+}
+
+/// Decrypt every encrypted file of a directory or matching a glob pattern.
+/// Files which are still locked are left alone and reported in a table
+/// holding their lock dates and hints. Files which are not encrypted messages
+/// are skipped. A failing file does not stop the run, but the error of the
+/// first failing file is returned in the end.
+pub fn decrypt_all(args: &DirectoryArg,
+				   mut out_descriptor: Box<dyn Write>)
+	-> Result<(), Error> {
+	let files: Vec<PathBuf> = list_files(args.input.as_str())?;
+	if let Some(output_dir) = &args.output_dir {
+		fs::create_dir_all(output_dir).map_err(|error| {
+			Error::Io(format!("unable to create the output directory \"{}\": {}", output_dir.display(), error))
+		})?;
+	}
+
+	//=========================================================================
+	// Setup client object. It is used to read the lock dates only.
+	let analyzer_factory: factory::AnalyzerFactory = 
+		factory::AnalyzerFactory::new();
+	let analyzer = analyzer_factory.create();
+	
+	let config = settings::client_config();
+	
+	let client_factory: factory::ClientFactory = 
+		factory::ClientFactory::new(Rc::clone(&analyzer),
+									Rc::clone(&config));
+	let client = client_factory.create();
+
+	//=========================================================================
+	// Decrypt the files whose lock date has passed
+	let mut decrypted: Vec<Value> = Vec::new();
+	let mut locked: Vec<Locked> = Vec::new();
+	let mut skipped: Vec<Value> = Vec::new();
+	let mut failed: Vec<Value> = Vec::new();
+	let mut first_error: Option<Error> = None;
+	for file in files {
+		let ciphertext::DecryptInput { ciphertext, .. } = match File::open(&file)
+			.map_err(Error::from)
+			.and_then(|in_descriptor| ciphertext::read(Box::new(in_descriptor))) {
+			Ok(input) => input,
+			Err(Error::Malformed(message)) => {
+				skipped.push(json!({ "file": file.to_string_lossy(), "reason": message }));
+				continue;
+			},
+			Err(error) => {
+				if args.json == false {
+					eprintln!("Error: {}: {}", file.display(), error);
+				}
+				failed.push(json!({ "file": file.to_string_lossy(), "error": error.to_json() }));
+				first_error.get_or_insert(error);
+				continue;
+			},
+		};
+		let lockdate: DateTime<FixedOffset> = match ciphertext::lockdate(&analyzer, &client, ciphertext.as_str()) {
+			Ok(lockdate) => lockdate,
+			Err(error) => {
+				skipped.push(json!({ "file": file.to_string_lossy(), "reason": error.message() }));
+				continue;
+			},
+		};
+		let hint: String = ciphertext::hint(&analyzer, ciphertext.as_str()).unwrap_or_default();
+
+		//=====================================================================
+		// Only contact the server for files whose lock date has passed
+		if lockdate > Local::now() {
+			locked.push(Locked { file, lockdate, hint });
+			continue;
+		}
+
+		let output: PathBuf = output_path(file.as_path(), &args.output_dir);
+		match decrypt_file(file.as_path(), output.as_path()) {
+			Ok(()) => {
+				if args.json == false {
+					eprintln!("Decrypted {} to {}", file.display(), output.display());
+				}
+				decrypted.push(json!({ "file": file.to_string_lossy(), "output": output.to_string_lossy() }));
+			},
+			Err(Error::Locked(_)) => {
+				locked.push(Locked { file, lockdate, hint });
+			},
+			Err(error) => {
+				if args.json == false {
+					eprintln!("Error: {}: {}", file.display(), error);
+				}
+				failed.push(json!({ "file": file.to_string_lossy(), "error": error.to_json() }));
+				first_error.get_or_insert(error);
+			},
+		}
+	}
+
+	//=========================================================================
+	// Report the files which are still locked
+	if args.json == true {
+		let report: Value = json!({
+			"decrypted": decrypted,
+			"locked": locked.iter().map(|locked| json!({
+				"file": locked.file.to_string_lossy(),
+				"lockdate": locked.lockdate.format(client.get_datetime_format()).to_string(),
+				"hint": locked.hint,
+			})).collect::<Vec<Value>>(),
+			"skipped": skipped,
+			"failed": failed,
+		});
+		writeln!(out_descriptor, "{}", report)?;
+	} else {
+		if !locked.is_empty() {
+			let rows: Vec<Vec<String>> = locked.iter().map(|locked| vec![
+				locked.file.display().to_string(),
+				locked.lockdate.format(client.get_datetime_format()).to_string(),
+				lockdate::format_duration(locked.lockdate.signed_duration_since(Local::now()).num_seconds()),
+				locked.hint.clone(),
+			]).collect();
+			table::write(out_descriptor.as_mut(),
+						 &["FILE", "LOCK DATE", "REMAINING", "HINT"],
+						 rows.as_slice())?;
+		}
+		
+		eprintln!("Decrypted {} file(s), {} still locked, {} failed, {} skipped.",
+				  decrypted.len(), locked.len(), failed.len(), skipped.len());
+	}
+
+	match first_error {
+		Some(error) => {
+			return Err(error);
+		},
+		None => {
+			return Ok(());
+		},
+	}
+}
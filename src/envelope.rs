@@ -0,0 +1,261 @@
+/**
+ * This file is part of snailcrypt-cli. For more information visit
+ * https://www snailcrypt.com
+ * Copyright (C) 2022-2023  Richard Bäck <richard.baeck@icloud.com>
+ *
+ * This program is free software; you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation; either version 2 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along
+ * with this program; if not, write to the Free Software Foundation, Inc.,
+ * 51 Franklin Street, Fifth Floor, Boston, MA 02110-1301 USA.
+ */
+
+
+use std::{
+	io::{
+		BufRead,
+		Read,
+		Write,
+	},
+	rc::Rc,
+};
+
+use chrono::{
+	DateTime,
+	FixedOffset,
+};
+use openssl::{
+	rand::rand_bytes,
+	symm::{
+		Cipher,
+		decrypt_aead,
+		encrypt_aead,
+	},
+};
+use snailcrypt::client;
+
+use crate::error::{
+	catch_client,
+	Error,
+};
+
+/// First line of an envelope container.
+pub const MAGIC: &[u8] = b"SNAILCRYPT-ENVELOPE-1\n";
+
+/// Size of the symmetric key in bytes (AES-256-GCM).
+const KEY_LEN: usize = 32;
+
+/// Size of the random part of the nonce in bytes. The remaining 5 bytes of the
+/// 12 byte nonce are the chunk counter and the last chunk flag.
+const NONCE_PREFIX_LEN: usize = 7;
+
+/// Size of the authentication tag appended to every chunk in bytes.
+const TAG_LEN: usize = 16;
+
+/// Size of a plaintext chunk in bytes.
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// Flag of a chunk which is followed by another chunk.
+const CHUNK_FLAG_MORE: u8 = 0;
+
+/// Flag of the last chunk of the stream.
+const CHUNK_FLAG_LAST: u8 = 1;
+
+/// Build the nonce of a chunk. Binding the counter and the last chunk flag to
+/// the nonce detects reordered, removed and truncated chunks.
+fn chunk_nonce(nonce_prefix: &[u8], counter: u32, flag: u8) -> Vec<u8> {
+	let mut nonce: Vec<u8> = Vec::with_capacity(12);
+	nonce.extend_from_slice(nonce_prefix);
+	nonce.extend_from_slice(&counter.to_be_bytes());
+	nonce.push(flag);
+
+	return nonce;
+}
+
+/// Read until `buffer` is full or the end of the input has been reached.
+/// Returns the number of bytes read.
+fn read_full(in_descriptor: &mut dyn Read, buffer: &mut [u8]) -> Result<usize, Error> {
+	let mut filled: usize = 0;
+	while filled < buffer.len() {
+		let count: usize = in_descriptor.read(&mut buffer[filled..])?;
+		if count == 0 {
+			break;
+		}
+		filled += count;
+	}
+
+	return Ok(filled);
+}
+
+/// Check whether the input starts with the envelope magic. The bytes consumed
+/// for the check are returned, so they can be reused if the input is not an
+/// envelope container.
+pub fn detect(in_descriptor: &mut dyn Read) -> Result<(bool, Vec<u8>), Error> {
+	let mut prefix: Vec<u8> = vec![0; MAGIC.len()];
+	let count: usize = read_full(in_descriptor, prefix.as_mut_slice())?;
+	prefix.truncate(count);
+
+	return Ok((prefix.as_slice() == MAGIC, prefix));
+}
+
+/// Read the time-locked key following the magic of an envelope container.
+pub fn read_locked_key(in_reader: &mut dyn BufRead) -> Result<String, Error> {
+	let mut locked_key: String = String::new();
+	in_reader.read_line(&mut locked_key)?;
+	if !locked_key.ends_with('\n') {
+		return Err(Error::Malformed(String::from("the envelope is truncated")));
+	}
+	locked_key.pop();
+
+	return Ok(locked_key);
+}
+
+/// Encrypt the input into an envelope container. The input is encrypted
+/// locally using a fresh key in chunks of constant size. Only the key is
+/// encrypted using the snailcrypt client. The time-locked key is returned.
+pub fn seal(client: &Rc<dyn client::Client>,
+			lockdate: DateTime<FixedOffset>,
+			hint: &str,
+			in_descriptor: &mut dyn Read,
+			out_descriptor: &mut dyn Write)
+	-> Result<String, Error> {
+	//=========================================================================
+	// Generate the key and the nonce prefix
+	let mut key: Vec<u8> = vec![0; KEY_LEN];
+	let mut nonce_prefix: Vec<u8> = vec![0; NONCE_PREFIX_LEN];
+	rand_bytes(key.as_mut_slice()).map_err(|error| {
+		Error::Io(format!("unable to generate a key: {}", error))
+	})?;
+	rand_bytes(nonce_prefix.as_mut_slice()).map_err(|error| {
+		Error::Io(format!("unable to generate a nonce: {}", error))
+	})?;
+
+	//=========================================================================
+	// Time-lock the key
+	let locked_key: String = catch_client(|| {
+		client.encrypt(&client::ClientEncryptArg {
+			plaintext: base64::encode(key.as_slice()),
+			lockdate,
+			hint: String::from(hint),
+		})
+	})?.map_err(Error::Server)?;
+
+	//=========================================================================
+	// Write the header
+	out_descriptor.write_all(MAGIC)?;
+	out_descriptor.write_all(locked_key.as_bytes())?;
+	out_descriptor.write_all(b"\n")?;
+	out_descriptor.write_all(nonce_prefix.as_slice())?;
+
+	//=========================================================================
+	// Encrypt the chunks. A chunk shorter than CHUNK_SIZE (which may be empty)
+	// is the last one.
+	let cipher: Cipher = Cipher::aes_256_gcm();
+	let mut chunk: Vec<u8> = vec![0; CHUNK_SIZE];
+	let mut counter: u32 = 0;
+	loop {
+		let count: usize = read_full(in_descriptor, chunk.as_mut_slice())?;
+		let flag: u8 = if count < CHUNK_SIZE { CHUNK_FLAG_LAST } else { CHUNK_FLAG_MORE };
+
+		let mut tag: Vec<u8> = vec![0; TAG_LEN];
+		let mut encrypted: Vec<u8> = encrypt_aead(cipher,
+												   key.as_slice(),
+												   Some(chunk_nonce(nonce_prefix.as_slice(), counter, flag).as_slice()),
+												   &[],
+												   &chunk[..count],
+												   tag.as_mut_slice())
+			.map_err(|error| Error::Io(format!("unable to encrypt: {}", error)))?;
+		encrypted.extend_from_slice(tag.as_slice());
+
+		out_descriptor.write_all(&[flag])?;
+		out_descriptor.write_all(&(encrypted.len() as u32).to_be_bytes())?;
+		out_descriptor.write_all(encrypted.as_slice())?;
+
+		if flag == CHUNK_FLAG_LAST {
+			break;
+		}
+		counter = counter.checked_add(1).ok_or_else(|| {
+			Error::Argument(String::from("the input is too large for an envelope"))
+		})?;
+	}
+
+	out_descriptor.flush()?;
+
+	return Ok(locked_key);
+}
+
+/// Decrypt the stream of an envelope container whose magic and time-locked
+/// key have already been consumed. The chunks are decrypted one after another
+/// using the unlocked key, so the memory usage is constant.
+pub fn open(key_plaintext: &str,
+			in_reader: &mut dyn BufRead,
+			out_descriptor: &mut dyn Write)
+	-> Result<(), Error> {
+	//=========================================================================
+	// Decode the key
+	let key: Vec<u8> = base64::decode(key_plaintext).unwrap_or_default();
+	if key.len() != KEY_LEN {
+		return Err(Error::Malformed(String::from("the envelope key is malformed")));
+	}
+
+	//=========================================================================
+	// Read the nonce prefix
+	let mut nonce_prefix: Vec<u8> = vec![0; NONCE_PREFIX_LEN];
+	if read_full(in_reader, nonce_prefix.as_mut_slice())? != NONCE_PREFIX_LEN {
+		return Err(Error::Malformed(String::from("the envelope is truncated")));
+	}
+
+	//=========================================================================
+	// Decrypt the chunks
+	let cipher: Cipher = Cipher::aes_256_gcm();
+	let mut encrypted: Vec<u8> = vec![0; CHUNK_SIZE + TAG_LEN];
+	let mut counter: u32 = 0;
+	loop {
+		let mut frame_header: [u8; 5] = [0; 5];
+		if read_full(in_reader, &mut frame_header)? != frame_header.len() {
+			return Err(Error::Malformed(String::from("the envelope is truncated")));
+		}
+
+		let flag: u8 = frame_header[0];
+		let len: usize = u32::from_be_bytes([frame_header[1],
+											 frame_header[2],
+											 frame_header[3],
+											 frame_header[4]]) as usize;
+		if (flag != CHUNK_FLAG_MORE && flag != CHUNK_FLAG_LAST)
+			|| !(TAG_LEN..=CHUNK_SIZE + TAG_LEN).contains(&len) {
+			return Err(Error::Malformed(String::from("the envelope is malformed")));
+		}
+
+		if read_full(in_reader, &mut encrypted[..len])? != len {
+			return Err(Error::Malformed(String::from("the envelope is truncated")));
+		}
+
+		let decrypted: Vec<u8> = decrypt_aead(cipher,
+											  key.as_slice(),
+											  Some(chunk_nonce(nonce_prefix.as_slice(), counter, flag).as_slice()),
+											  &[],
+											  &encrypted[..len - TAG_LEN],
+											  &encrypted[len - TAG_LEN..len])
+			.map_err(|_error| Error::Malformed(String::from("the envelope has been tampered with")))?;
+		out_descriptor.write_all(decrypted.as_slice())?;
+
+		if flag == CHUNK_FLAG_LAST {
+			break;
+		}
+		counter = counter.checked_add(1).ok_or_else(|| {
+			Error::Malformed(String::from("the envelope is malformed"))
+		})?;
+	}
+
+	out_descriptor.flush()?;
+
+	return Ok(());
+}
@@ -0,0 +1,224 @@
+/**
+ * This file is part of snailcrypt-cli. For more information visit
+ * https://www snailcrypt.com
+ * Copyright (C) 2022-2023  Richard Bäck <richard.baeck@icloud.com>
+ *
+ * This program is free software; you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation; either version 2 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along
+ * with this program; if not, write to the Free Software Foundation, Inc.,
+ * 51 Franklin Street, Fifth Floor, Boston, MA 02110-1301 USA.
+ */
+
+
+use std::{
+	any::Any,
+	cell::Cell,
+	fmt,
+	io,
+	panic,
+};
+
+use serde_json::json;
+
+thread_local! {
+	/// Whether the thread is within a call of catch_client.
+	static CATCHING: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Exit code on success.
+pub const EXIT_SUCCESS: i32 = 0;
+
+/// Exit code of invalid or conflicting command line arguments.
+pub const EXIT_ARGUMENT: i32 = 2;
+
+/// Exit code of failures to read or write a file or a stream.
+pub const EXIT_IO: i32 = 3;
+
+/// Exit code of input which cannot be parsed (e.g. a lock date).
+pub const EXIT_PARSE: i32 = 4;
+
+/// Exit code of failures to reach the snailcrypt server.
+pub const EXIT_NETWORK: i32 = 5;
+
+/// Exit code of requests rejected by the snailcrypt server.
+pub const EXIT_SERVER: i32 = 6;
+
+/// Exit code of a message whose lock date has not been reached yet.
+pub const EXIT_LOCKED: i32 = 7;
+
+/// Exit code of a ciphertext which is malformed or has been tampered with.
+pub const EXIT_MALFORMED: i32 = 8;
+
+/// Exit code of a hook which failed or exceeded its timeout.
+pub const EXIT_HOOK: i32 = 9;
+
+/// Exit code of an operation interrupted by SIGINT.
+pub const EXIT_INTERRUPTED: i32 = 130;
+
+/// Errors of snailcrypt-cli. Every variant maps to its own exit code.
+#[derive(Debug)]
+pub enum Error {
+	Argument(String),
+	Io(String),
+	Parse(String),
+	Network(String),
+	Server(String),
+	Locked(String),
+	Malformed(String),
+	Hook(String),
+	Interrupted(String),
+}
+
+impl Error {
+	/// Get the exit code of the error category.
+	pub fn exit_code(&self) -> i32 {
+		match self {
+			Error::Argument(_)  => EXIT_ARGUMENT,
+			Error::Io(_)        => EXIT_IO,
+			Error::Parse(_)     => EXIT_PARSE,
+			Error::Network(_)   => EXIT_NETWORK,
+			Error::Server(_)    => EXIT_SERVER,
+			Error::Locked(_)    => EXIT_LOCKED,
+			Error::Malformed(_) => EXIT_MALFORMED,
+			Error::Hook(_)      => EXIT_HOOK,
+			Error::Interrupted(_) => EXIT_INTERRUPTED,
+		}
+	}
+
+	/// Get the name of the error category as used in JSON reports.
+	pub fn category(&self) -> &'static str {
+		match self {
+			Error::Argument(_)  => "argument",
+			Error::Io(_)        => "io",
+			Error::Parse(_)     => "parse",
+			Error::Network(_)   => "network",
+			Error::Server(_)    => "server",
+			Error::Locked(_)    => "locked",
+			Error::Malformed(_) => "malformed",
+			Error::Hook(_)      => "hook",
+			Error::Interrupted(_) => "interrupted",
+		}
+	}
+
+	/// Get the category, the exit code and the message of the error as JSON
+	/// object.
+	pub fn to_json(&self) -> serde_json::Value {
+		return json!({
+			"category": self.category(),
+			"code": self.exit_code(),
+			"message": self.message(),
+		});
+	}
+
+	/// Get the message of the error.
+	pub fn message(&self) -> &str {
+		match self {
+			Error::Argument(message)
+			| Error::Io(message)
+			| Error::Parse(message)
+			| Error::Network(message)
+			| Error::Server(message)
+			| Error::Locked(message)
+			| Error::Malformed(message)
+			| Error::Hook(message)
+			| Error::Interrupted(message) => message.as_str(),
+		}
+	}
+}
+
+/// This method will just print the error message.
+impl fmt::Display for Error {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "{}", self.message())
+	}
+}
+
+impl From<io::Error> for Error {
+	fn from(error: io::Error) -> Error {
+		return Error::Io(error.to_string());
+	}
+}
+
+/// Print the error of a result (if any) and return the matching exit code.
+/// If json is true, then the error is printed as JSON object holding its
+/// category, exit code and message.
+pub fn report(result: Result<(), Error>, json: bool) -> i32 {
+	match result {
+		Ok(()) => {
+			return EXIT_SUCCESS;
+		},
+		Err(error) => {
+			if json == true {
+				eprintln!("{}", json!({ "error": error.to_json() }));
+			} else {
+				eprintln!("Error: {}", error);
+			}
+			return error.exit_code();
+		},
+	}
+}
+
+/// Convert the payload of a panic raised by the snailcrypt library into an
+/// error. The library reports failures of curl, of the JSON parser and of the
+/// ciphertext decoding by panicking with the debug output of the failure.
+fn panic_to_error(payload: Box<dyn Any + Send>) -> Error {
+	let message: String = match payload.downcast::<String>() {
+		Ok(message) => *message,
+		Err(payload) => match payload.downcast::<&str>() {
+			Ok(message) => String::from(*message),
+			Err(_payload) => String::from("unknown failure"),
+		},
+	};
+
+	if message.contains("description:") && message.contains("code:") {
+		//=====================================================================
+		// curl errors are printed as Error { description: .., code: .. }
+		let description: &str = message
+			.split("description: \"")
+			.nth(1)
+			.and_then(|rest| rest.split('"').next())
+			.unwrap_or(message.as_str());
+		return Error::Network(format!("unable to reach the snailcrypt server: {}", description));
+	}
+
+	if message.contains("Cipher is invalid")
+		|| message.contains("InvalidByte")
+		|| message.contains("InvalidLength")
+		|| message.contains("InvalidLastSymbol")
+		|| message.contains("FromUtf8Error")
+		|| message.contains("ErrorStack") {
+		return Error::Malformed(String::from("the ciphertext is malformed"));
+	}
+
+	return Error::Server(format!("unexpected response of the snailcrypt server: {}", message));
+}
+
+/// Install the panic hook of the program. It prints the default panic
+/// message, except for the panics caught by catch_client.
+pub fn install_panic_hook() {
+	let default_hook = panic::take_hook();
+	panic::set_hook(Box::new(move |info| {
+		if CATCHING.with(Cell::get) == false {
+			default_hook(info);
+		}
+	}));
+}
+
+/// Call the snailcrypt library and turn its panics into errors. The panic
+/// message is suppressed by the hook of install_panic_hook during the call.
+pub fn catch_client<T, F: FnOnce() -> T>(f: F) -> Result<T, Error> {
+	let catching: bool = CATCHING.with(|flag| flag.replace(true));
+	let result = panic::catch_unwind(panic::AssertUnwindSafe(f));
+	CATCHING.with(|flag| flag.set(catching));
+
+	return result.map_err(panic_to_error);
+}
@@ -0,0 +1,184 @@
+/**
+ * This file is part of snailcrypt-cli. For more information visit
+ * https://www snailcrypt.com
+ * Copyright (C) 2022-2023  Richard Bäck <richard.baeck@icloud.com>
+ *
+ * This program is free software; you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation; either version 2 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along
+ * with this program; if not, write to the Free Software Foundation, Inc.,
+ * 51 Franklin Street, Fifth Floor, Boston, MA 02110-1301 USA.
+ */
+
+
+#[cfg(unix)]
+use std::os::unix::process::CommandExt;
+use std::{
+	io::{
+		self,
+		Read,
+	},
+	path::Path,
+	process::{
+		Child,
+		Command,
+		ExitStatus,
+		Stdio,
+	},
+	thread,
+	time,
+};
+
+use crate::{
+	error::Error,
+	lockdate,
+	wait,
+};
+
+/// Time a hook may run if no timeout has been given.
+const DEFAULT_TIMEOUT: time::Duration = time::Duration::from_secs(60);
+
+/// Interval of the checks whether the hook has finished.
+const POLL_INTERVAL: time::Duration = time::Duration::from_millis(50);
+
+/// Command executed once a message has been unlocked.
+pub struct Hook {
+	command: String,
+	timeout: time::Duration,
+}
+
+/// Metadata of an unlocked message passed to a hook using environment
+/// variables.
+pub struct Unlocked<'a> {
+	pub lockdate: String,
+	pub hint: String,
+	/// Encrypted file, if the message has not been read from stdin.
+	pub file: Option<&'a Path>,
+	/// File the plaintext has been written to, if it has not been written to
+	/// stdout.
+	pub output: Option<&'a Path>,
+}
+
+impl Hook {
+	/// Create a hook running the command using the shell. The timeout is a
+	/// duration like "30s" or "5m" and defaults to 60 seconds.
+	pub fn new(command: String, timeout_str: Option<&str>) -> Result<Hook, Error> {
+		let mut timeout: time::Duration = DEFAULT_TIMEOUT;
+		if let Some(timeout_str) = timeout_str {
+			timeout = lockdate::parse_duration(timeout_str)
+				.and_then(|timeout| timeout.to_std().map_err(|error| error.to_string()))
+				.map_err(|error| {
+				Error::Parse(format!("unable to parse the timeout of the hook \"{}\": {}", timeout_str, error))
+			})?;
+		}
+
+		return Ok(Hook {
+			command,
+			timeout,
+		});
+	}
+
+	/// Spawn the command using the shell of the platform.
+	fn spawn(&self, unlocked: &Unlocked) -> Result<Child, Error> {
+		let mut command: Command;
+		if cfg!(windows) {
+			command = Command::new("cmd");
+			command.arg("/C");
+		} else {
+			command = Command::new("sh");
+			command.arg("-c");
+		}
+
+		//=====================================================================
+		// Start the shell in its own process group, so the processes started
+		// by the command can be killed together with the shell
+		#[cfg(unix)]
+		command.process_group(0);
+
+		let path_str = |path: Option<&Path>| -> String {
+			return path.map(|path| path.to_string_lossy().into_owned()).unwrap_or_default();
+		};
+
+		return command
+			.arg(self.command.as_str())
+			.env("SNAILCRYPT_LOCKDATE", unlocked.lockdate.as_str())
+			.env("SNAILCRYPT_HINT", unlocked.hint.as_str())
+			.env("SNAILCRYPT_FILE", path_str(unlocked.file))
+			.env("SNAILCRYPT_OUTPUT", path_str(unlocked.output))
+			.stdin(Stdio::piped())
+			.stdout(Stdio::from(io::stderr()))
+			.spawn()
+			.map_err(|error| {
+				Error::Hook(format!("unable to run the hook \"{}\": {}", self.command, error))
+			});
+	}
+
+	/// Run the hook with the plaintext on stdin and the metadata of the
+	/// message in environment variables. The output of the hook is written to
+	/// stderr. A hook which fails or exceeds its timeout is reported as error;
+	/// a hook exceeding its timeout is killed.
+	pub fn run(&self,
+			   unlocked: &Unlocked,
+			   mut plaintext: Box<dyn Read + Send>)
+		-> Result<(), Error> {
+		let mut child: Child = self.spawn(unlocked)?;
+
+		//=====================================================================
+		// Feed the plaintext in the background, so a hook which does not read
+		// its input does not block
+		if let Some(mut stdin) = child.stdin.take() {
+			thread::spawn(move || {
+				let _ = io::copy(&mut plaintext, &mut stdin);
+			});
+		}
+
+		//=====================================================================
+		// Wait for the hook to finish
+		let started: time::Instant = time::Instant::now();
+		let status: ExitStatus = loop {
+			if let Some(status) = child.try_wait()? {
+				break status;
+			}
+
+			if started.elapsed() >= self.timeout || wait::interrupted() {
+				kill(&mut child);
+				if wait::interrupted() {
+					return Err(Error::Interrupted(String::from("interrupted while running the hook")));
+				}
+				return Err(Error::Hook(format!("the hook \"{}\" has been killed after {}",
+											   self.command,
+											   lockdate::format_duration(self.timeout.as_secs() as i64))));
+			}
+			thread::sleep(POLL_INTERVAL);
+		};
+
+		if status.success() == false {
+			return Err(Error::Hook(format!("the hook \"{}\" failed with {}", self.command, status)));
+		}
+
+		return Ok(());
+	}
+}
+
+/// Kill a hook together with the processes started by its command. On Unix
+/// the process group of the shell is killed, elsewhere only the shell itself.
+fn kill(child: &mut Child) {
+	#[cfg(unix)]
+	{
+		// The shell leads its own process group, whose id is its process id.
+		// SAFETY: kill only sends a signal and does not touch any memory.
+		unsafe {
+			libc::kill(-(child.id() as libc::pid_t), libc::SIGKILL);
+		}
+	}
+	let _ = child.kill();
+	let _ = child.wait();
+}
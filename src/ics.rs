@@ -0,0 +1,176 @@
+/**
+ * This file is part of snailcrypt-cli. For more information visit
+ * https://www snailcrypt.com
+ * Copyright (C) 2022-2023  Richard Bäck <richard.baeck@icloud.com>
+ *
+ * This program is free software; you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation; either version 2 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along
+ * with this program; if not, write to the Free Software Foundation, Inc.,
+ * 51 Franklin Street, Fifth Floor, Boston, MA 02110-1301 USA.
+ */
+
+
+use std::io::Write;
+
+use chrono::{
+	DateTime,
+	Duration,
+	FixedOffset,
+	Utc,
+};
+
+use crate::{
+	error::Error,
+	lockdate,
+};
+
+/// Product identifier of the calendars written by snailcrypt-cli.
+const PRODID: &str = "-//snailcrypt//snailcrypt-cli//EN";
+
+/// Date time format of iCalendar for dates in UTC.
+const ICS_DATETIME_FORMAT: &str = "%Y%m%dT%H%M%SZ";
+
+/// Maximum length of a content line in octets, excluding the line break.
+const LINE_MAX_LEN: usize = 75;
+
+/// Summary of an event whose message has no hint.
+const DEFAULT_SUMMARY: &str = "snailcrypt message unlocks";
+
+/// An event at the lock date of a message.
+pub struct Event {
+	/// Unique identifier of the event. It is derived from the fingerprint of
+	/// the ciphertext, so the event of a message is updated rather than
+	/// duplicated when it is imported again.
+	pub uid: String,
+	pub lockdate: DateTime<FixedOffset>,
+	/// Hint of the message, used as summary of the event.
+	pub hint: String,
+	pub description: String,
+	/// Timer URL of the message.
+	pub url: Option<String>,
+	/// Ciphertext of the message, attached as text file.
+	pub ciphertext: Option<String>,
+	/// Time before the lock date to remind at.
+	pub alarm: Option<Duration>,
+}
+
+/// Escape a text value.
+fn escape(text: &str) -> String {
+	return text
+		.replace('\\', "\\\\")
+		.replace(';', "\\;")
+		.replace(',', "\\,")
+		.replace("\r\n", "\\n")
+		.replace('\n', "\\n");
+}
+
+/// Format a duration as negative iCalendar duration (e.g. "-P1DT2H").
+fn format_trigger(duration: Duration) -> String {
+	let mut seconds: i64 = duration.num_seconds().abs();
+	let days: i64 = seconds / (60 * 60 * 24);
+	seconds %= 60 * 60 * 24;
+
+	let mut trigger: String = String::from("-P");
+	if days > 0 {
+		trigger.push_str(format!("{}D", days).as_str());
+	}
+	if seconds > 0 || days == 0 {
+		trigger.push('T');
+		let units: [(i64, char); 3] = [(60 * 60, 'H'), (60, 'M'), (1, 'S')];
+		for (unit_seconds, unit) in units {
+			if seconds >= unit_seconds {
+				trigger.push_str(format!("{}{}", seconds / unit_seconds, unit).as_str());
+				seconds %= unit_seconds;
+			}
+		}
+		if trigger.ends_with('T') {
+			trigger.push_str("0S");
+		}
+	}
+
+	return trigger;
+}
+
+/// Write a content line, folded after LINE_MAX_LEN octets without splitting
+/// a UTF-8 character.
+fn write_line(out_descriptor: &mut dyn Write, line: &str) -> Result<(), Error> {
+	let mut remaining: &str = line;
+	let mut max_len: usize = LINE_MAX_LEN;
+	loop {
+		if remaining.len() <= max_len {
+			write!(out_descriptor, "{}\r\n", remaining)?;
+			return Ok(());
+		}
+
+		let mut split: usize = max_len;
+		while !remaining.is_char_boundary(split) {
+			split -= 1;
+		}
+		write!(out_descriptor, "{}\r\n ", &remaining[..split])?;
+		remaining = &remaining[split..];
+		// The leading space of a continuation line counts as well
+		max_len = LINE_MAX_LEN - 1;
+	}
+}
+
+/// Write the events as iCalendar (RFC 5545) calendar.
+pub fn write_calendar(out_descriptor: &mut dyn Write,
+					  events: &[Event])
+	-> Result<(), Error> {
+	let now: String = Utc::now().format(ICS_DATETIME_FORMAT).to_string();
+
+	write_line(out_descriptor, "BEGIN:VCALENDAR")?;
+	write_line(out_descriptor, "VERSION:2.0")?;
+	write_line(out_descriptor, format!("PRODID:{}", PRODID).as_str())?;
+	write_line(out_descriptor, "CALSCALE:GREGORIAN")?;
+	for event in events {
+		let summary: String = escape(if event.hint.is_empty() { DEFAULT_SUMMARY } else { event.hint.as_str() });
+		let start: String = event.lockdate.with_timezone(&Utc).format(ICS_DATETIME_FORMAT).to_string();
+
+		write_line(out_descriptor, "BEGIN:VEVENT")?;
+		write_line(out_descriptor, format!("UID:{}", event.uid).as_str())?;
+		write_line(out_descriptor, format!("DTSTAMP:{}", now).as_str())?;
+		write_line(out_descriptor, format!("DTSTART:{}", start).as_str())?;
+		write_line(out_descriptor, format!("DTEND:{}", start).as_str())?;
+		write_line(out_descriptor, format!("SUMMARY:{}", summary).as_str())?;
+		if !event.description.is_empty() {
+			write_line(out_descriptor, format!("DESCRIPTION:{}", escape(event.description.as_str())).as_str())?;
+		}
+		if let Some(url) = &event.url {
+			write_line(out_descriptor, format!("URL;VALUE=URI:{}", url).as_str())?;
+			write_line(out_descriptor, format!("ATTACH:{}", url).as_str())?;
+		}
+		if let Some(ciphertext) = &event.ciphertext {
+			write_line(out_descriptor,
+					   format!("ATTACH;FMTTYPE=text/plain;ENCODING=BASE64;VALUE=BINARY:{}",
+							   base64::encode(ciphertext.as_bytes())).as_str())?;
+		}
+		if let Some(alarm) = event.alarm {
+			write_line(out_descriptor, "BEGIN:VALARM")?;
+			write_line(out_descriptor, "ACTION:DISPLAY")?;
+			write_line(out_descriptor, format!("DESCRIPTION:{}", summary).as_str())?;
+			write_line(out_descriptor, format!("TRIGGER:{}", format_trigger(alarm)).as_str())?;
+			write_line(out_descriptor, "END:VALARM")?;
+		}
+		write_line(out_descriptor, "END:VEVENT")?;
+	}
+	write_line(out_descriptor, "END:VCALENDAR")?;
+
+	return Ok(());
+}
+
+/// Parse the time before the lock date to remind at (e.g. "15m" or "1d").
+pub fn parse_alarm(alarm_str: &str) -> Result<Duration, Error> {
+	return lockdate::parse_duration(alarm_str).map_err(|error| {
+		Error::Parse(format!("unable to parse the alarm \"{}\": {}", alarm_str, error))
+	});
+}
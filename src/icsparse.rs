@@ -0,0 +1,632 @@
+/**
+ * This file is part of snailcrypt-cli. For more information visit
+ * https://www snailcrypt.com
+ * Copyright (C) 2022-2023  Richard Bäck <richard.baeck@icloud.com>
+ *
+ * This program is free software; you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation; either version 2 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along
+ * with this program; if not, write to the Free Software Foundation, Inc.,
+ * 51 Franklin Street, Fifth Floor, Boston, MA 02110-1301 USA.
+ */
+
+
+use std::{
+	collections::HashSet,
+	fs,
+};
+
+use chrono::{
+	DateTime,
+	Datelike,
+	Duration,
+	FixedOffset,
+	Local,
+	Months,
+	NaiveDate,
+	NaiveDateTime,
+	TimeZone,
+	Utc,
+	Weekday,
+};
+use chrono_tz::Tz;
+
+use crate::{
+	error::Error,
+	lockdate,
+	settings,
+};
+
+/// Separates the path of a calendar from the UID or summary of an event
+/// within a reference like "calendar.ics#Press release".
+const REFERENCE_SEPARATOR: char = '#';
+
+/// File extension of calendars.
+const ICS_EXTENSION: &str = ".ics";
+
+/// Date format of the occurrence of a recurring event.
+const OCCURRENCE_FORMAT: &str = "%Y-%m-%d";
+
+/// Maximum number of occurrences enumerated for a recurring event.
+const OCCURRENCE_MAX_COUNT: usize = 100_000;
+
+/// A content line of a calendar, e.g. "DTSTART;TZID=Europe/Vienna:20240603T080000".
+struct Property {
+	name: String,
+	params: Vec<(String, String)>,
+	value: String,
+}
+
+/// An event of a calendar.
+struct Event {
+	properties: Vec<Property>,
+}
+
+/// Time zone of a date and time within a calendar.
+#[derive(Clone)]
+enum Zone {
+	Utc,
+	Tz(Tz),
+	/// A floating time, which is interpreted in the default time zone of the
+	/// lock dates.
+	Floating,
+}
+
+/// A date and time of a calendar together with its time zone.
+#[derive(Clone)]
+struct Time {
+	naive: NaiveDateTime,
+	zone: Zone,
+}
+
+/// A recurrence rule. Only the parts needed for regular events are
+/// supported, any other part is rejected.
+struct Rule {
+	freq: String,
+	interval: u32,
+	count: Option<usize>,
+	until: Option<NaiveDateTime>,
+	by_day: Vec<Weekday>,
+	/// First day of a week (WKST), which delimits the weeks of a weekly rule
+	/// with BYDAY.
+	week_start: Weekday,
+}
+
+impl Property {
+	fn param(&self, name: &str) -> Option<&str> {
+		return self.params.iter()
+			.find(|(param_name, _value)| param_name == name)
+			.map(|(_name, value)| value.as_str());
+	}
+}
+
+impl Event {
+	fn get(&self, name: &str) -> Option<&Property> {
+		return self.properties.iter().find(|property| property.name == name);
+	}
+
+	fn get_all<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a Property> + 'a {
+		return self.properties.iter().filter(move |property| property.name == name);
+	}
+
+	fn uid(&self) -> Option<&str> {
+		return self.get("UID").map(|property| property.value.as_str());
+	}
+
+	fn summary(&self) -> String {
+		return self.get("SUMMARY")
+			.map(|property| unescape(property.value.as_str()))
+			.unwrap_or_default();
+	}
+
+	/// Get the name of the event used in error messages.
+	fn name(&self) -> String {
+		let summary: String = self.summary();
+		if summary.is_empty() {
+			return String::from(self.uid().unwrap_or_default());
+		}
+
+		return summary;
+	}
+}
+
+impl Time {
+	/// Resolve the time to an instant.
+	fn resolve(&self) -> Result<DateTime<FixedOffset>, Error> {
+		let datetime_format: &str = "%Y-%m-%dT%H:%M:%S%z";
+		let result: Result<DateTime<FixedOffset>, String> = match &self.zone {
+			Zone::Utc => Ok(Utc.from_utc_datetime(&self.naive).fixed_offset()),
+			Zone::Tz(tz) => lockdate::resolve_local(tz, tz.name(), &self.naive, datetime_format),
+			Zone::Floating => lockdate::resolve_default(&self.naive, datetime_format),
+		};
+
+		return result.map_err(|error| Error::Parse(format!("unable to resolve the start of the event: {}", error)));
+	}
+
+	/// Get the date of the time in the time zone of another time.
+	fn date_in(&self, other: &Time) -> NaiveDate {
+		match (&self.zone, &other.zone) {
+			(Zone::Utc, Zone::Tz(tz)) => {
+				return Utc.from_utc_datetime(&self.naive).with_timezone(tz).date_naive();
+			},
+			(Zone::Utc, Zone::Floating) => {
+				return match settings::get().timezone {
+					Some(tz) => Utc.from_utc_datetime(&self.naive).with_timezone(&tz).date_naive(),
+					None => Utc.from_utc_datetime(&self.naive).with_timezone(&Local).date_naive(),
+				};
+			},
+			_ => {
+				return self.naive.date();
+			},
+		}
+	}
+}
+
+/// Whether a lock date refers to an event of a calendar, i.e. it names a file
+/// with the extension ".ics", optionally followed by "#" and the UID or the
+/// summary of the event.
+pub fn is_reference(lockdate_str: &str) -> bool {
+	let path: &str = lockdate_str
+		.split_once(REFERENCE_SEPARATOR)
+		.map(|(path, _selector)| path)
+		.unwrap_or(lockdate_str);
+
+	return path.trim().to_lowercase().ends_with(ICS_EXTENSION);
+}
+
+/// Undo the escaping of a text value.
+fn unescape(text: &str) -> String {
+	let mut result: String = String::new();
+	let mut chars = text.chars();
+	while let Some(c) = chars.next() {
+		if c != '\\' {
+			result.push(c);
+			continue;
+		}
+
+		match chars.next() {
+			Some('n') | Some('N') => result.push('\n'),
+			Some(escaped) => result.push(escaped),
+			None => result.push('\\'),
+		}
+	}
+
+	return result;
+}
+
+/// Split a string at a separator which is not enclosed in double quotes.
+fn split_unquoted(text: &str, separator: char) -> Vec<&str> {
+	let mut parts: Vec<&str> = Vec::new();
+	let mut quoted: bool = false;
+	let mut start: usize = 0;
+	for (index, c) in text.char_indices() {
+		if c == '"' {
+			quoted = !quoted;
+		} else if c == separator && quoted == false {
+			parts.push(&text[start..index]);
+			start = index + c.len_utf8();
+		}
+	}
+	parts.push(&text[start..]);
+
+	return parts;
+}
+
+/// Parse a content line.
+fn parse_property(line: &str) -> Option<Property> {
+	let mut quoted: bool = false;
+	let mut colon: Option<usize> = None;
+	for (index, c) in line.char_indices() {
+		if c == '"' {
+			quoted = !quoted;
+		} else if c == ':' && quoted == false {
+			colon = Some(index);
+			break;
+		}
+	}
+	let colon: usize = colon?;
+
+	let head: Vec<&str> = split_unquoted(&line[..colon], ';');
+	let params: Vec<(String, String)> = head[1..].iter()
+		.filter_map(|param| param.split_once('='))
+		.map(|(name, value)| (name.trim().to_uppercase(), String::from(value.trim().trim_matches('"'))))
+		.collect();
+
+	return Some(Property {
+		name: head[0].trim().to_uppercase(),
+		params,
+		value: String::from(&line[colon + 1..]),
+	});
+}
+
+/// Parse the events of a calendar. Properties of components nested within an
+/// event (e.g. alarms) are ignored.
+fn parse_events(calendar: &str) -> Vec<Event> {
+	let unfolded: String = calendar
+		.replace("\r\n", "\n")
+		.replace("\n ", "")
+		.replace("\n\t", "");
+
+	let mut events: Vec<Event> = Vec::new();
+	let mut stack: Vec<String> = Vec::new();
+	for line in unfolded.lines() {
+		let property: Property = match parse_property(line) {
+			Some(property) => property,
+			None => continue,
+		};
+
+		match property.name.as_str() {
+			"BEGIN" => {
+				let component: String = property.value.trim().to_uppercase();
+				if component == "VEVENT" {
+					events.push(Event { properties: Vec::new() });
+				}
+				stack.push(component);
+			},
+			"END" => {
+				stack.pop();
+			},
+			_ => {
+				if stack.last().is_some_and(|component| component == "VEVENT") {
+					if let Some(event) = events.last_mut() {
+						event.properties.push(property);
+					}
+				}
+			},
+		}
+	}
+
+	return events;
+}
+
+/// Parse a single date or date and time value. A value of a property without
+/// TZID is in UTC if it ends with "Z" and floating otherwise.
+fn parse_time(value: &str, tzid: Option<&str>) -> Result<Time, Error> {
+	let value: &str = value.trim();
+	let error = || -> Error {
+		return Error::Parse(format!("unable to parse the date \"{}\" of the event", value));
+	};
+
+	let naive: NaiveDateTime;
+	let utc: bool = value.ends_with('Z');
+	if value.len() == 8 {
+		naive = NaiveDate::parse_from_str(value, "%Y%m%d")
+			.map_err(|_error| error())?
+			.and_hms_opt(0, 0, 0)
+			.ok_or_else(error)?;
+	} else {
+		naive = NaiveDateTime::parse_from_str(value.trim_end_matches('Z'), "%Y%m%dT%H%M%S")
+			.map_err(|_error| error())?;
+	}
+
+	let zone: Zone;
+	if utc == true {
+		zone = Zone::Utc;
+	} else if let Some(tzid) = tzid {
+		//=====================================================================
+		// Some calendars prefix the name of the time zone with a path (e.g.
+		// "/freeassociation.sourceforge.net/Europe/Vienna")
+		let segments: Vec<&str> = tzid.rsplitn(3, '/').collect();
+		let tz: Tz = tzid.parse::<Tz>()
+			.ok()
+			.or_else(|| match segments.as_slice() {
+				[name, region, _prefix] => format!("{}/{}", region, name).parse::<Tz>().ok(),
+				_ => None,
+			})
+			.ok_or_else(|| Error::Parse(format!("unknown time zone \"{}\" of the event", tzid)))?;
+		zone = Zone::Tz(tz);
+	} else {
+		zone = Zone::Floating;
+	}
+
+	return Ok(Time { naive, zone });
+}
+
+/// Parse all values of a property, which may hold a comma separated list.
+fn parse_times(property: &Property) -> Result<Vec<Time>, Error> {
+	return property.value
+		.split(',')
+		.map(|value| parse_time(value, property.param("TZID")))
+		.collect();
+}
+
+/// Parse the weekday of a BYDAY part.
+fn parse_weekday(day: &str) -> Option<Weekday> {
+	match day.trim() {
+		"MO" => Some(Weekday::Mon),
+		"TU" => Some(Weekday::Tue),
+		"WE" => Some(Weekday::Wed),
+		"TH" => Some(Weekday::Thu),
+		"FR" => Some(Weekday::Fri),
+		"SA" => Some(Weekday::Sat),
+		"SU" => Some(Weekday::Sun),
+		_ => None,
+	}
+}
+
+/// Parse a recurrence rule of an event starting at `start`.
+fn parse_rule(value: &str, start: &Time) -> Result<Rule, Error> {
+	let unsupported = |part: &str| -> Error {
+		return Error::Parse(format!("the recurrence rule part \"{}\" of the event is not supported", part));
+	};
+
+	let mut rule: Rule = Rule {
+		freq: String::new(),
+		interval: 1,
+		count: None,
+		until: None,
+		by_day: Vec::new(),
+		week_start: Weekday::Mon,
+	};
+	let mut names: Vec<String> = Vec::new();
+	for part in value.split(';').filter(|part| !part.trim().is_empty()) {
+		let (name, part_value) = part.split_once('=').ok_or_else(|| unsupported(part))?;
+		let name: String = name.trim().to_uppercase();
+		if names.contains(&name) {
+			return Err(Error::Parse(format!("the recurrence rule part \"{}\" of the event occurs more than once", name)));
+		}
+		names.push(name.clone());
+
+		match name.as_str() {
+			"FREQ" => {
+				rule.freq = part_value.trim().to_uppercase();
+			},
+			"INTERVAL" => {
+				rule.interval = part_value.trim().parse::<u32>()
+					.ok()
+					.filter(|interval| *interval > 0)
+					.ok_or_else(|| unsupported(part))?;
+			},
+			"COUNT" => {
+				rule.count = Some(part_value.trim().parse::<usize>().map_err(|_error| unsupported(part))?);
+			},
+			"UNTIL" => {
+				let until: Time = parse_time(part_value, None)?;
+				let mut naive: NaiveDateTime = until.naive;
+				if part_value.trim().len() == 8 {
+					naive += Duration::days(1) - Duration::seconds(1);
+				}
+				if let (Zone::Utc, Zone::Tz(tz)) = (&until.zone, &start.zone) {
+					naive = Utc.from_utc_datetime(&naive).with_timezone(tz).naive_local();
+				}
+				rule.until = Some(naive);
+			},
+			"BYDAY" => {
+				for day in part_value.split(',') {
+					rule.by_day.push(parse_weekday(day.to_uppercase().as_str()).ok_or_else(|| unsupported(part))?);
+				}
+			},
+			"WKST" => {
+				rule.week_start = parse_weekday(part_value.to_uppercase().as_str()).ok_or_else(|| unsupported(part))?;
+			},
+			_ => {
+				return Err(unsupported(part));
+			},
+		}
+	}
+
+	if !["DAILY", "WEEKLY", "MONTHLY", "YEARLY"].contains(&rule.freq.as_str()) {
+		return Err(Error::Parse(format!("the recurrence frequency \"{}\" of the event is not supported", rule.freq)));
+	}
+	if !rule.by_day.is_empty() && rule.freq != "WEEKLY" {
+		return Err(unsupported("BYDAY"));
+	}
+
+	return Ok(rule);
+}
+
+/// Keep a monthly or yearly occurrence only if it has not been clamped to the
+/// end of a shorter month.
+fn same_day(start: NaiveDateTime, occurrence: NaiveDateTime) -> Vec<NaiveDateTime> {
+	if occurrence.day() != start.day() {
+		return Vec::new();
+	}
+
+	return vec![occurrence];
+}
+
+/// Check whether a recurrence rule yields an occurrence on a date. The
+/// occurrences are enumerated from the start of the event on.
+fn occurs_on(rule: &Rule, start: NaiveDateTime, date: NaiveDate) -> bool {
+	let mut count: usize = 0;
+	let mut period: u32 = 0;
+	let days_from_week_start = |day: &Weekday| -> i64 {
+		return ((day.num_days_from_monday() + 7 - rule.week_start.num_days_from_monday()) % 7) as i64;
+	};
+	let mut by_day: Vec<Weekday> = rule.by_day.clone();
+	by_day.sort_by_key(days_from_week_start);
+	let week_start: NaiveDate = start.date() - Duration::days(days_from_week_start(&start.weekday()));
+
+	while count < OCCURRENCE_MAX_COUNT {
+		//=====================================================================
+		// Get the occurrences of the current period. Invalid dates (e.g. the
+		// 31st of a shorter month) are skipped. Periods beyond the range of
+		// dates cannot contain the date.
+		let step: u32 = match period.checked_mul(rule.interval) {
+			Some(step) => step,
+			None => return false,
+		};
+		let occurrences: Option<Vec<NaiveDateTime>> = match rule.freq.as_str() {
+			"DAILY" => start.checked_add_signed(Duration::days(step as i64)).map(|occurrence| vec![occurrence]),
+			"WEEKLY" if by_day.is_empty() => start.checked_add_signed(Duration::weeks(step as i64)).map(|occurrence| vec![occurrence]),
+			"WEEKLY" => week_start.checked_add_signed(Duration::weeks(step as i64)).map(|week| {
+				by_day.iter()
+					.filter_map(|day| week.checked_add_signed(Duration::days(days_from_week_start(day))))
+					.map(|day| day.and_time(start.time()))
+					.filter(|occurrence| *occurrence >= start)
+					.collect()
+			}),
+			"MONTHLY" => start.checked_add_months(Months::new(step))
+				.map(|occurrence| same_day(start, occurrence)),
+			_ => step.checked_mul(12)
+				.and_then(|months| start.checked_add_months(Months::new(months)))
+				.map(|occurrence| same_day(start, occurrence)),
+		};
+		let occurrences: Vec<NaiveDateTime> = match occurrences {
+			Some(occurrences) => occurrences,
+			None => return false,
+		};
+		period += 1;
+
+		for occurrence in occurrences {
+			if occurrence.date() > date
+				|| rule.count.is_some_and(|max_count| count >= max_count)
+				|| rule.until.is_some_and(|until| occurrence > until) {
+				return false;
+			}
+			if occurrence.date() == date {
+				return true;
+			}
+			count += 1;
+		}
+	}
+
+	return false;
+}
+
+/// Get the start of the occurrence of a recurring event on a date.
+fn occurrence(events: &[&Event],
+			  master: &Event,
+			  start: &Time,
+			  date: NaiveDate)
+	-> Result<Time, Error> {
+	let not_occurring = || -> Error {
+		return Error::Argument(format!("the event \"{}\" does not occur on {}",
+									   master.name(), date.format(OCCURRENCE_FORMAT)));
+	};
+
+	//=========================================================================
+	// An occurrence may have been moved or cancelled by an event of its own
+	for event in events {
+		if let Some(recurrence_id) = event.get("RECURRENCE-ID") {
+			if parse_time(recurrence_id.value.as_str(), recurrence_id.param("TZID"))?.date_in(start) != date {
+				continue;
+			}
+			if event.get("STATUS").is_some_and(|status| status.value.trim().eq_ignore_ascii_case("CANCELLED")) {
+				return Err(Error::Argument(format!("the occurrence of the event \"{}\" on {} has been cancelled",
+												   master.name(), date.format(OCCURRENCE_FORMAT))));
+			}
+			let dtstart: &Property = event.get("DTSTART").ok_or_else(not_occurring)?;
+			return parse_time(dtstart.value.as_str(), dtstart.param("TZID"));
+		}
+	}
+
+	for exdate in master.get_all("EXDATE") {
+		if parse_times(exdate)?.iter().any(|time| time.date_in(start) == date) {
+			return Err(not_occurring());
+		}
+	}
+
+	//=========================================================================
+	// Check the additional dates and the recurrence rule
+	for rdate in master.get_all("RDATE") {
+		for time in parse_times(rdate)? {
+			if time.date_in(start) == date {
+				if rdate.value.trim().len() == 8 {
+					return Ok(Time { naive: date.and_time(start.naive.time()), zone: start.zone.clone() });
+				}
+				return Ok(time);
+			}
+		}
+	}
+
+	let mut occurs: bool = start.naive.date() == date;
+	if let Some(rrule) = master.get("RRULE") {
+		let rule: Rule = parse_rule(rrule.value.as_str(), start)?;
+		occurs = occurs || occurs_on(&rule, start.naive, date);
+	}
+	if occurs == false {
+		return Err(not_occurring());
+	}
+
+	return Ok(Time { naive: date.and_time(start.naive.time()), zone: start.zone.clone() });
+}
+
+/// Resolve the lock date given by a reference to an event of a calendar (e.g.
+/// "calendar.ics#Press release"). The event is selected by its UID or by a
+/// part of its summary; a calendar holding a single event needs no selection.
+/// The start of a recurring event is ambiguous, so its occurrence has to be
+/// selected by date.
+pub fn resolve(reference: &str, occurrence_str: Option<&str>) -> Result<DateTime<FixedOffset>, Error> {
+	let (path, selector) = reference.split_once(REFERENCE_SEPARATOR).unwrap_or((reference, ""));
+	let path: &str = path.trim();
+	let selector: &str = selector.trim();
+
+	let calendar: String = fs::read_to_string(path).map_err(|error| {
+		Error::Io(format!("unable to read the calendar \"{}\": {}", path, error))
+	})?;
+	let events: Vec<Event> = parse_events(calendar.as_str());
+
+	//=========================================================================
+	// Select the event. Events overriding an occurrence of a recurring event
+	// share its UID and are not selected on their own.
+	let masters: Vec<&Event> = events.iter()
+		.filter(|event| event.get("RECURRENCE-ID").is_none())
+		.collect();
+	let mut candidates: Vec<&Event> = masters.iter()
+		.copied()
+		.filter(|event| !selector.is_empty() && event.uid() == Some(selector))
+		.collect();
+	if candidates.is_empty() {
+		let selector_lower: String = selector.to_lowercase();
+		candidates = masters.iter()
+			.copied()
+			.filter(|event| event.summary().to_lowercase().contains(selector_lower.as_str()))
+			.collect();
+	}
+
+	let unique_uids: HashSet<Option<&str>> = candidates.iter().map(|event| event.uid()).collect();
+	if candidates.is_empty() {
+		return Err(Error::Argument(format!("no event of the calendar \"{}\" matches \"{}\"", path, selector)));
+	}
+	if selector.is_empty() && candidates.len() > 1 {
+		return Err(Error::Argument(format!("the calendar \"{}\" contains {} events; select one by its UID or summary (e.g. \"{}#{}\")",
+										   path, candidates.len(),
+										   path, candidates[0].uid().unwrap_or_default())));
+	}
+	if candidates.len() > 1 || unique_uids.len() > 1 {
+		return Err(Error::Argument(format!("{} events of the calendar \"{}\" match \"{}\"; select one by its UID (e.g. \"{}#{}\")",
+										   candidates.len(), path, selector,
+										   path, candidates[0].uid().unwrap_or_default())));
+	}
+	let master: &Event = candidates[0];
+
+	//=========================================================================
+	// Get the start of the event or of the selected occurrence
+	let dtstart: &Property = master.get("DTSTART").ok_or_else(|| {
+		Error::Parse(format!("the event \"{}\" has no start", master.name()))
+	})?;
+	let start: Time = parse_time(dtstart.value.as_str(), dtstart.param("TZID"))?;
+	let recurring: bool = master.get("RRULE").is_some() || master.get("RDATE").is_some();
+
+	let occurrence_date: Option<NaiveDate> = match occurrence_str {
+		Some(occurrence_str) => Some(NaiveDate::parse_from_str(occurrence_str.trim(), OCCURRENCE_FORMAT).map_err(|error| {
+			Error::Parse(format!("unable to parse the occurrence \"{}\": {}", occurrence_str, error))
+		})?),
+		None => None,
+	};
+
+	match occurrence_date {
+		None if recurring == true => {
+			return Err(Error::Argument(format!("the event \"{}\" is recurring; select an occurrence using --occurrence (e.g. \"{}\")",
+											   master.name(), start.naive.format(OCCURRENCE_FORMAT))));
+		},
+		None => {
+			return start.resolve();
+		},
+		Some(date) => {
+			let overrides: Vec<&Event> = events.iter()
+				.filter(|event| event.get("RECURRENCE-ID").is_some() && event.uid() == master.uid())
+				.collect();
+			return occurrence(overrides.as_slice(), master, &start, date)?.resolve();
+		},
+	}
+}
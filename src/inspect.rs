@@ -0,0 +1,174 @@
+/**
+ * This file is part of snailcrypt-cli. For more information visit
+ * https://www snailcrypt.com
+ * Copyright (C) 2022-2023  Richard Bäck <richard.baeck@icloud.com>
+ *
+ * This program is free software; you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation; either version 2 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along
+ * with this program; if not, write to the Free Software Foundation, Inc.,
+ * 51 Franklin Street, Fifth Floor, Boston, MA 02110-1301 USA.
+ */
+
+
+use std::{
+	io::{
+		Read,
+		Write,
+	},
+	rc::Rc,
+};
+
+use chrono::{
+	DateTime,
+	FixedOffset,
+	Local,
+};
+use serde_json::json;
+use snailcrypt::{
+	client,
+	factory,
+};
+
+use crate::{
+	ciphertext,
+	error::Error,
+	ledger,
+	lockdate,
+	settings,
+};
+
+/// Date time format used for the lock dates in the report.
+const REPORT_DATETIME_FORMAT: &str = "%Y-%m-%dT%H:%M:%S%z";
+
+/// Report the format version, the lock date, the hint and the remaining time
+/// of a ciphertext without writing its plaintext.
+pub fn inspect(json: bool,
+			   in_descriptor: Box<dyn Read>,
+			   mut out_descriptor: Box<dyn Write>)
+	-> Result<(), Error> {
+	//=========================================================================
+	// Retrieve ciphertext. The same inputs as for a decryption are accepted.
+	let ciphertext::DecryptInput {
+		is_envelope,
+		ciphertext,
+		server,
+		..
+	} = ciphertext::read(in_descriptor)?;
+
+	//=========================================================================
+    // Setup client object
+	let analyzer_factory: factory::AnalyzerFactory = 
+		factory::AnalyzerFactory::new();
+	let analyzer = analyzer_factory.create();
+     		
+	let config = settings::client_config();
+ 
+	let client_factory: factory::ClientFactory = 
+		factory::ClientFactory::new(Rc::clone(&analyzer),
+									Rc::clone(&config));
+	let client = client_factory.create();
+
+	//=========================================================================
+	// Warn if the message has been encrypted with another server
+	ledger::warn_server(server, ciphertext.as_str());
+
+	//=========================================================================
+	// Analyze the ciphertext locally
+	let version: client::ClientVersion = ciphertext::version(&analyzer, ciphertext.as_str())?;
+	let lockdate: DateTime<FixedOffset> = ciphertext::lockdate(&analyzer,
+															   &client,
+															   ciphertext.as_str())?;
+	let hint: String = ciphertext::hint(&analyzer, ciphertext.as_str())?;
+
+	//=========================================================================
+	// Ask the server whether the ciphertext is already decryptable once its
+	// lock date has passed. The client offers no other way than decrypting it
+	// to find out whether the key has been released, the plaintext is
+	// discarded though. A message which is still locked or rejected by the
+	// server is reported as such.
+	let mut decryptable: bool = false;
+	let mut error_message: String = String::new();
+	if lockdate <= Local::now() {
+		match ciphertext::decrypt(&analyzer, &client, ciphertext.as_str()) {
+			Ok(_success) => {
+				decryptable = true;
+			},
+			Err(failure) => match failure.error {
+				Error::Locked(_) | Error::Server(_) => {
+					error_message = String::from(failure.error.message());
+				},
+				error => {
+					return Err(error);
+				},
+			},
+		}
+	}
+
+	//=========================================================================
+	// Compute the remaining time
+	let remaining_seconds: i64 = lockdate
+		.signed_duration_since(Local::now())
+		.num_seconds();
+	let status: String;
+	if decryptable == true {
+		status = format!("decryptable (unlocked {} ago)",
+						 lockdate::format_duration(remaining_seconds.min(0)));
+	} else if remaining_seconds > 0 {
+		status = format!("locked (unlocks in {})",
+						 lockdate::format_duration(remaining_seconds));
+	} else {
+		status = format!("locked (the lock date has passed, but the key has not been released: {})",
+						 error_message);
+	}
+
+	//=========================================================================
+	// Write the report
+	let lockdate_utc: String = lockdate
+		.with_timezone(&chrono::Utc)
+		.format(REPORT_DATETIME_FORMAT)
+		.to_string();
+	let lockdate_local: String = lockdate
+		.with_timezone(&Local)
+		.format(REPORT_DATETIME_FORMAT)
+		.to_string();
+	let report: String;
+	if json == true {
+		report = json!({
+			"version": version.to_string(),
+			"envelope": is_envelope,
+			"lockdate_utc": lockdate_utc,
+			"lockdate_local": lockdate_local,
+			"hint": hint,
+			"remaining_seconds": remaining_seconds.max(0),
+			"remaining": lockdate::format_duration(remaining_seconds.max(0)),
+			"decryptable": decryptable,
+			"status": status,
+		}).to_string() + "\n";
+	} else {
+		report = format!("Version:           {}{}
+Lock date (UTC):   {}
+Lock date (local): {}
+Hint:              {}
+Status:            {}
+",
+						 version,
+						 if is_envelope { " (envelope)" } else { "" },
+						 lockdate_utc,
+						 lockdate_local,
+						 hint,
+						 status);
+	}
+
+	out_descriptor.write_all(report.as_bytes())?;
+
+	return Ok(());
+}
@@ -0,0 +1,423 @@
+/**
+ * This file is part of snailcrypt-cli. For more information visit
+ * https://www snailcrypt.com
+ * Copyright (C) 2022-2023  Richard Bäck <richard.baeck@icloud.com>
+ *
+ * This program is free software; you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation; either version 2 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along
+ * with this program; if not, write to the Free Software Foundation, Inc.,
+ * 51 Franklin Street, Fifth Floor, Boston, MA 02110-1301 USA.
+ */
+
+
+use std::{
+	env,
+	fs::{
+		self,
+		File,
+		OpenOptions,
+	},
+	io::{
+		BufRead,
+		BufReader,
+		Write,
+	},
+	path::{
+		self,
+		PathBuf,
+	},
+};
+
+use chrono::{
+	DateTime,
+	Duration,
+	FixedOffset,
+	Local,
+};
+use openssl::sha::sha256;
+use serde_json::{
+	json,
+	Value,
+};
+
+use crate::{
+	error::Error,
+	ics,
+	lockdate,
+	settings,
+	table,
+};
+
+/// Date time format used for the dates of the ledger.
+const LEDGER_DATETIME_FORMAT: &str = "%Y-%m-%dT%H:%M:%S%z";
+
+/// Name of the ledger file within the data directory of snailcrypt.
+const LEDGER_FILENAME: &str = "ledger.jsonl";
+
+/// Number of characters of the fingerprint used as short id.
+const SHORT_ID_LEN: usize = 12;
+
+/// A message recorded in the ledger. Only a fingerprint of the ciphertext is
+/// recorded, not the ciphertext itself.
+pub struct Record {
+	pub fingerprint: String,
+	pub created: DateTime<FixedOffset>,
+	pub lockdate: DateTime<FixedOffset>,
+	pub hint: String,
+	pub output: Option<String>,
+	pub label: Option<String>,
+	/// URL of the snailcrypt server the message has been encrypted with. It is
+	/// unknown for records created by older versions.
+	pub server: Option<String>,
+}
+
+/// Options of the ledger operation.
+pub struct LedgerArg {
+	/// One of list, upcoming, ics, show and forget.
+	pub command: String,
+	/// Fingerprint (or a prefix of it) of the message used by show and
+	/// forget.
+	pub id: Option<String>,
+	pub from_str: Option<String>,
+	pub until_str: Option<String>,
+	pub label: Option<String>,
+	/// Time before the lock date to remind at, used by ics.
+	pub ics_alarm_str: Option<String>,
+	pub json: bool,
+}
+
+impl Record {
+	/// Create the record of a message which has just been encrypted.
+	pub fn new(ciphertext: &str,
+			   lockdate: DateTime<FixedOffset>,
+			   hint: &str,
+			   output: Option<&str>,
+			   label: Option<&str>)
+		-> Record {
+		return Record {
+			fingerprint: fingerprint(ciphertext),
+			created: Local::now().fixed_offset(),
+			lockdate,
+			hint: String::from(hint),
+			output: output.map(|output| {
+				path::absolute(output)
+					.map(|output| output.to_string_lossy().into_owned())
+					.unwrap_or_else(|_error| String::from(output))
+			}),
+			label: label.map(String::from),
+			server: Some(settings::get().server.clone()),
+		};
+	}
+
+	/// Get the short id of the record.
+	fn id(&self) -> &str {
+		return &self.fingerprint[..SHORT_ID_LEN.min(self.fingerprint.len())];
+	}
+
+	fn to_json(&self) -> Value {
+		return json!({
+			"fingerprint": self.fingerprint,
+			"created": self.created.format(LEDGER_DATETIME_FORMAT).to_string(),
+			"lockdate": self.lockdate.format(LEDGER_DATETIME_FORMAT).to_string(),
+			"hint": self.hint,
+			"output": self.output,
+			"label": self.label,
+			"server": self.server,
+		});
+	}
+
+	fn from_json(value: &Value) -> Option<Record> {
+		let date = |name: &str| -> Option<DateTime<FixedOffset>> {
+			return DateTime::parse_from_str(value.get(name)?.as_str()?, LEDGER_DATETIME_FORMAT).ok();
+		};
+		let optional_str = |name: &str| -> Option<String> {
+			return value.get(name).and_then(Value::as_str).map(String::from);
+		};
+
+		return Some(Record {
+			fingerprint: optional_str("fingerprint")?,
+			created: date("created")?,
+			lockdate: date("lockdate")?,
+			hint: optional_str("hint").unwrap_or_default(),
+			output: optional_str("output"),
+			label: optional_str("label"),
+			server: optional_str("server"),
+		});
+	}
+}
+
+/// Get the fingerprint of a ciphertext, i.e. its SHA-256 hash in hex.
+pub fn fingerprint(ciphertext: &str) -> String {
+	return sha256(ciphertext.trim().as_bytes())
+		.iter()
+		.map(|byte| format!("{:02x}", byte))
+		.collect();
+}
+
+/// Get the path of the ledger within the data directory of the user, i.e.
+/// $XDG_DATA_HOME/snailcrypt (defaulting to ~/.local/share/snailcrypt) or
+/// %APPDATA%\snailcrypt on Windows.
+pub fn path() -> Result<PathBuf, Error> {
+	let non_empty = |name: &str| -> Option<PathBuf> {
+		return env::var_os(name)
+			.filter(|value| !value.is_empty())
+			.map(PathBuf::from);
+	};
+
+	let data_dir: Option<PathBuf>;
+	if cfg!(windows) {
+		data_dir = non_empty("APPDATA");
+	} else {
+		data_dir = non_empty("XDG_DATA_HOME")
+			.or_else(|| non_empty("HOME").map(|home| home.join(".local").join("share")));
+	}
+
+	return data_dir
+		.map(|data_dir| data_dir.join("snailcrypt").join(LEDGER_FILENAME))
+		.ok_or_else(|| Error::Io(String::from("unable to determine the data directory of the ledger")));
+}
+
+/// Get the server a message has been encrypted with according to the ledger.
+/// None is returned if the message is not recorded or the ledger cannot be
+/// read.
+pub fn server(ciphertext: &str) -> Option<String> {
+	let fingerprint: String = fingerprint(ciphertext);
+
+	return load(&path().ok()?)
+		.ok()?
+		.into_iter()
+		.find(|record| record.fingerprint == fingerprint)
+		.and_then(|record| record.server);
+}
+
+/// Warn if a message has been encrypted with another server than the one in
+/// use. Messages which are not armored are looked up in the ledger.
+pub fn warn_server(server: Option<String>, ciphertext: &str) {
+	if let Some(server) = server.or_else(|| self::server(ciphertext)) {
+		if server != settings::get().server {
+			eprintln!("Warning: the message has been encrypted with the server {}, but the server {} is used.",
+					  server, settings::get().server);
+		}
+	}
+}
+
+/// Append a record to the ledger. The ledger is created if it does not exist.
+pub fn append(record: &Record) -> Result<(), Error> {
+	let path: PathBuf = path()?;
+	let to_error = |error: std::io::Error| -> Error {
+		return Error::Io(format!("unable to record the message in the ledger \"{}\": {}", path.display(), error));
+	};
+
+	if let Some(dir) = path.parent() {
+		fs::create_dir_all(dir).map_err(to_error)?;
+	}
+	let mut file: File = OpenOptions::new()
+		.create(true)
+		.append(true)
+		.open(&path)
+		.map_err(to_error)?;
+	writeln!(file, "{}", record.to_json()).map_err(to_error)?;
+
+	return Ok(());
+}
+
+/// Load all records of the ledger. A missing ledger is empty.
+fn load(path: &PathBuf) -> Result<Vec<Record>, Error> {
+	let file: File = match File::open(path) {
+		Ok(file) => file,
+		Err(error) if error.kind() == std::io::ErrorKind::NotFound => {
+			return Ok(Vec::new());
+		},
+		Err(error) => {
+			return Err(Error::Io(format!("unable to read the ledger \"{}\": {}", path.display(), error)));
+		},
+	};
+
+	let mut records: Vec<Record> = Vec::new();
+	for (index, line) in BufReader::new(file).lines().enumerate() {
+		let line: String = line?;
+		if line.trim().is_empty() {
+			continue;
+		}
+
+		let record: Option<Record> = serde_json::from_str::<Value>(line.as_str())
+			.ok()
+			.and_then(|value| Record::from_json(&value));
+		match record {
+			Some(record) => records.push(record),
+			None => {
+				return Err(Error::Parse(format!("line {} of the ledger \"{}\" is malformed", index + 1, path.display())));
+			},
+		}
+	}
+
+	return Ok(records);
+}
+
+/// Replace the ledger by the records.
+fn store(path: &PathBuf, records: &[Record]) -> Result<(), Error> {
+	let mut partial: PathBuf = path.clone();
+	partial.as_mut_os_string().push(".part");
+
+	let mut content: String = String::new();
+	for record in records {
+		content.push_str(record.to_json().to_string().as_str());
+		content.push('\n');
+	}
+	fs::write(&partial, content)
+		.and_then(|()| fs::rename(&partial, path))
+		.map_err(|error| {
+			Error::Io(format!("unable to write the ledger \"{}\": {}", path.display(), error))
+		})?;
+
+	return Ok(());
+}
+
+/// Find the index of the only record whose fingerprint starts with the id.
+fn find(records: &[Record], id: &str) -> Result<usize, Error> {
+	let id: String = id.trim().to_lowercase();
+	let matches: Vec<usize> = records.iter()
+		.enumerate()
+		.filter(|(_index, record)| !id.is_empty() && record.fingerprint.starts_with(id.as_str()))
+		.map(|(index, _record)| index)
+		.collect();
+
+	match matches.len() {
+		0 => Err(Error::Argument(format!("no message of the ledger matches the id \"{}\"", id))),
+		1 => Ok(matches[0]),
+		_ => Err(Error::Argument(format!("the id \"{}\" matches {} messages of the ledger", id, matches.len()))),
+	}
+}
+
+/// Describe the time remaining until a lock date.
+fn remaining(lockdate: DateTime<FixedOffset>) -> String {
+	let seconds: i64 = lockdate.signed_duration_since(Local::now()).num_seconds();
+	if seconds <= 0 {
+		return String::from("unlocked");
+	}
+
+	return lockdate::format_duration(seconds);
+}
+
+/// List, show or forget the messages recorded in the ledger. list, upcoming
+/// and ics may be filtered by a range of lock dates and by label; upcoming
+/// only lists messages which are still locked; ics exports an iCalendar event
+/// for each message.
+pub fn ledger(args: &LedgerArg,
+			  mut out_descriptor: Box<dyn Write>)
+	-> Result<(), Error> {
+	let path: PathBuf = path()?;
+	let mut records: Vec<Record> = load(&path)?;
+	let now: DateTime<FixedOffset> = Local::now().fixed_offset();
+
+	match args.command.as_str() {
+		"list" | "upcoming" | "ics" => {
+			//=================================================================
+			// Filter the records
+			let from: Option<DateTime<FixedOffset>> = match &args.from_str {
+				Some(from_str) => Some(lockdate::resolve(from_str.as_str(), now, LEDGER_DATETIME_FORMAT)?),
+				None => None,
+			};
+			let until: Option<DateTime<FixedOffset>> = match &args.until_str {
+				Some(until_str) => Some(lockdate::resolve(until_str.as_str(), now, LEDGER_DATETIME_FORMAT)?),
+				None => None,
+			};
+
+			records.retain(|record| {
+				return (args.command != "upcoming" || record.lockdate > now)
+					&& from.is_none_or(|from| record.lockdate >= from)
+					&& until.is_none_or(|until| record.lockdate <= until)
+					&& (args.label.is_none() || record.label == args.label);
+			});
+			records.sort_by_key(|record| record.lockdate);
+
+			//=================================================================
+			// Write the records
+			if args.command == "ics" {
+				let mut alarm: Option<Duration> = None;
+				if let Some(ics_alarm_str) = &args.ics_alarm_str {
+					alarm = Some(ics::parse_alarm(ics_alarm_str.as_str())?);
+				}
+				
+				let events: Vec<ics::Event> = records.iter().map(|record| {
+					let mut description: String = String::from("The message encrypted using snailcrypt unlocks.");
+					if let Some(output) = &record.output {
+						description.push_str(format!("\nEncrypted file: {}", output).as_str());
+					}
+					if let Some(label) = &record.label {
+						description.push_str(format!("\nLabel: {}", label).as_str());
+					}
+					
+					return ics::Event {
+						uid: format!("{}@snailcrypt.com", record.fingerprint),
+						lockdate: record.lockdate,
+						hint: record.hint.clone(),
+						description,
+						url: None,
+						ciphertext: None,
+						alarm,
+					};
+				}).collect();
+				ics::write_calendar(out_descriptor.as_mut(), events.as_slice())?;
+			} else if args.json == true {
+				let values: Vec<Value> = records.iter().map(Record::to_json).collect();
+				writeln!(out_descriptor, "{}", Value::Array(values))?;
+			} else if records.is_empty() {
+				eprintln!("No message of the ledger matches.");
+			} else {
+				let rows: Vec<Vec<String>> = records.iter().map(|record| vec![
+					String::from(record.id()),
+					record.lockdate.format(LEDGER_DATETIME_FORMAT).to_string(),
+					remaining(record.lockdate),
+					record.label.clone().unwrap_or_default(),
+					record.hint.clone(),
+					record.output.clone().unwrap_or_default(),
+				]).collect();
+				table::write(out_descriptor.as_mut(),
+							 &["ID", "LOCK DATE", "REMAINING", "LABEL", "HINT", "OUTPUT"],
+							 rows.as_slice())?;
+			}
+		},
+		"show" => {
+			let record: &Record = &records[find(records.as_slice(), require_id(args)?)?];
+			if args.json == true {
+				writeln!(out_descriptor, "{}", record.to_json())?;
+			} else {
+				writeln!(out_descriptor, "Fingerprint: {}", record.fingerprint)?;
+				writeln!(out_descriptor, "Created:     {}", record.created.format(LEDGER_DATETIME_FORMAT))?;
+				writeln!(out_descriptor, "Lock date:   {}", record.lockdate.format(LEDGER_DATETIME_FORMAT))?;
+				writeln!(out_descriptor, "Remaining:   {}", remaining(record.lockdate))?;
+				writeln!(out_descriptor, "Label:       {}", record.label.clone().unwrap_or_default())?;
+				writeln!(out_descriptor, "Hint:        {}", record.hint)?;
+				writeln!(out_descriptor, "Output:      {}", record.output.clone().unwrap_or_default())?;
+				writeln!(out_descriptor, "Server:      {}", record.server.clone().unwrap_or_default())?;
+			}
+		},
+		"forget" => {
+			let record: Record = records.remove(find(records.as_slice(), require_id(args)?)?);
+			store(&path, records.as_slice())?;
+			eprintln!("Forgot the message {}.", record.id());
+		},
+		command => {
+			return Err(Error::Argument(format!("unknown ledger command \"{}\" (expected list, upcoming, ics, show or forget)", command)));
+		},
+	}
+
+	return Ok(());
+}
+
+/// Get the id given to show or forget.
+fn require_id(args: &LedgerArg) -> Result<&str, Error> {
+	return args.id.as_deref().ok_or_else(|| {
+		Error::Argument(format!("the ledger command \"{}\" requires the id of a message", args.command))
+	});
+}
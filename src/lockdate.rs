@@ -0,0 +1,396 @@
+/**
+ * This file is part of snailcrypt-cli. For more information visit
+ * https://www snailcrypt.com
+ * Copyright (C) 2022-2023  Richard Bäck <richard.baeck@icloud.com>
+ *
+ * This program is free software; you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation; either version 2 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along
+ * with this program; if not, write to the Free Software Foundation, Inc.,
+ * 51 Franklin Street, Fifth Floor, Boston, MA 02110-1301 USA.
+ */
+
+use chrono::{
+	DateTime,
+	Duration,
+	FixedOffset,
+	Local,
+	LocalResult,
+	Months,
+	NaiveDate,
+	NaiveDateTime,
+	TimeZone,
+};
+use chrono_tz::Tz;
+
+use crate::{
+	error::Error,
+	settings,
+};
+
+/// Formats of a date and time without a time zone. A value in one of these
+/// formats is interpreted in the local time zone or in the IANA time zone
+/// following it.
+const NAIVE_DATETIME_FORMATS: [&str; 4] = [
+	"%Y-%m-%dT%H:%M:%S",
+	"%Y-%m-%dT%H:%M",
+	"%Y-%m-%d %H:%M:%S",
+	"%Y-%m-%d %H:%M",
+];
+
+/// Format of a date without a time. A value in this format means midnight.
+const NAIVE_DATE_FORMAT: &str = "%Y-%m-%d";
+
+/// Units available in a relative lock date (e.g. "+2w4h").
+enum RelativeUnit {
+	Seconds,
+	Minutes,
+	Hours,
+	Days,
+	Weeks,
+	Months,
+	Years,
+}
+
+fn str_to_unit(unit: &str) -> Option<RelativeUnit> {
+	match unit.to_lowercase().as_str() {
+		"s" | "sec" | "secs" | "second" | "seconds" => Some(RelativeUnit::Seconds),
+		"m" | "min" | "mins" | "minute" | "minutes" => Some(RelativeUnit::Minutes),
+		"h" | "hr" | "hrs" | "hour" | "hours"       => Some(RelativeUnit::Hours),
+		"d" | "day" | "days"                        => Some(RelativeUnit::Days),
+		"w" | "week" | "weeks"                      => Some(RelativeUnit::Weeks),
+		"mo" | "month" | "months"                   => Some(RelativeUnit::Months),
+		"y" | "yr" | "yrs" | "year" | "years"       => Some(RelativeUnit::Years),
+		_                                           => None,
+	}
+}
+
+/// Check whether a lock date string uses the relative grammar. Relative lock
+/// dates either start with a sign ("+3d", "-1h") or with the word "in"
+/// ("in 90 minutes").
+pub fn is_relative(lockdate_str: &str) -> bool {
+	let trimmed = lockdate_str.trim_start();
+
+	return trimmed.starts_with('+')
+		|| trimmed.starts_with('-')
+		|| trimmed.to_lowercase().starts_with("in ");
+}
+
+/// Apply a single component of a relative lock date to a date.
+fn apply_component(date: DateTime<FixedOffset>,
+				   negative: bool,
+				   amount: u32,
+				   unit: RelativeUnit)
+	-> Option<DateTime<FixedOffset>> {
+	let seconds: i64 = match unit {
+		RelativeUnit::Seconds => amount as i64,
+		RelativeUnit::Minutes => amount as i64 * 60,
+		RelativeUnit::Hours   => amount as i64 * 60 * 60,
+		RelativeUnit::Days    => amount as i64 * 60 * 60 * 24,
+		RelativeUnit::Weeks   => amount as i64 * 60 * 60 * 24 * 7,
+		RelativeUnit::Months | RelativeUnit::Years => {
+			//=================================================================
+			// Months and years are calendar based and cannot be expressed
+			// as a fixed amount of seconds
+			let months: Months = match unit {
+				RelativeUnit::Years => Months::new(amount.checked_mul(12)?),
+				_                   => Months::new(amount),
+			};
+
+			if negative {
+				return date.checked_sub_months(months);
+			} else {
+				return date.checked_add_months(months);
+			}
+		},
+	};
+
+	let duration: Duration = Duration::seconds(seconds);
+	if negative {
+		return date.checked_sub_signed(duration);
+	} else {
+		return date.checked_add_signed(duration);
+	}
+}
+
+/// Resolve a relative lock date against the date `now`.
+///
+/// The grammar consists of one or more components built from an optional sign,
+/// an amount and a unit. Components without a sign reuse the sign of the
+/// previous component. Examples: "+3d", "+2w4h", "+1d -2h", "in 90 minutes".
+pub fn resolve_relative(lockdate_str: &str,
+						now: DateTime<FixedOffset>)
+	-> Result<DateTime<FixedOffset>, String> {
+	let mut remaining: &str = lockdate_str.trim();
+	if remaining.to_lowercase().starts_with("in ") {
+		remaining = &remaining[3..];
+	}
+
+	let chars: Vec<char> = remaining.chars().collect();
+	let mut pos: usize = 0;
+	let mut negative: bool = false;
+	let mut lockdate: DateTime<FixedOffset> = now;
+	let mut component_count: usize = 0;
+
+	loop {
+		//=====================================================================
+		// Skip separators
+		while pos < chars.len() && (chars[pos].is_whitespace() || chars[pos] == ',') {
+			pos += 1;
+		}
+		if pos >= chars.len() {
+			break;
+		}
+
+		//=====================================================================
+		// Parse sign
+		if chars[pos] == '+' || chars[pos] == '-' {
+			negative = chars[pos] == '-';
+			pos += 1;
+			while pos < chars.len() && chars[pos].is_whitespace() {
+				pos += 1;
+			}
+		}
+
+		//=====================================================================
+		// Parse amount
+		let amount_start: usize = pos;
+		while pos < chars.len() && chars[pos].is_ascii_digit() {
+			pos += 1;
+		}
+		if amount_start == pos {
+			return Err(String::from("expected a number"));
+		}
+		let amount_str: String = chars[amount_start..pos].iter().collect();
+		let amount: u32 = amount_str.parse::<u32>().map_err(|_error| {
+			format!("the amount \"{}\" is too large", amount_str)
+		})?;
+
+		//=====================================================================
+		// Parse unit
+		while pos < chars.len() && chars[pos].is_whitespace() {
+			pos += 1;
+		}
+		let unit_start: usize = pos;
+		while pos < chars.len() && chars[pos].is_alphabetic() {
+			pos += 1;
+		}
+		if unit_start == pos {
+			return Err(format!("expected a unit after \"{}\"", amount_str));
+		}
+		let unit_str: String = chars[unit_start..pos].iter().collect();
+		let unit: RelativeUnit = match str_to_unit(unit_str.as_str()) {
+			Some(unit) => unit,
+			None => {
+				return Err(format!("unknown unit \"{}\"", unit_str));
+			},
+		};
+
+		//=====================================================================
+		// Apply the component
+		lockdate = match apply_component(lockdate, negative, amount, unit) {
+			Some(lockdate) => lockdate,
+			None => {
+				return Err(String::from("the resulting date is out of range"));
+			},
+		};
+		component_count += 1;
+	}
+
+	if component_count == 0 {
+		return Err(String::from("no duration given"));
+	}
+
+	return Ok(lockdate);
+}
+
+/// Parse a date and time without a time zone. A date without a time means
+/// midnight.
+fn parse_naive(naive_str: &str) -> Option<NaiveDateTime> {
+	for format in NAIVE_DATETIME_FORMATS {
+		if let Ok(naive) = NaiveDateTime::parse_from_str(naive_str, format) {
+			return Some(naive);
+		}
+	}
+
+	return NaiveDate::parse_from_str(naive_str, NAIVE_DATE_FORMAT)
+		.ok()
+		.and_then(|date| date.and_hms_opt(0, 0, 0));
+}
+
+/// Resolve a local date and time in the time zone `tz`. Local times which are
+/// ambiguous or skipped due to a daylight saving time transition are rejected.
+pub fn resolve_local<T: TimeZone>(tz: &T,
+								  tz_name: &str,
+								  naive: &NaiveDateTime,
+								  datetime_format: &str)
+	-> Result<DateTime<FixedOffset>, String> {
+	match tz.from_local_datetime(naive) {
+		LocalResult::Single(date) => {
+			return Ok(date.fixed_offset());
+		},
+		LocalResult::Ambiguous(earliest, latest) => {
+			return Err(format!("the local time \"{}\" is ambiguous in {} (it may be \"{}\" or \"{}\")",
+							   naive.format("%Y-%m-%d %H:%M:%S"),
+							   tz_name,
+							   earliest.fixed_offset().format(datetime_format),
+							   latest.fixed_offset().format(datetime_format)));
+		},
+		LocalResult::None => {
+			return Err(format!("the local time \"{}\" does not exist in {}",
+							   naive.format("%Y-%m-%d %H:%M:%S"),
+							   tz_name));
+		},
+	}
+}
+
+/// Parse an absolute lock date which is not in the format of the client. The
+/// following formats are supported:
+/// - RFC 3339 (e.g. "2023-01-31T23:00:00Z")
+/// - RFC 2822 (e.g. "Tue, 31 Jan 2023 23:00:00 +0000")
+/// - Unix timestamps (e.g. "1675206000")
+/// - A date and an optional time in the local time zone (e.g. "2024-06-01" or
+///   "2024-06-01 09:00")
+/// - A date and an optional time followed by an IANA time zone name (e.g.
+///   "2024-06-01 09:00 Europe/Vienna")
+///
+/// The result is returned with a fixed offset, so it can be formatted using
+/// the date time format of the client.
+pub fn parse_absolute(lockdate_str: &str,
+					  datetime_format: &str)
+	-> Result<DateTime<FixedOffset>, String> {
+	let trimmed: &str = lockdate_str.trim();
+
+	//=========================================================================
+	// Try the formats carrying their own offset
+	if let Ok(lockdate) = DateTime::parse_from_str(trimmed, datetime_format) {
+		return Ok(lockdate);
+	}
+	if let Ok(lockdate) = DateTime::parse_from_rfc3339(trimmed) {
+		return Ok(lockdate);
+	}
+	if let Ok(lockdate) = DateTime::parse_from_rfc2822(trimmed) {
+		return Ok(lockdate);
+	}
+
+	//=========================================================================
+	// Try a Unix timestamp
+	if !trimmed.is_empty() && trimmed.chars().all(|c| c.is_ascii_digit()) {
+		let timestamp: i64 = trimmed.parse::<i64>().map_err(|_error| {
+			format!("the timestamp \"{}\" is too large", trimmed)
+		})?;
+
+		return match DateTime::from_timestamp(timestamp, 0) {
+			Some(lockdate) => Ok(lockdate.fixed_offset()),
+			None => Err(format!("the timestamp \"{}\" is out of range", trimmed)),
+		};
+	}
+
+	//=========================================================================
+	// Try a local date and time followed by an IANA time zone name
+	if let Some((naive_str, tz_name)) = trimmed.rsplit_once(char::is_whitespace) {
+		if let Ok(tz) = tz_name.parse::<Tz>() {
+			return match parse_naive(naive_str.trim_end()) {
+				Some(naive) => resolve_local(&tz, tz.name(), &naive, datetime_format),
+				None => Err(format!("unable to parse \"{}\" as a date and time", naive_str.trim_end())),
+			};
+		}
+	}
+
+	//=========================================================================
+	// Try a local date and time in the default time zone
+	if let Some(naive) = parse_naive(trimmed) {
+		return resolve_default(&naive, datetime_format);
+	}
+
+	return Err(String::from("unknown date format"));
+}
+
+/// Resolve a local date and time in the time zone given by the setting
+/// timezone, or in the local time zone if there is none.
+pub fn resolve_default(naive: &NaiveDateTime, datetime_format: &str) -> Result<DateTime<FixedOffset>, String> {
+	match settings::get().timezone {
+		Some(tz) => {
+			return resolve_local(&tz, tz.name(), naive, datetime_format);
+		},
+		None => {
+			return resolve_local(&Local, "the local time zone", naive, datetime_format);
+		},
+	}
+}
+
+/// Parse a positive duration using the grammar of relative lock dates without
+/// the leading sign (e.g. "2h", "1d 12h" or "90" for 90 seconds).
+pub fn parse_duration(duration_str: &str) -> Result<Duration, String> {
+	let mut relative_str: String = String::from("+");
+	relative_str.push_str(duration_str.trim());
+	if duration_str.trim().chars().all(|c| c.is_ascii_digit()) {
+		relative_str.push('s');
+	}
+
+	let now: DateTime<FixedOffset> = Local::now().fixed_offset();
+	let duration: Duration = resolve_relative(relative_str.as_str(), now)?.signed_duration_since(now);
+	if duration <= Duration::zero() {
+		return Err(String::from("the duration must be positive"));
+	}
+
+	return Ok(duration);
+}
+
+/// Resolve a lock date given on the command line, which is either relative to
+/// the date `now` or absolute.
+pub fn resolve(lockdate_str: &str,
+			   now: DateTime<FixedOffset>,
+			   datetime_format: &str)
+	-> Result<DateTime<FixedOffset>, Error> {
+	if is_relative(lockdate_str) {
+		return resolve_relative(lockdate_str, now).map_err(|error| {
+			Error::Parse(format!("unable to parse the relative lock date \"{}\": {}", lockdate_str, error))
+		});
+	}
+
+	return parse_absolute(lockdate_str, datetime_format).map_err(|error| {
+		Error::Parse(format!("unable to parse the lock date \"{}\": {}", lockdate_str, error))
+	});
+}
+
+/// Format an amount of seconds as a human readable duration using its two
+/// largest units (e.g. "3 days 4 hours").
+pub fn format_duration(seconds: i64) -> String {
+	let units: [(i64, &str, &str); 4] = [
+		(60 * 60 * 24, "day",    "days"),
+		(60 * 60,      "hour",   "hours"),
+		(60,           "minute", "minutes"),
+		(1,            "second", "seconds"),
+	];
+
+	let mut remaining: i64 = seconds.abs();
+	let mut parts: Vec<String> = Vec::new();
+	for (unit_seconds, singular, plural) in units {
+		let amount: i64 = remaining / unit_seconds;
+		remaining %= unit_seconds;
+
+		if amount > 0 {
+			parts.push(format!("{} {}", amount, if amount == 1 { singular } else { plural }));
+		} else if !parts.is_empty() {
+			break;
+		}
+		if parts.len() == 2 {
+			break;
+		}
+	}
+
+	if parts.is_empty() {
+		return String::from("0 seconds");
+	}
+
+	return parts.join(" ");
+}
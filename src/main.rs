@@ -22,16 +22,21 @@ extern crate getopts;
 extern crate chrono;
 extern crate snailcrypt;
 
-use core::cmp::Ordering;
 use std::{
 	env,
 	fs::File,
 	io::{
+		Cursor,
+		empty,
 		Read,
 		stdin,
 		stdout,
 		Write,
 	},	
+	path::{
+		Path,
+		PathBuf,
+	},
 	process::exit,
     rc::Rc,
 };
@@ -44,14 +49,47 @@ use snailcrypt::{
 use chrono::{
     DateTime,
     FixedOffset,
-    Local,
 };	
-use url::form_urlencoded;
+use qrcode::EcLevel;
+use serde_json::json;
+use url::Url;
 
-const URL_MAX_LEN: usize = 8000;
+use error::Error;
+
+mod armor;
+mod batch;
+mod ciphertext;
+mod directory;
+mod envelope;
+mod error;
+mod hook;
+mod ics;
+mod icsparse;
+mod inspect;
+mod ledger;
+mod lockdate;
+mod manifest;
+mod mock;
+mod payload;
+mod qr;
+mod qrdecode;
+mod scan;
+mod settings;
+mod table;
+mod wait;
+mod watch;
 
 fn print_usage(program: &str, opts: Options) {
-    let brief = format!("Usage: {} [options]", program);
+    let brief = format!("Usage: {} [options]
+       {} --batch [options]
+       {} inspect [options]
+       {} scan [options]
+       {} seal [options]
+       {} watch [options]
+       {} ledger list|upcoming|ics [options]
+       {} ledger show|forget ID [options]
+       {} config show [options]
+       {} serve-mock [options]", program, program, program, program, program, program, program, program, program, program);
     print!("{}", opts.usage(&brief));
 }
 
@@ -63,22 +101,40 @@ If you have not received a copy of the LICENSE you can access it by downloading
 snailcrypt-cli at https://github.com/ritschmaster/snailcrypt-cli", program);
 }
 
-fn encrypt(lockdate_str: &str, 
-		   hint: &str,
-		   generate_url: bool,
-		   force_lockdate: bool,
-		   force_url_length: bool,		   
+/// Input parameter structure for an encryption.
+struct EncryptArg {
+	lockdate_str: String,
+	/// Date of the occurrence of a recurring calendar event given as lock date.
+	occurrence_str: Option<String>,
+	hint: String,
+	binary: bool,
+	envelope: bool,
+	armor: bool,
+	generate_url: bool,
+	url_base_str: Option<String>,
+	qr_format_str: Option<String>,
+	qr_level_str: Option<String>,
+	force_lockdate: bool,
+	force_url_length: bool,
+	json: bool,
+	ledger: bool,
+	label: Option<String>,
+	output_filename: Option<String>,
+	ics_filename: Option<String>,
+	ics_alarm_str: Option<String>,
+}
+
+fn encrypt(args: &EncryptArg,
 		   mut in_descriptor: Box<dyn Read>,
 		   mut out_descriptor: Box<dyn Write>) 
-	-> i32 {
+	-> Result<(), Error> {
 	//=========================================================================
 	// Setup client object
 	let analyzer_factory: factory::AnalyzerFactory = 
 		factory::AnalyzerFactory::new();
     let analyzer = analyzer_factory.create();
     
-	let config_factory: factory::ConfigFactory = factory::ConfigFactory::new();
-    let config = config_factory.create();
+	let config = settings::client_config();
  
 	let client_factory: factory::ClientFactory = 
 		factory::ClientFactory::new(Rc::clone(&analyzer),
@@ -86,85 +142,231 @@ fn encrypt(lockdate_str: &str,
     let client: Rc<dyn client::Client> = client_factory
     									  .create();
        
-    //=========================================================================
-    // Parse lock date
-    let lockdate: DateTime<FixedOffset> = DateTime::parse_from_str(&lockdate_str,
-	                                                               client.get_datetime_format())
-	    .unwrap_or_else(|_error| {
-	    eprintln!("Error: unable to parse the lock date \"{}\"", lockdate_str);
-	    exit(1);
-	});
+	//=========================================================================
+	// Parse lock date
+	let lockdate: DateTime<FixedOffset> = ciphertext::resolve_lockdate(&client,
+																		args.lockdate_str.as_str(),
+																		args.occurrence_str.as_deref(),
+																		args.force_lockdate,
+																		args.json == false)?;
+	
+	//=========================================================================
+	// Validate the output options before contacting the server
+	let mut url_base: Option<Url> = None;
+	if args.generate_url == true {
+		url_base = Some(ciphertext::parse_url_base(args.url_base_str
+												   .as_deref()
+												   .unwrap_or(ciphertext::DEFAULT_URL_BASE))?);
+	}
+	
+	let mut qr_format: Option<qr::QrFormat> = None;
+	if let Some(qr_format_str) = &args.qr_format_str {
+		qr_format = Some(qr::str_to_format(qr_format_str.as_str()).ok_or_else(|| {
+			Error::Argument(format!("unknown QR code format \"{}\" (expected png, svg, terminal or ansi)", qr_format_str))
+		})?);
+	}
+	
+	if args.armor == true && (args.generate_url == true || qr_format.is_some()) {
+		return Err(Error::Argument(String::from("an armored message cannot be embedded in a URL or a QR code")));
+	}
+	
+	if args.json == true && qr_format.is_some() {
+		return Err(Error::Argument(String::from("a QR code cannot be printed as JSON")));
+	}
+	
+	let mut ics_alarm: Option<chrono::Duration> = None;
+	if let Some(ics_alarm_str) = &args.ics_alarm_str {
+		ics_alarm = Some(ics::parse_alarm(ics_alarm_str.as_str())?);
+	}
+	
+	let mut qr_ec_level: EcLevel = EcLevel::M;
+	if let Some(qr_level_str) = &args.qr_level_str {
+		qr_ec_level = qr::str_to_ec_level(qr_level_str.as_str()).ok_or_else(|| {
+			Error::Argument(format!("unknown error correction level \"{}\" (expected L, M, Q or H)", qr_level_str))
+		})?;
+	}
 	
 	//=========================================================================
-	// Exit on lockdate in the past
-	let date_now: DateTime<FixedOffset> = Local::now()
-									.with_timezone(
-										&FixedOffset::east_opt(0)
-										.unwrap_or_else(|| {
-											panic!("Error: unexpected error during conversion of current date time.");									
-										}));
-	if force_lockdate == false && date_now.cmp(&lockdate) != Ordering::Less {
-		eprintln!("Error: lock date \"{}\" is in the past.",		
-				  lockdate.format(client.get_datetime_format()).to_string());
-		return 1;
+	// Encrypt the input locally and time-lock only its key
+	if args.envelope == true {
+		if args.generate_url == true {
+			return Err(Error::Argument(String::from("an envelope container cannot be embedded in a URL")));
+		}
+		if qr_format.is_some() {
+			return Err(Error::Argument(String::from("an envelope container cannot be embedded in a QR code")));
+		}
+		if args.armor == true {
+			return Err(Error::Argument(String::from("an envelope container cannot be armored")));
+		}
+		if args.json == true {
+			return Err(Error::Argument(String::from("an envelope container cannot be printed as JSON")));
+		}
+		
+		let locked_key: String = envelope::seal(&client,
+												lockdate,
+												args.hint.as_str(),
+												in_descriptor.as_mut(),
+												out_descriptor.as_mut())?;
+		export_ics(args, locked_key.as_str(), lockdate, None, false, ics_alarm)?;
+		return record(args, locked_key.as_str(), lockdate);
 	}
 	
 	//=========================================================================
 	// Retrieve plaintext
-	let mut plaintext: String = String::new();
-	in_descriptor.read_to_string(&mut plaintext).unwrap_or_else(|error| {
-		panic!("Error: {:?}", error);
-	});
-	 
+	let mut data: Vec<u8> = Vec::new();
+	in_descriptor.read_to_end(&mut data)?;
+	
  	//=========================================================================
  	// Encrypt plaintext
- 	let ciphertext_result: Result<String, String> =
- 		client.encrypt(&client::ClientEncryptArg { 
- 			plaintext,
- 			lockdate,
- 			hint: String::from(hint) 
- 		});
- 	if ciphertext_result.is_err() {
- 		eprintln!("{}", ciphertext_result.unwrap_err());
- 		return 1;
- 	}
- 	let mut ciphertext = ciphertext_result.unwrap();
+ 	let ciphertext: String = ciphertext::encrypt(&client,
+ 												 data,
+ 												 args.binary,
+ 												 lockdate,
+ 												 args.hint.as_str())?;
+ 	let mut output: String = ciphertext.clone();
  	
+	//=========================================================================
+	// Armor ciphertext
+	let mut armored: Option<String> = None;
+	if args.armor == true {
+		let mut headers: Vec<(&str, String)> = vec![
+			("Version", String::from(ciphertext.split(':').next().unwrap_or_default())),
+			("Lock-Date", lockdate.format(client.get_datetime_format()).to_string()),
+		];
+		if !args.hint.is_empty() {
+			headers.push(("Hint", args.hint.clone()));
+		}
+		if settings::get().server != settings::DEFAULT_SERVER {
+			headers.push(("Server", settings::get().server.clone()));
+		}
+		
+		output = armor::encode(ciphertext.as_str(), headers.as_slice());
+		armored = Some(output.clone());
+	}
+	
 	//=========================================================================
 	// Generate URL
-	if generate_url == true {
-		ciphertext = form_urlencoded::Serializer::new(String::new())
-                            .append_pair("c",
-                                         ciphertext.as_str())
-                            .finish();
-		ciphertext.insert_str(0, "https://webapp.snailcrypt.com/timer.php?");
-		if ciphertext.len() > URL_MAX_LEN
-			&& force_url_length == false {
-			eprintln!("Error: the generated URL is longer than {} characters", URL_MAX_LEN);
-			return 1;
+	let mut url: Option<String> = None;
+	if let Some(url_base) = &url_base {
+		output = ciphertext::to_url(url_base, ciphertext.as_str());
+		if output.len() > settings::get().url_max_length
+			&& args.force_url_length == false {
+			return Err(Error::Argument(format!("the generated URL is longer than {} characters", settings::get().url_max_length)));
 		}
+		url = Some(output.clone());
 	}
 	
 	//=========================================================================
-	// Write ciphertext
-	out_descriptor.write_all(ciphertext.as_bytes())
-				  .unwrap_or_else(|error| {
-		panic!("Error: {:?}", error);
-	});
+	// Write the ciphertext or the URL as QR code
+	if let Some(qr_format) = &qr_format {
+		qr::render(output.as_str(),
+				   qr_format,
+				   qr_ec_level,
+				   out_descriptor.as_mut())?;
+	} else if args.json == true {
+		//=====================================================================
+		// Write the ciphertext together with its metadata as JSON
+		let report: serde_json::Value = json!({
+			"ciphertext": ciphertext,
+			"lockdate": lockdate.format(client.get_datetime_format()).to_string(),
+			"hint": args.hint,
+			"armored": armored,
+			"url": url,
+			"url_length": url.as_ref().map(|url| url.len()),
+			"server": settings::get().server,
+		});
+		writeln!(out_descriptor, "{}", report)?;
+	} else {
+		//=====================================================================
+		// Write ciphertext
+		out_descriptor.write_all(output.as_bytes())?;
+	}
+	
+	export_ics(args, ciphertext.as_str(), lockdate, url, true, ics_alarm)?;
+	return record(args, ciphertext.as_str(), lockdate);
+}
+
+/// Write an iCalendar event at the lock date of an encrypted message, if it
+/// has been requested. The timer URL is attached to the event, otherwise the
+/// ciphertext (unless attach_ciphertext is false, e.g. for the time-locked key
+/// of an envelope).
+fn export_ics(args: &EncryptArg,
+			  ciphertext: &str,
+			  lockdate: DateTime<FixedOffset>,
+			  url: Option<String>,
+			  attach_ciphertext: bool,
+			  alarm: Option<chrono::Duration>)
+	-> Result<(), Error> {
+	let ics_filename: &str = match &args.ics_filename {
+		Some(ics_filename) => ics_filename.as_str(),
+		None => {
+			return Ok(());
+		},
+	};
+	
+	let mut description: String = String::from("The message encrypted using snailcrypt unlocks.");
+	if let Some(output_filename) = &args.output_filename {
+		description.push_str(format!("\nEncrypted file: {}", output_filename).as_str());
+	}
+	
+	let mut ics_descriptor: File = File::create(ics_filename).map_err(|error| {
+		Error::Io(format!("unable to create the iCalendar file \"{}\": {}", ics_filename, error))
+	})?;
+	return ics::write_calendar(&mut ics_descriptor,
+							   &[ics::Event {
+								   uid: format!("{}@snailcrypt.com", ledger::fingerprint(ciphertext)),
+								   lockdate,
+								   hint: args.hint.clone(),
+								   description,
+								   ciphertext: if url.is_none() && attach_ciphertext == true { Some(String::from(ciphertext)) } else { None },
+								   url,
+								   alarm,
+							   }]);
+}
+
+/// Record an encrypted message in the ledger, if the ledger is enabled.
+fn record(args: &EncryptArg,
+		  ciphertext: &str,
+		  lockdate: DateTime<FixedOffset>)
+	-> Result<(), Error> {
+	if args.ledger == false {
+		return Ok(());
+	}
 	
-	return 0;
+	return ledger::append(&ledger::Record::new(ciphertext,
+											   lockdate,
+											   args.hint.as_str(),
+											   args.output_filename.as_deref(),
+											   args.label.as_deref()));
 }
 
-fn decrypt(extract_hint: bool,
-	       mut in_descriptor: Box<dyn Read>,
+/// Input parameter structure for a decryption.
+struct DecryptArg {
+	extract_hint: bool,
+	wait: bool,
+	max_wait_str: Option<String>,
+	json: bool,
+	/// Whether the JSON output is a configured default rather than an option.
+	/// A configured default gives way to an envelope container.
+	json_default: bool,
+	on_unlock: Option<String>,
+	on_unlock_timeout_str: Option<String>,
+	input_filename: Option<String>,
+	output_filename: Option<String>,
+}
+
+fn decrypt(args: &DecryptArg,
+	       in_descriptor: Box<dyn Read>,
 		   mut out_descriptor: Box<dyn Write>)
-	-> i32 {
+	-> Result<(), Error> {
 	//=========================================================================
 	// Retrieve ciphertext
-	let mut ciphertext: String = String::new();
-	in_descriptor.read_to_string(&mut ciphertext).unwrap_or_else(|error| {
-		panic!("Error: {:?}", error);
-	});			
+	let ciphertext::DecryptInput {
+		is_envelope,
+		reader: mut in_reader,
+		ciphertext,
+		server,
+	} = ciphertext::read(in_descriptor)?;
 			
 	//=========================================================================
     // Setup client object
@@ -172,74 +374,198 @@ fn decrypt(extract_hint: bool,
 		factory::AnalyzerFactory::new();
 	let analyzer = analyzer_factory.create();
      		
-	let config_factory: factory::ConfigFactory = factory::ConfigFactory::new();
-    let config = config_factory.create();
+	let config = settings::client_config();
  
 	let client_factory: factory::ClientFactory = 
 		factory::ClientFactory::new(Rc::clone(&analyzer),
 									Rc::clone(&config));
 	let client = client_factory.create();
+	
+	let ciphertext: &str = ciphertext.as_str();
+	
+	let json: bool = args.json == true && (is_envelope == false || args.json_default == false);
+	if is_envelope == true && json == true {
+		return Err(Error::Argument(String::from("an envelope container cannot be printed as JSON")));
+	}
+	
+	let mut on_unlock: Option<hook::Hook> = None;
+	if let Some(command) = &args.on_unlock {
+		if args.wait == false {
+			return Err(Error::Argument(String::from("option 'on-unlock' is only used for --wait and watch")));
+		}
+		if is_envelope == true && args.output_filename.is_none() {
+			return Err(Error::Argument(String::from("the plaintext of an envelope container can only be passed to a hook if it is written to a file")));
+		}
+		
+		on_unlock = Some(hook::Hook::new(command.clone(), args.on_unlock_timeout_str.as_deref())?);
+	}
     
     //=========================================================================
-	// Decrypt ciphertext but do not retrieve anything from the result   
-    let decryption_result = client
-   		.decrypt(ciphertext.as_str());
-
-    let plaintext: String;
-    if extract_hint == true {
+    // Warn if the message has been encrypted with another server
+    ledger::warn_server(server, ciphertext);
+    
+    if args.extract_hint == true {
     	//=====================================================================
-    	// Retrieve hint
-    	if decryption_result.is_err() {
-    		plaintext = decryption_result.unwrap_err().hint;
-    	} else {
-    		plaintext = decryption_result.unwrap().hint;
+    	// Retrieve hint. A message which is still locked or rejected by the
+    	// server still reveals its hint.
+    	let mut status: &str = "decrypted";
+    	let hint: String = match ciphertext::decrypt(&analyzer, &client, ciphertext) {
+    		Ok(success) => success.hint,
+    		Err(failure) => match failure.error {
+    			Error::Locked(_) | Error::Server(_) => {
+    				status = failure.error.category();
+    				failure.hint
+    			},
+    			error => {
+    				return Err(error);
+    			},
+    		},
+    	};
+    	
+    	if json == true {
+    		let lockdate: DateTime<FixedOffset> = ciphertext::lockdate(&analyzer, &client, ciphertext)?;
+    		let report: serde_json::Value = json!({
+    			"hint": hint,
+    			"lockdate": lockdate.format(client.get_datetime_format()).to_string(),
+    			"status": status,
+    		});
+    		writeln!(out_descriptor, "{}", report)?;
+    		return Ok(());
     	}
-    } else {
-		//=====================================================================
-		// Retrieve ciphertext    
-	    plaintext = decryption_result
-			.unwrap_or_else(|error| {
-			    panic!("Error: {:?}", error.error_message);
-			})
-			.plaintext;
+    	
+    	out_descriptor.write_all(hint.as_bytes())?;
+    	return Ok(());
+    }
+    
+    //=========================================================================
+	// Decrypt ciphertext, optionally waiting until it has been unlocked
+	let plaintext: String;
+	let hint: String;
+	if args.wait == true {
+		let deadline: Option<DateTime<FixedOffset>> = match &args.max_wait_str {
+			Some(max_wait_str) => Some(wait::parse_max_wait(max_wait_str.as_str())?),
+			None => None,
+		};
+		
+		wait::install_interrupt_handler()?;
+		let success: client::ClientDecryptResultSuccess = 
+			wait::decrypt(&analyzer, &client, ciphertext, deadline)?;
+		plaintext = success.plaintext;
+		hint = success.hint;
+	} else {
+		let success: client::ClientDecryptResultSuccess = 
+			ciphertext::decrypt(&analyzer, &client, ciphertext)
+			.map_err(|failure| failure.error)?;
+		plaintext = success.plaintext;
+		hint = success.hint;
+	}
+	
+	//=========================================================================
+	// Decrypt the stream of an envelope container directly into the output
+	let hook_input: Box<dyn Read + Send>;
+	if is_envelope == true {
+		envelope::open(plaintext.as_str(),
+					   in_reader.as_mut(),
+					   out_descriptor.as_mut())?;
+		
+		hook_input = match &args.output_filename {
+			Some(output_filename) if on_unlock.is_some() => Box::new(File::open(output_filename)?),
+			_ => Box::new(empty()),
+		};
+	} else {
+		let data: Vec<u8> = payload::decode(plaintext)?;
+		if json == true {
+			//=================================================================
+			// Write the plaintext together with its metadata as JSON. Binary
+			// data is encoded using base64.
+			let lockdate: DateTime<FixedOffset> = ciphertext::lockdate(&analyzer, &client, ciphertext)?;
+			let (text, encoding) = payload::to_text(data.clone());
+			let report: serde_json::Value = json!({
+				"plaintext": text,
+				"encoding": encoding,
+				"hint": hint,
+				"lockdate": lockdate.format(client.get_datetime_format()).to_string(),
+				"status": "decrypted",
+			});
+			writeln!(out_descriptor, "{}", report)?;
+		} else {
+			//=================================================================
+			// Write plaintext
+			out_descriptor.write_all(data.as_slice())?;
+		}
+		
+		hook_input = Box::new(Cursor::new(data));
 	}
 	
 	//=========================================================================
-	// Write plaintext
-	out_descriptor.write_all(plaintext.as_bytes()).unwrap_or_else(|error| {
-		panic!("Error: {:?}", error);
-	});
+	// Pass the plaintext to the hook
+	if let Some(on_unlock) = on_unlock {
+		out_descriptor.flush()?;
+		let lockdate: DateTime<FixedOffset> = ciphertext::lockdate(&analyzer, &client, ciphertext)?;
+		on_unlock.run(&hook::Unlocked {
+						  lockdate: lockdate.format(client.get_datetime_format()).to_string(),
+						  hint,
+						  file: args.input_filename.as_deref().map(Path::new),
+						  output: args.output_filename.as_deref().map(Path::new),
+					  },
+					  hook_input)?;
+	}
 	
-	return 0;
+	return Ok(());
 }
 
 fn main() {
+	error::install_panic_hook();
+
     //=========================================================================
     // Setup argument parsing and the available options
 	let args: Vec<String> = env::args().collect();
     let program = args[0].clone();
 
     let mut opts = Options::new();
-    opts.optflag("d", "decrypt",       "Decrypts a string");
-    opts.optopt( "e", "encrypt",       "Encrypts a string using the given lock date (e.g. \"2023-01-31T23:00:00+0000\"", "LOCK_DATE");
+    opts.optflag("d", "decrypt",       "Decrypts a string. The input may also be a timer URL or a PNG or JPEG image of a QR code. If the input is a directory or a glob pattern (e.g. \"sealed/*.snail\"), then every file whose lock date has passed is decrypted into the output directory (defaults to the directory of the file) and the files which are still locked are listed.");
+    opts.optopt( "e", "encrypt",       "Encrypts a string using the given lock date (e.g. \"2023-01-31T23:00:00+0000\"). Also accepted are RFC 3339, RFC 2822, Unix timestamps and a date with an optional time followed by an optional IANA time zone (e.g. \"2024-06-01 09:00 Europe/Vienna\"). A relative lock date like \"+3d\", \"+2w4h\" or \"in 90 minutes\" is resolved against the current time. Available units: s, m, h, d, w, mo, y. The start of an event of an iCalendar file may be used as well, selected by its UID or a part of its summary (e.g. \"calendar.ics#Press release\").", "LOCK_DATE");
+    opts.optopt( "",  "occurrence",    "Use the occurrence of a recurring calendar event on the date (e.g. \"2024-06-03\") as lock date. This option is only used for -e.", "DATE");
     opts.optopt( "t", "hint",          "Use string as hint for the encrypted string. This option is only used for -e.", "HINT");
+    opts.optflag("b", "binary",        "Encrypt the input as binary data, so arbitrary files survive the round trip. Input which is not valid UTF-8 is always encrypted as binary data. This option is only used for -e. Decryption detects binary data automatically.");
+    opts.optflag("",  "envelope",      "Encrypt the input locally using a fresh key and time-lock only this key. This is suited for large inputs. This option is only used for -e. Decryption detects an envelope automatically.");
+    opts.optflag("a", "armor",         "Wrap the ciphertext in BEGIN and END lines with a checksum, so it survives being pasted into emails or chats. This option is only used for -e. Decryption detects an armored message automatically, also within surrounding text.");
     opts.optflag( "T", "extract-hint", "Extracts hint from encrypted string. This option is only used for -d.");
+    opts.optflag("",  "wait",          "Wait until the lock date has passed and the server released the key, then decrypt. A countdown is shown if stderr is a terminal. This option is only used for -d.");
+    opts.optopt( "",  "max-wait",      "Give up waiting after the given duration (e.g. \"2h\" or \"1d 12h\"). This option is only used for --wait.", "DURATION");
+    opts.optopt( "",  "on-unlock",     "Run the command using the shell once the message has been unlocked. The plaintext is passed on stdin, the lock date, the hint, the encrypted file and the output file in the environment variables SNAILCRYPT_LOCKDATE, SNAILCRYPT_HINT, SNAILCRYPT_FILE and SNAILCRYPT_OUTPUT. This option is only used for --wait and watch.", "COMMAND");
+    opts.optopt( "",  "on-unlock-timeout", "Kill the command of --on-unlock after the given duration (defaults to 60s).", "DURATION");
     opts.optopt( "i", "input",         "Use input file instead of stdin", "INPUT_FILE");    
     opts.optopt( "o", "stdout",        "Use input file instead of stdout", "OUTPUT_FILE");    
     opts.optflag("f", "force",         "Use the force and ignore any warnings. Those include:
 - Ignore using a lock date in the past (option -e). This might still fail if the server rejects the request.
 - Ignore the URL limit on URL generation (option -u)");
     opts.optflag("u", "url",           "Generate a URL pointing to a timer containing the message on https://webapp.snailcrypt.com. This is an option for -e.");
+    opts.optopt( "",  "url-base",      "Use the timer of another copy of the webapp for -u (e.g. \"https://intranet.example/snailcrypt/timer.php\"). Query parameters of the URL are preserved. Defaults to the setting url.base (environment variable SNAILCRYPT_URL_BASE).", "URL");
+    opts.optopt( "",  "qr",            "Write the ciphertext (or the URL of -u) as QR code instead. Available formats: png, svg, terminal (Unicode blocks) and ansi (ANSI colors). This option is only used for -e.", "FORMAT");
+    opts.optopt( "",  "qr-level",      "Use the error correction level L, M (default), Q or H for --qr.", "LEVEL");
+    opts.optflag("",  "batch",         "Process newline delimited JSON requests from the input and write one JSON response per request (e.g. {\"op\":\"encrypt\",\"plaintext\":\"..\",\"lockdate\":\"+1d\",\"hint\":\"..\"} or {\"op\":\"decrypt\",\"ciphertext\":\"..\"}). A failing request does not abort the batch.");
+    opts.optflag("",  "continue-on-error", "Encrypt the remaining entries of a manifest after an entry failed. This option is only used for seal.");
+    opts.optflag("",  "ledger",        "Record the encrypted message in the ledger of the user. Only a fingerprint of the ciphertext is recorded. The ledger is also enabled by the setting ledger.enabled (environment variable SNAILCRYPT_LEDGER). This option is only used for -e and seal.");
+    opts.optopt( "",  "label",         "Record the message in the ledger using the label. For the ledger operation only messages with the label are listed.", "LABEL");
+    opts.optopt( "",  "from",          "List only messages of the ledger unlocking at or after the date (e.g. \"2024-06-01\" or \"+1w\"). This option is only used for ledger.", "DATE");
+    opts.optopt( "",  "until",         "List only messages of the ledger unlocking at or before the date. This option is only used for ledger.", "DATE");
+    opts.optopt( "",  "ics",           "Write an iCalendar event at the lock date to the file. The hint is used as summary and the timer URL of -u (or the ciphertext) is attached. This option is only used for -e.", "ICS_FILE");
+    opts.optopt( "",  "ics-alarm",     "Add an alarm to the iCalendar events of --ics and of ledger ics, which reminds the given duration (e.g. \"15m\" or \"1d\") before the lock date.", "DURATION");
+    opts.optopt( "",  "server",        "Use the snailcrypt server whose API is available at the URL (e.g. \"https://snailcrypt.intranet.example/api\"). Defaults to the setting server (environment variable SNAILCRYPT_SERVER). Armored messages and the ledger record the server, so -d warns about a message encrypted with another server.", "URL");
+    opts.optopt( "",  "listen",        "Listen on the address (defaults to 127.0.0.1:8080). This option is only used for serve-mock.", "ADDRESS");
+    opts.optopt( "",  "clock",         "Freeze the clock of the mock server at the date. This option is only used for serve-mock.", "DATE");
+    opts.optflag("",  "json",          "Print the result of -e, -d or inspect as JSON. Errors are printed as JSON to stderr as well.");
     opts.optflag("h", "help",          "Print this help");
     opts.optflag("V", "version",       "Print version");
     
     let matches = match opts.parse(&args[1..]) {
         Ok(m) => { m },
         Err(f) => { 
-			eprintln!("{}", f.to_string());
+			eprintln!("{}", f);
 			println!("");
 			print_usage(&program, opts);
-			return;
+			exit(error::EXIT_ARGUMENT);
 		},
     };	
     
@@ -257,6 +583,86 @@ fn main() {
         return;
     }    
     
+    //=========================================================================
+    // Load the settings. Options take precedence over the environment and the
+    // configuration files.
+    let flag_value = |name: &str| -> Option<String> {
+    	return matches.opt_present(name).then(|| String::from("true"));
+    };
+    let settings: settings::Settings = settings::load(&[
+    		settings::Flag { name: "output.armor", value: flag_value("a"), option: "--armor" },
+    		settings::Flag { name: "output.json", value: flag_value("json"), option: "--json" },
+    		settings::Flag { name: "url.generate", value: flag_value("u"), option: "--url" },
+    		settings::Flag { name: "url.base", value: matches.opt_str("url-base"), option: "--url-base" },
+    		settings::Flag { name: "server", value: matches.opt_str("server"), option: "--server" },
+    		settings::Flag { name: "ledger.enabled", value: flag_value("ledger"), option: "--ledger" },
+    	])
+    	.unwrap_or_else(|error| {
+    		exit(error::report(Err(error), matches.opt_present("json")));
+    	});
+    
+    //=========================================================================
+    // Set JSON output flag. A configured default gives way to options which
+    // cannot be printed as JSON.
+    let mut json: bool = settings.json;
+    if settings.source("output.json") != settings::Source::Option("--json")
+    	&& (matches.opt_present("qr") || matches.opt_present("envelope")) {
+    	json = false;
+    }
+    settings::init(settings);
+    let settings: &settings::Settings = settings::get();
+    
+    //=========================================================================
+    // Watch a directory given by the input. The output names a directory.
+    if matches.free.first().map(String::as_str) == Some("watch") {
+    	if matches.opt_present("e") || matches.opt_present("d") {
+			eprintln!("Option 'e' and option 'd' cannot be combined with an operation.");
+			println!("");
+			print_usage(&program, opts);
+			exit(error::EXIT_ARGUMENT);
+    	}
+    	
+    	let input_dir: PathBuf = match matches.opt_str("i") {
+    		Some(input_dir) => PathBuf::from(input_dir),
+    		None => {
+    			exit(error::report(Err(Error::Argument(String::from("the operation 'watch' requires a directory given by option 'i'"))),
+    							   json));
+    		},
+    	};
+    	
+    	exit(error::report(watch::watch(&watch::WatchArg {
+    										input_dir,
+    										output_dir: matches.opt_str("o").map(PathBuf::from),
+    										on_unlock: matches.opt_str("on-unlock"),
+    										on_unlock_timeout_str: matches.opt_str("on-unlock-timeout"),
+    									}),
+    					   json));
+    }
+    
+    //=========================================================================
+    // Decrypt several files given by a directory or a glob pattern. The output
+    // names a directory then.
+    if matches.opt_present("d")
+    	&& !matches.opt_present("e")
+    	&& !matches.opt_present("batch")
+    	&& matches.free.is_empty() {
+    	if let Some(input) = matches.opt_str("i")
+    		.filter(|input| directory::is_collection(input.as_str())) {
+    		if matches.opt_present("T") || matches.opt_present("wait") {
+    			exit(error::report(Err(Error::Argument(String::from("option 'T' and option 'wait' cannot be used for several files"))),
+    							   json));
+    		}
+    		
+    		exit(error::report(directory::decrypt_all(&directory::DirectoryArg {
+    													   input,
+    													   output_dir: matches.opt_str("o").map(PathBuf::from),
+    													   json,
+    												   },
+    												   Box::new(stdout())),
+    						   json));
+    	}
+    }
+    
     //=========================================================================
     // Setup where to get the plaintext or ciphertext from
     let mut in_descriptor: Box<dyn Read> = Box::new(stdin());
@@ -269,16 +675,19 @@ fn main() {
 			},		
 		};
 	
-		in_descriptor = Box::new(File::open(input_filename)
+		in_descriptor = Box::new(File::open(&input_filename)
 								 .unwrap_or_else(|error| {
-			panic!("Error: {:?}", error);
+			exit(error::report(Err(Error::Io(format!("unable to open the input file \"{}\": {}", input_filename, error))),
+							   json));
 		}))
 	}
 	
 	//=========================================================================
-	// Setup where to write the ciphertext or plaintext to
+	// Setup where to write the ciphertext or plaintext to. The output file
+	// of scan is created by scan itself after having read the document,
+	// which may be the same file.
 	let mut out_descriptor: Box<dyn Write> = Box::new(stdout());
-	if matches.opt_present("o") {
+	if matches.opt_present("o") && matches.free.first().map(String::as_str) != Some("scan") {
 		let output_filename = match matches.opt_str("o") {
 			Some(output_filename) => output_filename,
 			None => {
@@ -287,18 +696,16 @@ fn main() {
 			},		
 		};
 	
-		out_descriptor = Box::new(File::create(output_filename)
+		out_descriptor = Box::new(File::create(&output_filename)
 								 .unwrap_or_else(|error| {
-			panic!("Error: {:?}", error);
+			exit(error::report(Err(Error::Io(format!("unable to create the output file \"{}\": {}", output_filename, error))),
+							   json));
 		}))	
 	}
 	
 	//=========================================================================
 	// Set hint
-	let hint = match matches.opt_str("t") {
-		Some(hint) => hint,
-		None => String::from(""),
-	};
+	let hint = matches.opt_str("t").unwrap_or_default();
 	
 	//=========================================================================
 	// Set extract hint flag
@@ -308,12 +715,44 @@ fn main() {
 	}
 	
 	//=========================================================================
-	// Set URL generation flag
-	let mut generate_url: bool = false;
-	if matches.opt_present("u") {
-		generate_url = true;
+	// Set binary flag
+	let mut binary: bool = false;
+	if matches.opt_present("b") {
+		binary = true;
+	}
+	
+	//=========================================================================
+	// Set envelope flag
+	let mut envelope: bool = false;
+	if matches.opt_present("envelope") {
+		envelope = true;
+	}
+	
+	//=========================================================================
+	// Set URL generation flag. A configured default gives way to an envelope
+	// container, which cannot be embedded in a URL.
+	let mut generate_url: bool = settings.generate_url;
+	if settings.source("url.generate") != settings::Source::Option("--url") && envelope {
+		generate_url = false;
+	}
+	
+	//=========================================================================
+	// Set armor flag. A configured default gives way to the options it cannot
+	// be combined with.
+	let mut armor: bool = settings.armor;
+	if settings.source("output.armor") != settings::Source::Option("--armor")
+		&& (generate_url || matches.opt_present("qr") || envelope) {
+		armor = false;
 	}
 	
+	//=========================================================================
+	// Set the base URL of the timer
+	let url_base_str: Option<String> = settings.url_base.clone();
+	
+	//=========================================================================
+	// Set ledger flag
+	let ledger: bool = settings.ledger;
+	
 	//=========================================================================
 	// Set force flag
 	let mut force_lockdate: bool = false;
@@ -323,13 +762,109 @@ fn main() {
 		force_url_length = true;
 	}
 	
-	if matches.opt_present("e") && matches.opt_present("d") {
+	if matches.opt_present("batch") {
+		//=====================================================================
+		// Process a batch of JSON requests
+		if matches.opt_present("e")
+			|| matches.opt_present("d")
+			|| !matches.free.is_empty() {
+			eprintln!("Option 'batch' cannot be combined with option 'e', option 'd' or an operation.");
+			println!("");
+			print_usage(&program, opts);
+			exit(error::EXIT_ARGUMENT);
+		}
+		
+		exit(error::report(batch::batch(in_descriptor,
+										out_descriptor),
+						   true));
+	} else if !matches.free.is_empty() {
+		//=====================================================================
+		// Perform an operation given by name
+		if matches.opt_present("e") || matches.opt_present("d") {
+			eprintln!("Option 'e' and option 'd' cannot be combined with an operation.");
+			println!("");
+			print_usage(&program, opts);
+			exit(error::EXIT_ARGUMENT);
+		}
+		
+		match matches.free[0].as_str() {
+			"inspect" => {
+				exit(error::report(inspect::inspect(json,
+													in_descriptor,
+													out_descriptor),
+								   json));
+			},
+			"seal" => {
+				//=============================================================
+				// Paths within the manifest are relative to its directory
+				let base_dir: PathBuf = matches.opt_str("i")
+					.and_then(|input_filename| Path::new(&input_filename)
+							  .parent()
+							  .map(Path::to_path_buf))
+					.unwrap_or_default();
+				
+				exit(error::report(manifest::seal(&manifest::ManifestArg {
+													  base_dir,
+													  url_base_str,
+													  continue_on_error: matches.opt_present("continue-on-error"),
+													  ledger,
+												  },
+												  in_descriptor,
+												  out_descriptor),
+								   json));
+			},
+			"ledger" => {
+				exit(error::report(ledger::ledger(&ledger::LedgerArg {
+													  command: matches.free.get(1).cloned().unwrap_or_else(|| String::from("list")),
+													  id: matches.free.get(2).cloned(),
+													  from_str: matches.opt_str("from"),
+													  until_str: matches.opt_str("until"),
+													  label: matches.opt_str("label"),
+													  ics_alarm_str: matches.opt_str("ics-alarm"),
+													  json,
+												  },
+												  out_descriptor),
+								   json));
+			},
+			"config" => {
+				exit(error::report(settings::config(&settings::ConfigArg {
+														command: matches.free.get(1).cloned().unwrap_or_else(|| String::from("show")),
+														json,
+													},
+													out_descriptor),
+								   json));
+			},
+			"serve-mock" => {
+				exit(error::report(mock::serve(&mock::MockArg {
+												   listen: matches.opt_str("listen").unwrap_or_else(|| String::from("127.0.0.1:8080")),
+												   clock_str: matches.opt_str("clock"),
+											   },
+											   in_descriptor,
+											   out_descriptor),
+								   json));
+			},
+			"scan" => {
+				exit(error::report(scan::scan(&scan::ScanArg {
+												  output_filename: matches.opt_str("o"),
+											  },
+											  in_descriptor,
+											  out_descriptor),
+								   json));
+			},
+			operation => {
+				eprintln!("Unknown operation '{}'.", operation);
+				println!("");
+				print_usage(&program, opts);
+				exit(error::EXIT_ARGUMENT);
+			},
+		}
+	} else if matches.opt_present("e") && matches.opt_present("d") {
 		//=====================================================================
 		// Error: both options are present
 		eprintln!("Option 'e' and option 'd' are present. Select one.");
 		println!("");
 		print_usage(&program, opts);
-		exit(1);	
+		exit(error::EXIT_ARGUMENT);
 	} else if matches.opt_present("e") {
 		//=====================================================================
 		// Perform encryption
@@ -341,25 +876,52 @@ fn main() {
 			},		
 		};    
 		
-		exit(encrypt(&lockdate_str,
-						  hint.as_str(),
-						  generate_url, 
-					      force_lockdate,				
-					      force_url_length,	  
-					      in_descriptor, 
-					      out_descriptor));
+		exit(error::report(encrypt(&EncryptArg {
+										lockdate_str,
+										occurrence_str: matches.opt_str("occurrence"),
+										hint,
+										binary,
+										envelope,
+										armor,
+										generate_url,
+										url_base_str,
+										qr_format_str: matches.opt_str("qr"),
+										qr_level_str: matches.opt_str("qr-level"),
+										force_lockdate,
+										force_url_length,
+										json,
+										ledger,
+										label: matches.opt_str("label"),
+										output_filename: matches.opt_str("o"),
+										ics_filename: matches.opt_str("ics"),
+										ics_alarm_str: matches.opt_str("ics-alarm"),
+									},
+									in_descriptor,
+									out_descriptor),
+								   json));
 	} else if matches.opt_present("d") {
 		//=====================================================================
 		// Perform decryption
-		exit(decrypt(extract_hint,
-		   in_descriptor, 
-						  out_descriptor));
+		exit(error::report(decrypt(&DecryptArg {
+										extract_hint,
+										wait: matches.opt_present("wait"),
+										max_wait_str: matches.opt_str("max-wait"),
+										json,
+										json_default: settings.source("output.json") != settings::Source::Option("--json"),
+										on_unlock: matches.opt_str("on-unlock"),
+										on_unlock_timeout_str: matches.opt_str("on-unlock-timeout"),
+										input_filename: matches.opt_str("i"),
+										output_filename: matches.opt_str("o"),
+									},
+									in_descriptor,
+									out_descriptor),
+								   json));
 	} else {
 		//=====================================================================
 		// Error: neither option is present
 		eprintln!("Neither option 'e' nor option 'd' is present. Select one.");
 		println!("");
 		print_usage(&program, opts);
-        exit(1);		
+        exit(error::EXIT_ARGUMENT);
 	}
 }
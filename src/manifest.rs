@@ -0,0 +1,292 @@
+/**
+ * This file is part of snailcrypt-cli. For more information visit
+ * https://www snailcrypt.com
+ * Copyright (C) 2022-2023  Richard Bäck <richard.baeck@icloud.com>
+ *
+ * This program is free software; you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation; either version 2 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along
+ * with this program; if not, write to the Free Software Foundation, Inc.,
+ * 51 Franklin Street, Fifth Floor, Boston, MA 02110-1301 USA.
+ */
+
+
+use std::{
+	fs::{
+		self,
+		File,
+	},
+	io::{
+		Read,
+		Write,
+	},
+	path::{
+		Path,
+		PathBuf,
+	},
+};
+
+use serde_json::{
+	json,
+	Map,
+	Value,
+};
+
+use crate::{
+	encrypt,
+	error::Error,
+	EncryptArg,
+};
+
+/// Suffix of the output file of an entry which does not name one.
+const OUTPUT_SUFFIX: &str = ".snail";
+
+/// Suffix of the temporary file an entry is written to. It is renamed to the
+/// output file once the encryption succeeded, so a failing entry does not
+/// leave a truncated output file behind.
+const PARTIAL_SUFFIX: &str = ".part";
+
+/// Options shared by all entries of a manifest.
+pub struct ManifestArg {
+	/// Directory the paths of the entries are relative to.
+	pub base_dir: PathBuf,
+	pub url_base_str: Option<String>,
+	pub continue_on_error: bool,
+	/// Whether the encrypted files are recorded in the ledger.
+	pub ledger: bool,
+}
+
+/// A single file to be encrypted.
+struct Entry {
+	input: PathBuf,
+	output: PathBuf,
+	lockdate_str: String,
+	hint: String,
+	label: Option<String>,
+	generate_url: bool,
+	armor: bool,
+	binary: bool,
+	envelope: bool,
+	force: bool,
+}
+
+/// Parse a manifest given either as JSON or as TOML. Both hold a list named
+/// "entries", i.e. {"entries": [..]} or [[entries]] tables.
+fn parse(manifest: &str) -> Result<Vec<Value>, Error> {
+	let value: Value;
+	if manifest.trim_start().starts_with('{') {
+		value = serde_json::from_str(manifest).map_err(|error| {
+			Error::Parse(format!("unable to parse the manifest as JSON: {}", error))
+		})?;
+	} else {
+		let table: toml::Table = manifest.parse::<toml::Table>().map_err(|error| {
+			Error::Parse(format!("unable to parse the manifest as TOML: {}", error.message()))
+		})?;
+		value = serde_json::to_value(table).map_err(|error| {
+			Error::Parse(format!("unable to parse the manifest as TOML: {}", error))
+		})?;
+	}
+
+	match value.get("entries") {
+		Some(Value::Array(entries)) => {
+			return Ok(entries.clone());
+		},
+		_ => {
+			return Err(Error::Parse(String::from("the manifest does not contain a list of \"entries\"")));
+		},
+	}
+}
+
+/// Get an optional string member of an entry.
+fn get_str(entry: &Map<String, Value>, name: &str) -> Result<Option<String>, Error> {
+	match entry.get(name) {
+		None => {
+			return Ok(None);
+		},
+		Some(Value::String(value)) => {
+			return Ok(Some(value.clone()));
+		},
+		Some(_) => {
+			return Err(Error::Argument(format!("the member \"{}\" must be a string", name)));
+		},
+	}
+}
+
+/// Get an optional boolean member of an entry. It defaults to false.
+fn get_bool(entry: &Map<String, Value>, name: &str) -> Result<bool, Error> {
+	match entry.get(name) {
+		None => {
+			return Ok(false);
+		},
+		Some(Value::Bool(value)) => {
+			return Ok(*value);
+		},
+		Some(_) => {
+			return Err(Error::Argument(format!("the member \"{}\" must be a boolean", name)));
+		},
+	}
+}
+
+/// Validate an entry of the manifest. Relative paths are resolved against
+/// the base directory.
+fn to_entry(value: &Value, base_dir: &Path) -> Result<Entry, Error> {
+	let entry: &Map<String, Value> = value.as_object().ok_or_else(|| {
+		Error::Argument(String::from("the entry is not a table"))
+	})?;
+
+	let input: String = get_str(entry, "input")?.ok_or_else(|| {
+		Error::Argument(String::from("the member \"input\" is missing"))
+	})?;
+	let lockdate_str: String = get_str(entry, "lockdate")?.ok_or_else(|| {
+		Error::Argument(String::from("the member \"lockdate\" is missing"))
+	})?;
+	let output: String = get_str(entry, "output")?
+		.unwrap_or_else(|| format!("{}{}", input, OUTPUT_SUFFIX));
+
+	return Ok(Entry {
+		input: base_dir.join(input),
+		output: base_dir.join(output),
+		lockdate_str,
+		hint: get_str(entry, "hint")?.unwrap_or_default(),
+		label: get_str(entry, "label")?,
+		generate_url: get_bool(entry, "url")?,
+		armor: get_bool(entry, "armor")?,
+		binary: get_bool(entry, "binary")?,
+		envelope: get_bool(entry, "envelope")?,
+		force: get_bool(entry, "force")?,
+	});
+}
+
+/// Encrypt the input file of an entry into its output file.
+fn seal_entry(args: &ManifestArg, entry: &Entry) -> Result<(), Error> {
+	let in_descriptor: File = File::open(&entry.input).map_err(|error| {
+		Error::Io(format!("unable to open the input file \"{}\": {}", entry.input.display(), error))
+	})?;
+
+	let mut partial: PathBuf = entry.output.clone();
+	partial.as_mut_os_string().push(PARTIAL_SUFFIX);
+	let out_descriptor: File = File::create(&partial).map_err(|error| {
+		Error::Io(format!("unable to create the output file \"{}\": {}", partial.display(), error))
+	})?;
+
+	let result: Result<(), Error> = encrypt(&EncryptArg {
+												lockdate_str: entry.lockdate_str.clone(),
+												occurrence_str: None,
+												hint: entry.hint.clone(),
+												binary: entry.binary,
+												envelope: entry.envelope,
+												armor: entry.armor,
+												generate_url: entry.generate_url,
+												url_base_str: args.url_base_str.clone(),
+												qr_format_str: None,
+												qr_level_str: None,
+												force_lockdate: entry.force,
+												force_url_length: entry.force,
+												json: false,
+												ledger: args.ledger,
+												label: entry.label.clone(),
+												output_filename: Some(entry.output.to_string_lossy().into_owned()),
+												ics_filename: None,
+												ics_alarm_str: None,
+											},
+											Box::new(in_descriptor),
+											Box::new(out_descriptor))
+		.and_then(|()| {
+			return fs::rename(&partial, &entry.output).map_err(|error| {
+				Error::Io(format!("unable to create the output file \"{}\": {}", entry.output.display(), error))
+			});
+		});
+	if result.is_err() {
+		let _ = fs::remove_file(&partial);
+	}
+
+	return result;
+}
+
+/// Encrypt every file listed in a manifest and write a JSON report holding
+/// the outcome of every entry. Unless continue_on_error is set, the first
+/// failing entry stops the run and the remaining entries are skipped. The
+/// error of the first failing entry is returned after the report has been
+/// written.
+pub fn seal(args: &ManifestArg,
+			mut in_descriptor: Box<dyn Read>,
+			mut out_descriptor: Box<dyn Write>)
+	-> Result<(), Error> {
+	let mut manifest: String = String::new();
+	in_descriptor.read_to_string(&mut manifest)?;
+	let values: Vec<Value> = parse(manifest.as_str())?;
+
+	//=========================================================================
+	// Encrypt the entries
+	let mut reports: Vec<Value> = Vec::new();
+	let mut first_error: Option<Error> = None;
+	let mut encrypted_count: usize = 0;
+	let mut failed_count: usize = 0;
+	let mut skipped_count: usize = 0;
+	for (index, value) in values.iter().enumerate() {
+		let input: Value = value.get("input").cloned().unwrap_or(Value::Null);
+		if first_error.is_some() && args.continue_on_error == false {
+			reports.push(json!({ "index": index, "input": input, "status": "skipped" }));
+			skipped_count += 1;
+			continue;
+		}
+
+		let result: Result<Entry, Error> = to_entry(value, args.base_dir.as_path())
+			.and_then(|entry| seal_entry(args, &entry).map(|()| entry));
+		match result {
+			Ok(entry) => {
+				eprintln!("[{}/{}] Encrypted {} to {}",
+						  index + 1, values.len(), entry.input.display(), entry.output.display());
+				reports.push(json!({
+					"index": index,
+					"input": input,
+					"output": entry.output.to_string_lossy(),
+					"status": "encrypted",
+				}));
+				encrypted_count += 1;
+			},
+			Err(error) => {
+				eprintln!("[{}/{}] Error: {}", index + 1, values.len(), error);
+				reports.push(json!({
+					"index": index,
+					"input": input,
+					"status": "failed",
+					"error": error.to_json(),
+				}));
+				failed_count += 1;
+				if first_error.is_none() {
+					first_error = Some(error);
+				}
+			},
+		}
+	}
+
+	//=========================================================================
+	// Write the report
+	let report: Value = json!({
+		"entries": reports,
+		"encrypted": encrypted_count,
+		"failed": failed_count,
+		"skipped": skipped_count,
+	});
+	writeln!(out_descriptor, "{}", serde_json::to_string_pretty(&report).unwrap_or_default())?;
+	eprintln!("Encrypted {} of {} file(s), {} failed, {} skipped.",
+			  encrypted_count, values.len(), failed_count, skipped_count);
+
+	match first_error {
+		Some(error) => {
+			return Err(error);
+		},
+		None => {
+			return Ok(());
+		},
+	}
+}
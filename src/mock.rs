@@ -0,0 +1,421 @@
+/**
+ * This file is part of snailcrypt-cli. For more information visit
+ * https://www snailcrypt.com
+ * Copyright (C) 2022-2023  Richard Bäck <richard.baeck@icloud.com>
+ *
+ * This program is free software; you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation; either version 2 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along
+ * with this program; if not, write to the Free Software Foundation, Inc.,
+ * 51 Franklin Street, Fifth Floor, Boston, MA 02110-1301 USA.
+ */
+
+
+use std::{
+	collections::{
+		hash_map::Entry,
+		HashMap,
+		VecDeque,
+	},
+	io::{
+		BufRead,
+		BufReader,
+		Read,
+		Write,
+	},
+	net::{
+		TcpListener,
+		TcpStream,
+	},
+	sync::{
+		Arc,
+		Mutex,
+		MutexGuard,
+	},
+	thread,
+	time,
+};
+
+use chrono::{
+	DateTime,
+	FixedOffset,
+	Local,
+};
+use openssl::{
+	pkey::Private,
+	rsa::Rsa,
+};
+use serde_json::{
+	json,
+	Value,
+};
+
+use crate::{
+	error::Error,
+	lockdate,
+	wait,
+};
+
+/// Date time format of the lock dates exchanged with the snailcrypt client.
+const MOCK_DATETIME_FORMAT: &str = "%Y-%m-%dT%H:%M:%S%z";
+
+/// Size of the generated RSA keys in bits. The snailcrypt client encrypts
+/// chunks of 126 bytes using OAEP padding, which requires at least 2048 bits.
+const KEY_BITS: u32 = 2048;
+
+/// Maximum size of a request in bytes.
+const REQUEST_MAX_LEN: usize = 64 * 1024;
+
+/// Options of the serve-mock operation.
+pub struct MockArg {
+	/// Address to listen on, e.g. "127.0.0.1:8080". Port 0 picks a free port.
+	pub listen: String,
+	/// Date the clock of the server is frozen at initially.
+	pub clock_str: Option<String>,
+}
+
+/// A failure injected into the responses of the server.
+#[derive(Clone)]
+enum Fault {
+	/// Respond with an error object of the API.
+	Error(String),
+	/// Respond with an HTTP status and a body which is not JSON.
+	Status(u16),
+	/// Respond with a body which is not valid JSON.
+	Malformed,
+	/// Close the connection without responding.
+	Drop,
+	/// Respond after a delay.
+	Delay(time::Duration),
+	/// Withhold the private key, even if the lock date has passed.
+	Withhold,
+}
+
+/// State of the server shared between the connections and the control
+/// commands.
+struct State {
+	/// Key pairs by the timestamp of their lock date.
+	keys: HashMap<i64, Rsa<Private>>,
+	/// The time the clock is frozen at, or None if it follows the real time.
+	frozen: Option<DateTime<FixedOffset>>,
+	/// Injected failures together with the number of requests they apply to.
+	faults: VecDeque<(Fault, usize)>,
+}
+
+impl State {
+	fn now(&self) -> DateTime<FixedOffset> {
+		return self.frozen.unwrap_or_else(|| Local::now().fixed_offset());
+	}
+
+	/// Take the fault applying to the next request.
+	fn next_fault(&mut self) -> Option<Fault> {
+		let (fault, count) = self.faults.front_mut()?;
+		let fault: Fault = fault.clone();
+		*count -= 1;
+		if *count == 0 {
+			self.faults.pop_front();
+		}
+
+		return Some(fault);
+	}
+}
+
+/// Lock the shared state.
+fn lock(shared: &Mutex<State>) -> Result<MutexGuard<'_, State>, Error> {
+	return shared.lock().map_err(|_error| Error::Io(String::from("the state of the mock server is poisoned")));
+}
+
+/// Log a message of the server on stderr.
+fn log(state: &State, message: String) {
+	eprintln!("[{}] {}", state.now().format(MOCK_DATETIME_FORMAT), message);
+}
+
+/// Write an HTTP response and close the connection.
+fn respond(stream: &mut TcpStream, status: u16, content_type: &str, body: &str) -> Result<(), Error> {
+	let reason: &str = match status {
+		200 => "OK",
+		400 => "Bad Request",
+		404 => "Not Found",
+		500 => "Internal Server Error",
+		503 => "Service Unavailable",
+		_ => "Unknown",
+	};
+	write!(stream,
+		   "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+		   status, reason, content_type, body.len(), body)?;
+	stream.flush()?;
+
+	return Ok(());
+}
+
+/// Read an HTTP request. The path and the body are returned.
+fn read_request(stream: &mut TcpStream) -> Result<(String, String), Error> {
+	let mut reader: BufReader<TcpStream> = BufReader::new(stream.try_clone()?);
+
+	let mut request_line: String = String::new();
+	reader.read_line(&mut request_line)?;
+	let path: String = String::from(request_line.split_whitespace().nth(1).unwrap_or("/"));
+
+	//=========================================================================
+	// Read the headers. curl waits for a confirmation before sending a larger
+	// body.
+	let mut content_length: usize = 0;
+	let mut expect_continue: bool = false;
+	loop {
+		let mut header: String = String::new();
+		if reader.read_line(&mut header)? == 0 || header.trim().is_empty() {
+			break;
+		}
+		if let Some((name, value)) = header.split_once(':') {
+			match name.trim().to_lowercase().as_str() {
+				"content-length" => content_length = value.trim().parse::<usize>().unwrap_or_default(),
+				"expect" => expect_continue = value.trim().eq_ignore_ascii_case("100-continue"),
+				_ => {},
+			}
+		}
+	}
+	if content_length > REQUEST_MAX_LEN {
+		return Err(Error::Argument(format!("the request is larger than {} bytes", REQUEST_MAX_LEN)));
+	}
+	if expect_continue == true {
+		stream.write_all(b"HTTP/1.1 100 Continue\r\n\r\n")?;
+	}
+
+	let mut body: Vec<u8> = vec![0; content_length];
+	reader.read_exact(body.as_mut_slice())?;
+
+	return Ok((path, String::from_utf8_lossy(body.as_slice()).into_owned()));
+}
+
+/// Handle a request of the key API: POST .../keys {"lock_date": ".."}. The
+/// public key of the lock date is always returned, the private key only once
+/// the lock date has passed according to the clock of the server.
+fn handle(shared: &Mutex<State>, mut stream: TcpStream) -> Result<(), Error> {
+	let (path, body) = read_request(&mut stream)?;
+	let mut state: MutexGuard<State> = lock(shared)?;
+
+	if !path.ends_with("/keys") {
+		log(&state, format!("{} not found", path));
+		return respond(&mut stream, 404, "application/json",
+					   json!({ "code": 404, "message": "not found" }).to_string().as_str());
+	}
+
+	let lockdate: Option<DateTime<FixedOffset>> = serde_json::from_str::<Value>(body.as_str())
+		.ok()
+		.and_then(|request| request.get("lock_date").and_then(Value::as_str).map(String::from))
+		.and_then(|lockdate_str| DateTime::parse_from_str(lockdate_str.as_str(), MOCK_DATETIME_FORMAT).ok());
+	let lockdate: DateTime<FixedOffset> = match lockdate {
+		Some(lockdate) => lockdate,
+		None => {
+			log(&state, format!("{} rejected a malformed request", path));
+			return respond(&mut stream, 400, "application/json",
+						   json!({ "code": 400, "message": "the lock date is missing or malformed" }).to_string().as_str());
+		},
+	};
+
+	//=========================================================================
+	// Inject a failure
+	let fault: Option<Fault> = state.next_fault();
+	let mut withhold: bool = false;
+	match &fault {
+		Some(Fault::Error(message)) => {
+			log(&state, format!("{} {} failed: {}", path, lockdate.format(MOCK_DATETIME_FORMAT), message));
+			return respond(&mut stream, 500, "application/json",
+						   json!({ "code": 500, "message": message }).to_string().as_str());
+		},
+		Some(Fault::Status(status)) => {
+			log(&state, format!("{} {} failed with status {}", path, lockdate.format(MOCK_DATETIME_FORMAT), status));
+			return respond(&mut stream, *status, "text/plain", "failure injected by the mock server");
+		},
+		Some(Fault::Malformed) => {
+			log(&state, format!("{} {} responded with malformed JSON", path, lockdate.format(MOCK_DATETIME_FORMAT)));
+			return respond(&mut stream, 200, "application/json", "{\"public_key\":");
+		},
+		Some(Fault::Drop) => {
+			log(&state, format!("{} {} dropped the connection", path, lockdate.format(MOCK_DATETIME_FORMAT)));
+			return Ok(());
+		},
+		Some(Fault::Delay(delay)) => {
+			let delay: time::Duration = *delay;
+			log(&state, format!("{} {} delayed by {}", path, lockdate.format(MOCK_DATETIME_FORMAT),
+								lockdate::format_duration(delay.as_secs() as i64)));
+			drop(state);
+			thread::sleep(delay);
+			state = lock(shared)?;
+		},
+		Some(Fault::Withhold) => {
+			withhold = true;
+		},
+		None => {},
+	}
+
+	//=========================================================================
+	// Look up or generate the key pair of the lock date
+	let now: DateTime<FixedOffset> = state.now();
+	if let Entry::Vacant(entry) = state.keys.entry(lockdate.timestamp()) {
+		entry.insert(Rsa::generate(KEY_BITS).map_err(|error| {
+			Error::Io(format!("unable to generate a key: {}", error))
+		})?);
+	}
+	let key: &Rsa<Private> = &state.keys[&lockdate.timestamp()];
+	let to_pem = |pem: Result<Vec<u8>, openssl::error::ErrorStack>| -> Result<String, Error> {
+		return pem
+			.map(|pem| String::from_utf8_lossy(pem.as_slice()).into_owned())
+			.map_err(|error| Error::Io(format!("unable to encode a key: {}", error)));
+	};
+
+	let mut response: Value = json!({
+		"lock_date": lockdate.format(MOCK_DATETIME_FORMAT).to_string(),
+		"public_key": to_pem(key.public_key_to_pem())?,
+	});
+	let released: bool = now >= lockdate && withhold == false;
+	if released == true {
+		response["private_key"] = Value::String(to_pem(key.private_key_to_pem())?);
+	}
+
+	log(&state, format!("{} {} {}", path, lockdate.format(MOCK_DATETIME_FORMAT),
+						if released == true { "released" } else { "locked" }));
+	return respond(&mut stream, 200, "application/json", response.to_string().as_str());
+}
+
+/// Parse an injected failure given by its kind and its argument.
+fn parse_fault(kind: &str, argument: &str) -> Result<Fault, String> {
+	match kind {
+		"error" if argument.is_empty() => Ok(Fault::Error(String::from("internal server error"))),
+		"error" => Ok(Fault::Error(String::from(argument))),
+		"status" => argument.parse::<u16>()
+			.ok()
+			.filter(|status| (400..600).contains(status))
+			.map(Fault::Status)
+			.ok_or_else(|| format!("invalid HTTP status \"{}\" (expected 400 to 599)", argument)),
+		"malformed" => Ok(Fault::Malformed),
+		"drop" => Ok(Fault::Drop),
+		"delay" => lockdate::parse_duration(argument)
+			.and_then(|delay| delay.to_std().map_err(|error| error.to_string()))
+			.map(Fault::Delay)
+			.map_err(|error| format!("unable to parse the delay \"{}\": {}", argument, error)),
+		"withhold" => Ok(Fault::Withhold),
+		_ => Err(format!("unknown fault \"{}\" (expected error, status, malformed, drop, delay or withhold)", kind)),
+	}
+}
+
+/// Perform a control command and get its answer:
+///
+/// - clock DATE: freeze the clock at the date, which may be relative to the
+///   current time of the clock (e.g. "+1h")
+/// - clock real: let the clock follow the real time again
+/// - advance DURATION: freeze the clock the duration ahead
+/// - fault [COUNT] KIND [ARGUMENT]: inject a failure into the next COUNT
+///   (default 1) requests
+/// - reset: remove all failures and let the clock follow the real time
+fn control(state: &mut State, line: &str) -> Result<String, String> {
+	let (command, rest) = line.trim().split_once(char::is_whitespace).unwrap_or((line.trim(), ""));
+	let rest: &str = rest.trim();
+
+	match command {
+		"clock" if rest == "real" => {
+			state.frozen = None;
+		},
+		"clock" => {
+			state.frozen = Some(lockdate::resolve(rest, state.now(), MOCK_DATETIME_FORMAT)
+							 .map_err(|error| String::from(error.message()))?);
+		},
+		"advance" => {
+			let duration: chrono::Duration = lockdate::parse_duration(rest).map_err(|error| {
+				format!("unable to parse the duration \"{}\": {}", rest, error)
+			})?;
+			state.frozen = Some(state.now() + duration);
+		},
+		"fault" => {
+			let mut words = rest.splitn(2, char::is_whitespace);
+			let mut kind: &str = words.next().unwrap_or_default();
+			let mut argument: &str = words.next().unwrap_or_default().trim();
+			let mut count: usize = 1;
+			if let Ok(kind_count) = kind.parse::<usize>() {
+				count = kind_count.max(1);
+				(kind, argument) = argument.split_once(char::is_whitespace).unwrap_or((argument, ""));
+			}
+			state.faults.push_back((parse_fault(kind, argument.trim())?, count));
+		},
+		"reset" => {
+			state.frozen = None;
+			state.faults.clear();
+		},
+		"" => {
+			return Err(String::from("empty command"));
+		},
+		command => {
+			return Err(format!("unknown command \"{}\" (expected clock, advance, fault or reset)", command));
+		},
+	}
+
+	return Ok(format!("ok {}", state.now().format(MOCK_DATETIME_FORMAT)));
+}
+
+/// Perform the serve-mock operation. A mock of the key server of snailcrypt is
+/// served, which keeps its keys in memory and releases them according to a
+/// controllable clock. Control commands are read from the input line by line
+/// and answered on the output.
+pub fn serve(args: &MockArg,
+			 in_descriptor: Box<dyn Read>,
+			 mut out_descriptor: Box<dyn Write>)
+	-> Result<(), Error> {
+	let mut state: State = State {
+		keys: HashMap::new(),
+		frozen: None,
+		faults: VecDeque::new(),
+	};
+	if let Some(clock_str) = &args.clock_str {
+		state.frozen = Some(lockdate::resolve(clock_str.as_str(), state.now(), MOCK_DATETIME_FORMAT)?);
+	}
+
+	let listener: TcpListener = TcpListener::bind(args.listen.as_str()).map_err(|error| {
+		Error::Io(format!("unable to listen on \"{}\": {}", args.listen, error))
+	})?;
+	wait::install_interrupt_handler()?;
+
+	writeln!(out_descriptor, "Listening on http://{}", listener.local_addr()?)?;
+	out_descriptor.flush()?;
+
+	//=========================================================================
+	// Serve the requests one after another
+	let shared: Arc<Mutex<State>> = Arc::new(Mutex::new(state));
+	let server_state: Arc<Mutex<State>> = Arc::clone(&shared);
+	thread::spawn(move || {
+		for stream in listener.incoming().flatten() {
+			if let Err(error) = handle(&server_state, stream) {
+				eprintln!("Error: {}", error);
+			}
+		}
+	});
+
+	//=========================================================================
+	// Perform the control commands until the input ends, then serve until
+	// SIGINT
+	for line in BufReader::new(in_descriptor).lines() {
+		let line: String = line?;
+		if line.trim().is_empty() {
+			continue;
+		}
+
+		let answer: String = control(&mut *lock(&shared)?, line.as_str())
+			.unwrap_or_else(|error| format!("error {}", error));
+		writeln!(out_descriptor, "{}", answer)?;
+		out_descriptor.flush()?;
+	}
+
+	loop {
+		if let Err(error) = wait::sleep(time::Duration::from_secs(60)) {
+			log(&*lock(&shared)?, String::from("Stopped serving"));
+			return Err(error);
+		}
+	}
+}
@@ -0,0 +1,75 @@
+/**
+ * This file is part of snailcrypt-cli. For more information visit
+ * https://www snailcrypt.com
+ * Copyright (C) 2022-2023  Richard Bäck <richard.baeck@icloud.com>
+ *
+ * This program is free software; you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation; either version 2 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along
+ * with this program; if not, write to the Free Software Foundation, Inc.,
+ * 51 Franklin Street, Fifth Floor, Boston, MA 02110-1301 USA.
+ */
+
+
+use crate::error::Error;
+
+/// Prefix marking a plaintext which carries base64 encoded binary data.
+const BINARY_PREFIX: &str = "snailcrypt-cli:binary:";
+
+/// Encode the data to be encrypted into a plaintext string which can be handed
+/// to the snailcrypt client.
+///
+/// Data is stored as is if it is valid UTF-8. Otherwise, or if `binary` is set,
+/// the data is base64 encoded and prefixed with a marker so it can be restored
+/// byte by byte on decryption. Text containing NUL characters or starting with
+/// the marker itself is always encoded as binary data, because it would not
+/// survive the round trip otherwise.
+pub fn encode(data: Vec<u8>, binary: bool) -> String {
+	if binary == false {
+		if let Ok(text) = String::from_utf8(data.clone()) {
+			if !text.contains('\0') && !text.starts_with(BINARY_PREFIX) {
+				return text;
+			}
+		}
+	}
+
+	let mut plaintext: String = String::from(BINARY_PREFIX);
+	plaintext.push_str(base64::encode(data.as_slice()).as_str());
+
+	return plaintext;
+}
+
+/// Restore the original data from a decrypted plaintext string.
+pub fn decode(plaintext: String) -> Result<Vec<u8>, Error> {
+	match plaintext.strip_prefix(BINARY_PREFIX) {
+		Some(encoded) => {
+			return base64::decode(encoded).map_err(|error| {
+				Error::Malformed(format!("the binary payload is malformed: {}", error))
+			});
+		},
+		None => {
+			return Ok(plaintext.into_bytes());
+		},
+	}
+}
+
+/// Represent decrypted data as text for a JSON report. Data which is not valid
+/// UTF-8 is base64 encoded. The name of the encoding is returned as well.
+pub fn to_text(data: Vec<u8>) -> (String, &'static str) {
+	match String::from_utf8(data) {
+		Ok(text) => {
+			return (text, "utf-8");
+		},
+		Err(error) => {
+			return (base64::encode(error.as_bytes()), "base64");
+		},
+	}
+}
@@ -0,0 +1,201 @@
+/**
+ * This file is part of snailcrypt-cli. For more information visit
+ * https://www snailcrypt.com
+ * Copyright (C) 2022-2023  Richard Bäck <richard.baeck@icloud.com>
+ *
+ * This program is free software; you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation; either version 2 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along
+ * with this program; if not, write to the Free Software Foundation, Inc.,
+ * 51 Franklin Street, Fifth Floor, Boston, MA 02110-1301 USA.
+ */
+
+
+use std::io::{
+	Cursor,
+	Write,
+};
+
+use image::{
+	ImageFormat,
+	Luma,
+};
+use qrcode::{
+	render::svg,
+	Color,
+	EcLevel,
+	QrCode,
+	types::QrError,
+};
+
+use crate::error::Error;
+
+/// Size of a module of a PNG image in pixels.
+const PNG_MODULE_SIZE: u32 = 8;
+
+/// Size of the quiet zone around a QR code rendered in the terminal in modules.
+const TERMINAL_QUIET_ZONE: usize = 2;
+
+/// Output formats of a QR code.
+pub enum QrFormat {
+	Png,
+	Svg,
+	Terminal,
+	Ansi,
+}
+
+/// Get the QR code format of its name.
+pub fn str_to_format(format: &str) -> Option<QrFormat> {
+	match format.to_lowercase().as_str() {
+		"png"                 => Some(QrFormat::Png),
+		"svg"                 => Some(QrFormat::Svg),
+		"terminal" | "utf8"   => Some(QrFormat::Terminal),
+		"ansi"                => Some(QrFormat::Ansi),
+		_                     => None,
+	}
+}
+
+/// Get the error correction level of its name.
+pub fn str_to_ec_level(ec_level: &str) -> Option<EcLevel> {
+	match ec_level.to_uppercase().as_str() {
+		"L" => Some(EcLevel::L),
+		"M" => Some(EcLevel::M),
+		"Q" => Some(EcLevel::Q),
+		"H" => Some(EcLevel::H),
+		_   => None,
+	}
+}
+
+/// Maximum amount of bytes a QR code (version 40) can hold at an error
+/// correction level.
+fn byte_capacity(ec_level: EcLevel) -> usize {
+	match ec_level {
+		EcLevel::L => 2953,
+		EcLevel::M => 2331,
+		EcLevel::Q => 1663,
+		EcLevel::H => 1273,
+	}
+}
+
+fn ec_level_to_str(ec_level: EcLevel) -> &'static str {
+	match ec_level {
+		EcLevel::L => "L",
+		EcLevel::M => "M",
+		EcLevel::Q => "Q",
+		EcLevel::H => "H",
+	}
+}
+
+/// Check whether a module of a QR code is dark. Modules outside of the code
+/// belong to the quiet zone and are light.
+fn is_dark(colors: &[Color], width: usize, x: usize, y: usize) -> bool {
+	if x < TERMINAL_QUIET_ZONE
+		|| y < TERMINAL_QUIET_ZONE
+		|| x >= width + TERMINAL_QUIET_ZONE
+		|| y >= width + TERMINAL_QUIET_ZONE {
+		return false;
+	}
+
+	return colors[(y - TERMINAL_QUIET_ZONE) * width + x - TERMINAL_QUIET_ZONE] == Color::Dark;
+}
+
+/// Render a QR code using Unicode half blocks. Two rows of modules share a
+/// line. The light modules are drawn, so the code is readable on the usual
+/// light text on a dark background.
+fn render_terminal(code: &QrCode) -> String {
+	let colors: Vec<Color> = code.to_colors();
+	let width: usize = code.width();
+	let size: usize = width + 2 * TERMINAL_QUIET_ZONE;
+
+	let mut rendered: String = String::new();
+	for y in (0..size).step_by(2) {
+		for x in 0..size {
+			let upper_light: bool = !is_dark(&colors, width, x, y);
+			let lower_light: bool = y + 1 < size && !is_dark(&colors, width, x, y + 1);
+			rendered.push(match (upper_light, lower_light) {
+				(true, true)   => '\u{2588}',
+				(true, false)  => '\u{2580}',
+				(false, true)  => '\u{2584}',
+				(false, false) => ' ',
+			});
+		}
+		rendered.push('\n');
+	}
+
+	return rendered;
+}
+
+/// Render a QR code using ANSI background colors, so it is readable
+/// independently of the colors of the terminal.
+fn render_ansi(code: &QrCode) -> String {
+	let colors: Vec<Color> = code.to_colors();
+	let width: usize = code.width();
+	let size: usize = width + 2 * TERMINAL_QUIET_ZONE;
+
+	let mut rendered: String = String::new();
+	for y in 0..size {
+		for x in 0..size {
+			if is_dark(&colors, width, x, y) {
+				rendered.push_str("\x1b[40m  ");
+			} else {
+				rendered.push_str("\x1b[47m  ");
+			}
+		}
+		rendered.push_str("\x1b[0m\n");
+	}
+
+	return rendered;
+}
+
+/// Encode data as a QR code and write it in the given format. The smallest
+/// version holding the data is selected automatically.
+pub fn render(data: &str,
+			  format: &QrFormat,
+			  ec_level: EcLevel,
+			  out_descriptor: &mut dyn Write)
+	-> Result<(), Error> {
+	let code: QrCode = QrCode::with_error_correction_level(data.as_bytes(), ec_level)
+		.map_err(|error| match error {
+		QrError::DataTooLong => {
+			Error::Argument(format!("the message of {} bytes exceeds the capacity of a QR code ({} bytes at error correction level {})",
+									data.len(),
+									byte_capacity(ec_level),
+									ec_level_to_str(ec_level)))
+		},
+		error => Error::Argument(format!("unable to generate a QR code: {}", error)),
+	})?;
+
+	match format {
+		QrFormat::Png => {
+			let mut png: Cursor<Vec<u8>> = Cursor::new(Vec::new());
+			code.render::<Luma<u8>>()
+				.module_dimensions(PNG_MODULE_SIZE, PNG_MODULE_SIZE)
+				.build()
+				.write_to(&mut png, ImageFormat::Png)
+				.map_err(|error| Error::Io(format!("unable to write the PNG image: {}", error)))?;
+			out_descriptor.write_all(png.get_ref().as_slice())?;
+		},
+		QrFormat::Svg => {
+			let image: String = code.render::<svg::Color>().build();
+			out_descriptor.write_all(image.as_bytes())?;
+		},
+		QrFormat::Terminal => {
+			out_descriptor.write_all(render_terminal(&code).as_bytes())?;
+		},
+		QrFormat::Ansi => {
+			out_descriptor.write_all(render_ansi(&code).as_bytes())?;
+		},
+	}
+
+	out_descriptor.flush()?;
+
+	return Ok(());
+}
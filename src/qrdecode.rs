@@ -0,0 +1,1651 @@
+/**
+ * This file is part of snailcrypt-cli. For more information visit
+ * https://www snailcrypt.com
+ * Copyright (C) 2022-2023  Richard Bäck <richard.baeck@icloud.com>
+ *
+ * This program is free software; you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation; either version 2 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along
+ * with this program; if not, write to the Free Software Foundation, Inc.,
+ * 51 Franklin Street, Fifth Floor, Boston, MA 02110-1301 USA.
+ */
+
+
+use image::{
+	imageops::{
+		self,
+		FilterType,
+	},
+	GrayImage,
+};
+
+use crate::error::Error;
+
+/// Magic of a PNG image.
+const PNG_MAGIC: &[u8] = b"\x89PNG\r\n\x1a\n";
+
+/// Magic of a JPEG image.
+const JPEG_MAGIC: &[u8] = b"\xff\xd8\xff";
+
+/// Larger images are scaled down before searching for a QR code.
+const MAX_IMAGE_DIMENSION: u32 = 2000;
+
+/// Number of the best finder pattern triples tried per binarization.
+const MAX_TRIPLE_TRIES: usize = 4;
+
+/// Characters of the alphanumeric mode.
+const ALPHANUMERIC_CHARSET: &[u8] = b"0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZ $%*+-./:";
+
+/// Error correction codewords per block, indexed by the error correction level
+/// (L, M, Q, H) and the version.
+const ECC_CODEWORDS_PER_BLOCK: [[u8; 41]; 4] = [
+	[0,  7, 10, 15, 20, 26, 18, 20, 24, 30, 18, 20, 24, 26, 30, 22, 24, 28, 30, 28, 28, 28, 28, 30, 30, 26, 28, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30],
+	[0, 10, 16, 26, 18, 24, 16, 18, 22, 22, 26, 30, 22, 22, 24, 24, 28, 28, 26, 26, 26, 26, 28, 28, 28, 28, 28, 28, 28, 28, 28, 28, 28, 28, 28, 28, 28, 28, 28, 28, 28],
+	[0, 13, 22, 18, 26, 18, 24, 18, 22, 20, 24, 28, 26, 24, 20, 30, 24, 28, 28, 26, 30, 28, 30, 30, 30, 30, 28, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30],
+	[0, 17, 28, 22, 16, 22, 28, 26, 26, 24, 28, 24, 28, 22, 24, 24, 30, 28, 28, 26, 28, 30, 24, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30],
+];
+
+/// Error correction blocks, indexed by the error correction level (L, M, Q, H)
+/// and the version.
+const NUM_ERROR_CORRECTION_BLOCKS: [[u8; 41]; 4] = [
+	[0, 1, 1, 1, 1, 1, 2, 2, 2, 2, 4,  4,  4,  4,  4,  6,  6,  6,  6,  7,  8,  8,  9,  9, 10, 12, 12, 12, 13, 14, 15, 16, 17, 18, 19, 19, 20, 21, 22, 24, 25],
+	[0, 1, 1, 1, 2, 2, 4, 4, 4, 5, 5,  5,  8,  9,  9, 10, 10, 11, 13, 14, 16, 17, 17, 18, 20, 21, 23, 25, 26, 28, 29, 31, 33, 35, 37, 38, 40, 43, 45, 47, 49],
+	[0, 1, 1, 2, 2, 4, 4, 6, 6, 8, 8,  8, 10, 12, 16, 12, 17, 16, 18, 21, 20, 23, 23, 25, 27, 29, 34, 34, 35, 38, 40, 43, 45, 48, 51, 53, 56, 59, 62, 65, 68],
+	[0, 1, 1, 2, 4, 4, 4, 5, 6, 8, 8, 11, 11, 16, 16, 18, 16, 19, 21, 25, 25, 25, 34, 30, 32, 35, 37, 40, 42, 45, 48, 51, 54, 57, 60, 63, 66, 70, 74, 77, 81],
+];
+
+//=============================================================================
+// Binarization
+
+/// Black and white image. Pixels outside of the image are light.
+struct Bitmap {
+	width: i64,
+	height: i64,
+	dark: Vec<bool>,
+}
+
+impl Bitmap {
+	fn contains(&self, x: i64, y: i64) -> bool {
+		return x >= 0 && y >= 0 && x < self.width && y < self.height;
+	}
+
+	fn is_dark(&self, x: i64, y: i64) -> bool {
+		return self.contains(x, y) && self.dark[(y * self.width + x) as usize];
+	}
+}
+
+/// Binarize an image using a single threshold chosen by Otsu's method. This
+/// suits rendered images and evenly lit photos.
+fn binarize_global(image: &GrayImage) -> Bitmap {
+	let mut histogram: [u64; 256] = [0; 256];
+	for pixel in image.pixels() {
+		histogram[pixel.0[0] as usize] += 1;
+	}
+
+	let total: u64 = image.pixels().len() as u64;
+	let sum: f64 = histogram.iter()
+		.enumerate()
+		.map(|(value, count)| value as f64 * *count as f64)
+		.sum();
+
+	let mut threshold: u8 = 127;
+	let mut best_variance: f64 = -1.0;
+	let mut background_count: u64 = 0;
+	let mut background_sum: f64 = 0.0;
+	for (value, count) in histogram.iter().enumerate() {
+		background_count += count;
+		if background_count == 0 || background_count == total {
+			continue;
+		}
+		background_sum += value as f64 * *count as f64;
+
+		let background_mean: f64 = background_sum / background_count as f64;
+		let foreground_mean: f64 = (sum - background_sum) / (total - background_count) as f64;
+		let variance: f64 = background_count as f64
+			* (total - background_count) as f64
+			* (background_mean - foreground_mean).powi(2);
+		if variance > best_variance {
+			best_variance = variance;
+			threshold = value as u8;
+		}
+	}
+
+	return Bitmap {
+		width: image.width() as i64,
+		height: image.height() as i64,
+		dark: image.pixels().map(|pixel| pixel.0[0] <= threshold).collect(),
+	};
+}
+
+/// Binarize an image comparing each pixel to the mean of its neighborhood.
+/// This suits photos with uneven lighting.
+fn binarize_local(image: &GrayImage) -> Bitmap {
+	let width: usize = image.width() as usize;
+	let height: usize = image.height() as usize;
+	let radius: usize = (width.max(height) / 16).max(8);
+
+	//=========================================================================
+	// Build the integral image
+	let mut integral: Vec<u64> = vec![0; (width + 1) * (height + 1)];
+	for y in 0..height {
+		let mut row_sum: u64 = 0;
+		for x in 0..width {
+			row_sum += image.get_pixel(x as u32, y as u32).0[0] as u64;
+			integral[(y + 1) * (width + 1) + x + 1] = integral[y * (width + 1) + x + 1] + row_sum;
+		}
+	}
+
+	let mut dark: Vec<bool> = Vec::with_capacity(width * height);
+	for y in 0..height {
+		for x in 0..width {
+			let x0: usize = x.saturating_sub(radius);
+			let y0: usize = y.saturating_sub(radius);
+			let x1: usize = (x + radius + 1).min(width);
+			let y1: usize = (y + radius + 1).min(height);
+			let sum: u64 = integral[y1 * (width + 1) + x1] + integral[y0 * (width + 1) + x0]
+				- integral[y0 * (width + 1) + x1] - integral[y1 * (width + 1) + x0];
+			let count: u64 = ((x1 - x0) * (y1 - y0)) as u64;
+			let value: u64 = image.get_pixel(x as u32, y as u32).0[0] as u64;
+
+			dark.push(value * count * 100 < sum * 90);
+		}
+	}
+
+	return Bitmap {
+		width: width as i64,
+		height: height as i64,
+		dark,
+	};
+}
+
+//=============================================================================
+// Finder patterns
+
+#[derive(Clone, Copy)]
+struct Point {
+	x: f64,
+	y: f64,
+}
+
+fn distance(a: Point, b: Point) -> f64 {
+	return ((a.x - b.x).powi(2) + (a.y - b.y).powi(2)).sqrt();
+}
+
+/// Candidate of a finder pattern. The count is the amount of scan lines which
+/// confirmed it.
+struct FinderPattern {
+	center: Point,
+	module_size: f64,
+	count: usize,
+}
+
+/// Check whether five runs have the 1:1:3:1:1 ratio of a finder pattern.
+fn is_finder_ratio(counts: &[usize; 5]) -> bool {
+	let total: usize = counts.iter().sum();
+	if total < 7 || counts.contains(&0) {
+		return false;
+	}
+
+	let module_size: f64 = total as f64 / 7.0;
+	let max_variance: f64 = module_size * 0.6;
+
+	return (module_size - counts[0] as f64).abs() < max_variance
+		&& (module_size - counts[1] as f64).abs() < max_variance
+		&& (3.0 * module_size - counts[2] as f64).abs() < 3.0 * max_variance
+		&& (module_size - counts[3] as f64).abs() < max_variance
+		&& (module_size - counts[4] as f64).abs() < max_variance;
+}
+
+/// Check for a finder pattern crossing the point (x, y) in the direction
+/// (dx, dy). Returns the offset of its center relative to the point and the
+/// size of the pattern in pixels.
+fn cross_check(bitmap: &Bitmap,
+			   x: i64,
+			   y: i64,
+			   dx: i64,
+			   dy: i64,
+			   max_count: usize)
+	-> Option<(f64, usize)> {
+	let mut counts: [usize; 5] = [0; 5];
+
+	//=========================================================================
+	// Count backwards
+	let mut i: i64 = 0;
+	while bitmap.contains(x - i * dx, y - i * dy) && bitmap.is_dark(x - i * dx, y - i * dy) {
+		counts[2] += 1;
+		i += 1;
+	}
+	let backward_center: usize = counts[2];
+	while bitmap.contains(x - i * dx, y - i * dy) && !bitmap.is_dark(x - i * dx, y - i * dy)
+		&& counts[1] <= max_count {
+		counts[1] += 1;
+		i += 1;
+	}
+	while bitmap.contains(x - i * dx, y - i * dy) && bitmap.is_dark(x - i * dx, y - i * dy)
+		&& counts[0] <= max_count {
+		counts[0] += 1;
+		i += 1;
+	}
+
+	//=========================================================================
+	// Count forwards
+	i = 1;
+	while bitmap.contains(x + i * dx, y + i * dy) && bitmap.is_dark(x + i * dx, y + i * dy) {
+		counts[2] += 1;
+		i += 1;
+	}
+	let forward_center: usize = counts[2] - backward_center;
+	while bitmap.contains(x + i * dx, y + i * dy) && !bitmap.is_dark(x + i * dx, y + i * dy)
+		&& counts[3] <= max_count {
+		counts[3] += 1;
+		i += 1;
+	}
+	while bitmap.contains(x + i * dx, y + i * dy) && bitmap.is_dark(x + i * dx, y + i * dy)
+		&& counts[4] <= max_count {
+		counts[4] += 1;
+		i += 1;
+	}
+
+	if !is_finder_ratio(&counts) {
+		return None;
+	}
+
+	let offset: f64 = (forward_center as f64 - (backward_center as f64 - 1.0)) / 2.0;
+
+	return Some((offset + 0.5, counts.iter().sum()));
+}
+
+/// Confirm a finder pattern found on a row and add it to the candidates.
+fn handle_finder_candidate(bitmap: &Bitmap,
+						   counts: &[usize; 5],
+						   end: i64,
+						   y: i64,
+						   candidates: &mut Vec<FinderPattern>) {
+	let row_total: usize = counts.iter().sum();
+	let center_x: f64 = end as f64 - counts[4] as f64 - counts[3] as f64 - counts[2] as f64 / 2.0;
+
+	//=========================================================================
+	// Cross check vertically and horizontally again
+	let (offset_y, column_total) = match cross_check(bitmap, center_x as i64, y, 0, 1, counts[2]) {
+		Some(result) => result,
+		None => {
+			return;
+		},
+	};
+	if 5 * row_total.abs_diff(column_total) >= 2 * row_total {
+		return;
+	}
+	let center_y: f64 = y as f64 + offset_y;
+
+	let (offset_x, row_total) = match cross_check(bitmap, center_x as i64, center_y as i64, 1, 0, counts[2]) {
+		Some(result) => result,
+		None => {
+			return;
+		},
+	};
+	let center: Point = Point {
+		x: (center_x as i64) as f64 + offset_x,
+		y: center_y,
+	};
+
+	//=========================================================================
+	// Cross check diagonally to reject patterns formed by data modules
+	if cross_check(bitmap, center.x as i64, center.y as i64, 1, 1, 2 * counts[2]).is_none() {
+		return;
+	}
+	let module_size: f64 = (row_total + column_total) as f64 / 14.0;
+
+	//=========================================================================
+	// Merge with a known candidate
+	for candidate in candidates.iter_mut() {
+		if distance(candidate.center, center) <= candidate.module_size.max(module_size)
+			&& (candidate.module_size - module_size).abs() <= candidate.module_size.max(1.0) {
+			let count: f64 = candidate.count as f64;
+			candidate.center = Point {
+				x: (candidate.center.x * count + center.x) / (count + 1.0),
+				y: (candidate.center.y * count + center.y) / (count + 1.0),
+			};
+			candidate.module_size = (candidate.module_size * count + module_size) / (count + 1.0);
+			candidate.count += 1;
+			return;
+		}
+	}
+
+	candidates.push(FinderPattern {
+		center,
+		module_size,
+		count: 1,
+	});
+}
+
+/// Find candidates of finder patterns by scanning every row.
+fn find_finder_patterns(bitmap: &Bitmap) -> Vec<FinderPattern> {
+	let mut candidates: Vec<FinderPattern> = Vec::new();
+
+	for y in 0..bitmap.height {
+		let mut counts: [usize; 5] = [0; 5];
+		let mut state: usize = 0;
+
+		for x in 0..bitmap.width {
+			if bitmap.is_dark(x, y) {
+				if state % 2 == 1 {
+					state += 1;
+				}
+				counts[state] += 1;
+			} else if state % 2 == 1 {
+				counts[state] += 1;
+			} else if state == 0 && counts[0] == 0 {
+				continue;
+			} else if state == 4 {
+				if is_finder_ratio(&counts) {
+					handle_finder_candidate(bitmap, &counts, x, y, &mut candidates);
+				}
+				counts = [counts[2], counts[3], counts[4], 1, 0];
+				state = 3;
+			} else {
+				state += 1;
+				counts[state] += 1;
+			}
+		}
+
+		if state == 4 && is_finder_ratio(&counts) {
+			handle_finder_candidate(bitmap, &counts, bitmap.width, y, &mut candidates);
+		}
+	}
+
+	return candidates;
+}
+
+/// Order three finder patterns as top left, top right and bottom left.
+fn order_finder_patterns<'a>(a: &'a FinderPattern,
+							 b: &'a FinderPattern,
+							 c: &'a FinderPattern)
+	-> [&'a FinderPattern; 3] {
+	let ab: f64 = distance(a.center, b.center);
+	let bc: f64 = distance(b.center, c.center);
+	let ac: f64 = distance(a.center, c.center);
+
+	//=========================================================================
+	// The top left pattern is opposite of the longest side
+	let (top_left, mut top_right, mut bottom_left) = if bc >= ab && bc >= ac {
+		(a, b, c)
+	} else if ac >= ab && ac >= bc {
+		(b, a, c)
+	} else {
+		(c, a, b)
+	};
+
+	//=========================================================================
+	// Seen from the top left pattern the top right pattern comes first
+	// clockwise
+	let cross: f64 = (top_right.center.x - top_left.center.x) * (bottom_left.center.y - top_left.center.y)
+		- (top_right.center.y - top_left.center.y) * (bottom_left.center.x - top_left.center.x);
+	if cross < 0.0 {
+		std::mem::swap(&mut top_right, &mut bottom_left);
+	}
+
+	return [top_left, top_right, bottom_left];
+}
+
+/// Encode a version into its 18 bit codeword.
+fn version_codeword(version: u32) -> u32 {
+	let mut remainder: u32 = version;
+	for _ in 0..12 {
+		remainder = (remainder << 1) ^ ((remainder >> 11) * 0x1f25);
+	}
+
+	return (version << 12) | remainder;
+}
+
+/// Read the version information next to the top right and the bottom left
+/// finder pattern. It is only present from version 7 on.
+fn read_version(bitmap: &Bitmap, finders: &[&FinderPattern; 3]) -> Option<usize> {
+	let [top_left, top_right, bottom_left] = *finders;
+	let unit = |from: Point, to: Point, module_size: f64| -> Point {
+		let length: f64 = distance(from, to);
+		return Point {
+			x: (to.x - from.x) / length * module_size,
+			y: (to.y - from.y) / length * module_size,
+		};
+	};
+	let sample = |origin: &FinderPattern, u: f64, v: f64| -> u32 {
+		let step_u: Point = unit(top_left.center, top_right.center, origin.module_size);
+		let step_v: Point = unit(top_left.center, bottom_left.center, origin.module_size);
+		let x: f64 = origin.center.x + u * step_u.x + v * step_v.x;
+		let y: f64 = origin.center.y + u * step_u.y + v * step_v.y;
+		return bitmap.is_dark(x.floor() as i64, y.floor() as i64) as u32;
+	};
+
+	let mut upper: u32 = 0;
+	let mut lower: u32 = 0;
+	for i in 0..18 {
+		upper |= sample(top_right, (i % 3) as f64 - 7.0, (i / 3) as f64 - 3.0) << i;
+		lower |= sample(bottom_left, (i / 3) as f64 - 3.0, (i % 3) as f64 - 7.0) << i;
+	}
+
+	let mut best: Option<(usize, u32)> = None;
+	for version in 7..=40 {
+		let codeword: u32 = version_codeword(version);
+		let distance: u32 = (codeword ^ upper).count_ones().min((codeword ^ lower).count_ones());
+		if distance <= 3 && best.is_none_or(|(_version, best_distance)| distance < best_distance) {
+			best = Some((version as usize, distance));
+		}
+	}
+
+	return best.map(|(version, _distance)| version);
+}
+
+/// Select triples of finder patterns which likely belong to the same QR code,
+/// best first.
+fn select_triples(candidates: &[FinderPattern]) -> Vec<(usize, usize, usize)> {
+	let mut sorted: Vec<usize> = (0..candidates.len()).collect();
+	sorted.sort_by(|a, b| candidates[*b].count.cmp(&candidates[*a].count));
+	sorted.truncate(12);
+
+	let mut triples: Vec<(f64, (usize, usize, usize))> = Vec::new();
+	for i in 0..sorted.len() {
+		for j in (i + 1)..sorted.len() {
+			for k in (j + 1)..sorted.len() {
+				let patterns: [&FinderPattern; 3] = [&candidates[sorted[i]],
+													 &candidates[sorted[j]],
+													 &candidates[sorted[k]]];
+				let min_module_size: f64 = patterns.iter().map(|p| p.module_size).fold(f64::MAX, f64::min);
+				let max_module_size: f64 = patterns.iter().map(|p| p.module_size).fold(0.0, f64::max);
+				if max_module_size > 1.6 * min_module_size {
+					continue;
+				}
+
+				//=============================================================
+				// The patterns form an isosceles right triangle
+				let mut sides: [f64; 3] = [
+					distance(patterns[0].center, patterns[1].center).powi(2),
+					distance(patterns[1].center, patterns[2].center).powi(2),
+					distance(patterns[0].center, patterns[2].center).powi(2),
+				];
+				sides.sort_by(|a, b| a.total_cmp(b));
+				if sides[0] < (7.0 * min_module_size).powi(2) {
+					continue;
+				}
+
+				let score: f64 = (sides[2] - sides[0] - sides[1]).abs() / sides[2]
+					+ (sides[1] - sides[0]).abs() / sides[1]
+					+ (max_module_size - min_module_size) / max_module_size;
+				triples.push((score, (sorted[i], sorted[j], sorted[k])));
+			}
+		}
+	}
+
+	triples.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+	return triples.into_iter().map(|(_score, triple)| triple).take(MAX_TRIPLE_TRIES).collect();
+}
+
+//=============================================================================
+// Sampling
+
+/// Projective transformation from module coordinates to image coordinates.
+struct Transform {
+	h: [f64; 8],
+}
+
+impl Transform {
+	/// Fit the transformation mapping the source points to the destination
+	/// points. Four points are mapped exactly, more points are fitted using
+	/// least squares.
+	fn fit(src: &[Point], dst: &[Point]) -> Option<Transform> {
+		//=====================================================================
+		// Build the normal equations
+		let mut matrix: [[f64; 9]; 8] = [[0.0; 9]; 8];
+		for i in 0..src.len() {
+			let (u, v, x, y) = (src[i].x, src[i].y, dst[i].x, dst[i].y);
+			let rows: [[f64; 9]; 2] = [
+				[u, v, 1.0, 0.0, 0.0, 0.0, -u * x, -v * x, x],
+				[0.0, 0.0, 0.0, u, v, 1.0, -u * y, -v * y, y],
+			];
+			for row in rows.iter() {
+				for j in 0..8 {
+					for k in 0..9 {
+						matrix[j][k] += row[j] * row[k];
+					}
+				}
+			}
+		}
+
+		//=====================================================================
+		// Gaussian elimination with partial pivoting
+		for column in 0..8 {
+			let pivot: usize = (column..8)
+				.max_by(|a, b| matrix[*a][column].abs().total_cmp(&matrix[*b][column].abs()))
+				.unwrap_or(column);
+			if matrix[pivot][column].abs() < 1e-12 {
+				return None;
+			}
+			matrix.swap(column, pivot);
+
+			let pivot_row: [f64; 9] = matrix[column];
+			for (row, values) in matrix.iter_mut().enumerate() {
+				if row != column {
+					let factor: f64 = values[column] / pivot_row[column];
+					for (value, pivot_value) in values.iter_mut().zip(pivot_row.iter()).skip(column) {
+						*value -= factor * pivot_value;
+					}
+				}
+			}
+		}
+
+		let mut h: [f64; 8] = [0.0; 8];
+		for i in 0..8 {
+			h[i] = matrix[i][8] / matrix[i][i];
+		}
+
+		return Some(Transform { h });
+	}
+
+	fn map(&self, u: f64, v: f64) -> Point {
+		let h: &[f64; 8] = &self.h;
+		let w: f64 = h[6] * u + h[7] * v + 1.0;
+
+		return Point {
+			x: (h[0] * u + h[1] * v + h[2]) / w,
+			y: (h[3] * u + h[4] * v + h[5]) / w,
+		};
+	}
+}
+
+/// Find an alignment pattern near its expected position given in module
+/// coordinates. Returns its center in image coordinates.
+fn find_alignment_pattern(bitmap: &Bitmap,
+						  transform: &Transform,
+						  u: f64,
+						  v: f64,
+						  radius_modules: f64)
+	-> Option<Point> {
+	let expected: Point = transform.map(u, v);
+	let unit_u: Point = transform.map(u + 1.0, v);
+	let unit_v: Point = transform.map(u, v + 1.0);
+	let step_u: Point = Point { x: unit_u.x - expected.x, y: unit_u.y - expected.y };
+	let step_v: Point = Point { x: unit_v.x - expected.x, y: unit_v.y - expected.y };
+	let module_size: f64 = (distance(expected, unit_u) + distance(expected, unit_v)) / 2.0;
+
+	let radius: i64 = (radius_modules * module_size).ceil() as i64;
+	let step: i64 = ((module_size / 4.0) as i64).max(1);
+
+	let mut best_score: usize = 0;
+	let mut best: Vec<Point> = Vec::new();
+	let mut dy: i64 = -radius;
+	while dy <= radius {
+		let mut dx: i64 = -radius;
+		while dx <= radius {
+			let center: Point = Point { x: expected.x + dx as f64, y: expected.y + dy as f64 };
+
+			//=================================================================
+			// Match the 5x5 template: dark center and border, light ring
+			let mut score: usize = 0;
+			for a in -2i64..=2 {
+				for b in -2i64..=2 {
+					let x: f64 = center.x + a as f64 * step_u.x + b as f64 * step_v.x;
+					let y: f64 = center.y + a as f64 * step_u.y + b as f64 * step_v.y;
+					let expect_dark: bool = a.abs().max(b.abs()) != 1;
+					if bitmap.is_dark(x.floor() as i64, y.floor() as i64) == expect_dark {
+						score += 1;
+					}
+				}
+			}
+
+			if score > best_score {
+				best_score = score;
+				best.clear();
+			}
+			if score == best_score {
+				best.push(center);
+			}
+
+			dx += step;
+		}
+		dy += step;
+	}
+
+	if best_score < 22 || best.is_empty() {
+		return None;
+	}
+
+	return Some(Point {
+		x: best.iter().map(|point| point.x).sum::<f64>() / best.len() as f64,
+		y: best.iter().map(|point| point.y).sum::<f64>() / best.len() as f64,
+	});
+}
+
+/// Sample the modules of a QR code of the given version.
+fn sample_grid(bitmap: &Bitmap, finders: &[Point; 3], version: usize) -> Option<Vec<bool>> {
+	let size: usize = 17 + 4 * version;
+	let far: f64 = size as f64 - 3.5;
+	let [top_left, top_right, bottom_left] = *finders;
+
+	//=========================================================================
+	// Start with a parallelogram
+	let mut src: Vec<Point> = vec![
+		Point { x: 3.5, y: 3.5 },
+		Point { x: far, y: 3.5 },
+		Point { x: 3.5, y: far },
+	];
+	let mut dst: Vec<Point> = vec![top_left, top_right, bottom_left];
+	let mut transform: Transform = Transform::fit(&[src[0], src[1], src[2], Point { x: far, y: far }],
+												  &[top_left,
+													top_right,
+													bottom_left,
+													Point {
+														x: top_right.x + bottom_left.x - top_left.x,
+														y: top_right.y + bottom_left.y - top_left.y,
+													}])?;
+
+	//=========================================================================
+	// Refine it using the alignment patterns, starting with the bottom right
+	// one which covers the perspective best
+	let positions: Vec<usize> = alignment_positions(version);
+	let last: usize = positions.len().saturating_sub(1);
+	let mut alignments: Vec<Point> = Vec::new();
+	for (i, y) in positions.iter().enumerate() {
+		for (j, x) in positions.iter().enumerate() {
+			if is_finder_corner(i, j, last) {
+				continue;
+			}
+			alignments.push(Point { x: *x as f64 + 0.5, y: *y as f64 + 0.5 });
+		}
+	}
+	alignments.sort_by(|a, b| (b.x + b.y).total_cmp(&(a.x + a.y)));
+
+	for (i, alignment) in alignments.iter().enumerate() {
+		let radius: f64 = if i == 0 { (size as f64 / 8.0).max(5.0) } else { 3.0 };
+		if let Some(center) = find_alignment_pattern(bitmap, &transform, alignment.x, alignment.y, radius) {
+			src.push(*alignment);
+			dst.push(center);
+			if src.len() >= 4 {
+				transform = Transform::fit(src.as_slice(), dst.as_slice())?;
+			}
+		}
+	}
+
+	let mut grid: Vec<bool> = Vec::with_capacity(size * size);
+	for y in 0..size {
+		for x in 0..size {
+			let point: Point = transform.map(x as f64 + 0.5, y as f64 + 0.5);
+			grid.push(bitmap.is_dark(point.x.floor() as i64, point.y.floor() as i64));
+		}
+	}
+
+	return Some(grid);
+}
+
+//=============================================================================
+// Reed-Solomon error correction over GF(256)
+
+struct Galois {
+	exp: [u8; 512],
+	log: [u8; 256],
+}
+
+impl Galois {
+	fn new() -> Galois {
+		let mut exp: [u8; 512] = [0; 512];
+		let mut log: [u8; 256] = [0; 256];
+		let mut value: u16 = 1;
+		for (i, power) in exp.iter_mut().take(255).enumerate() {
+			*power = value as u8;
+			log[value as usize] = i as u8;
+			value <<= 1;
+			if value & 0x100 != 0 {
+				value ^= 0x11d;
+			}
+		}
+		for i in 255..512 {
+			exp[i] = exp[i - 255];
+		}
+
+		return Galois { exp, log };
+	}
+
+	fn mul(&self, a: u8, b: u8) -> u8 {
+		if a == 0 || b == 0 {
+			return 0;
+		}
+
+		return self.exp[self.log[a as usize] as usize + self.log[b as usize] as usize];
+	}
+
+	fn div(&self, a: u8, b: u8) -> u8 {
+		if a == 0 {
+			return 0;
+		}
+
+		return self.exp[(self.log[a as usize] as usize + 255 - self.log[b as usize] as usize) % 255];
+	}
+
+	/// Evaluate a polynomial given with ascending coefficients.
+	fn eval(&self, poly: &[u8], x: u8) -> u8 {
+		let mut result: u8 = 0;
+		for coefficient in poly.iter().rev() {
+			result = self.mul(result, x) ^ coefficient;
+		}
+
+		return result;
+	}
+}
+
+/// Syndromes of a block given with the highest coefficient first.
+fn syndromes(gf: &Galois, block: &[u8], ecc_len: usize) -> Vec<u8> {
+	return (0..ecc_len).map(|j| {
+		let mut result: u8 = 0;
+		for value in block {
+			result = gf.mul(result, gf.exp[j]) ^ value;
+		}
+		result
+	}).collect();
+}
+
+/// Correct the errors of a block in place. Returns false if the block cannot
+/// be corrected.
+fn correct_block(gf: &Galois, block: &mut [u8], ecc_len: usize) -> bool {
+	let syndrome: Vec<u8> = syndromes(gf, block, ecc_len);
+	if syndrome.iter().all(|value| *value == 0) {
+		return true;
+	}
+
+	//=========================================================================
+	// Find the error locator using Berlekamp-Massey
+	let mut locator: Vec<u8> = vec![1];
+	let mut previous: Vec<u8> = vec![1];
+	let mut errors: usize = 0;
+	let mut shift: usize = 1;
+	let mut previous_discrepancy: u8 = 1;
+	for n in 0..ecc_len {
+		let mut discrepancy: u8 = syndrome[n];
+		for i in 1..=errors.min(locator.len() - 1) {
+			discrepancy ^= gf.mul(locator[i], syndrome[n - i]);
+		}
+
+		if discrepancy == 0 {
+			shift += 1;
+			continue;
+		}
+
+		let copy: Vec<u8> = locator.clone();
+		let factor: u8 = gf.div(discrepancy, previous_discrepancy);
+		if locator.len() < previous.len() + shift {
+			locator.resize(previous.len() + shift, 0);
+		}
+		for i in 0..previous.len() {
+			locator[i + shift] ^= gf.mul(factor, previous[i]);
+		}
+
+		if 2 * errors <= n {
+			errors = n + 1 - errors;
+			previous = copy;
+			previous_discrepancy = discrepancy;
+			shift = 1;
+		} else {
+			shift += 1;
+		}
+	}
+	if 2 * errors > ecc_len {
+		return false;
+	}
+
+	//=========================================================================
+	// Find the error positions using a Chien search
+	let len: usize = block.len();
+	let positions: Vec<usize> = (0..len)
+		.filter(|k| gf.eval(&locator, gf.exp[(255 - (len - 1 - k) % 255) % 255]) == 0)
+		.collect();
+	if positions.len() != errors {
+		return false;
+	}
+
+	//=========================================================================
+	// Compute the error values using Forney's algorithm
+	let mut evaluator: Vec<u8> = vec![0; ecc_len];
+	for i in 0..ecc_len {
+		for j in 0..locator.len().min(i + 1) {
+			evaluator[i] ^= gf.mul(syndrome[i - j], locator[j]);
+		}
+	}
+	let derivative: Vec<u8> = (1..locator.len())
+		.map(|i| if i % 2 == 1 { locator[i] } else { 0 })
+		.collect();
+
+	for k in positions {
+		let power: usize = (len - 1 - k) % 255;
+		let x: u8 = gf.exp[power];
+		let x_inverse: u8 = gf.exp[(255 - power) % 255];
+		let denominator: u8 = gf.eval(&derivative, x_inverse);
+		if denominator == 0 {
+			return false;
+		}
+		block[k] ^= gf.mul(x, gf.div(gf.eval(&evaluator, x_inverse), denominator));
+	}
+
+	return syndromes(gf, block, ecc_len).iter().all(|value| *value == 0);
+}
+
+//=============================================================================
+// Decoding of the modules
+
+/// Encode the 5 bits of the format information into its 15 bit codeword.
+fn format_codeword(data: u32) -> u32 {
+	let mut remainder: u32 = data;
+	for _ in 0..10 {
+		remainder = (remainder << 1) ^ ((remainder >> 9) * 0x537);
+	}
+
+	return ((data << 10) | remainder) ^ 0x5412;
+}
+
+/// Read the format information. Returns the index of the error correction
+/// level (L, M, Q, H) and the mask.
+fn read_format(grid: &[bool], size: usize) -> Option<(usize, usize)> {
+	let module = |x: usize, y: usize| -> u32 { grid[y * size + x] as u32 };
+
+	//=========================================================================
+	// Read both copies
+	let mut first: u32 = 0;
+	for i in 0..6 {
+		first |= module(8, i) << i;
+	}
+	first |= module(8, 7) << 6;
+	first |= module(8, 8) << 7;
+	first |= module(7, 8) << 8;
+	for i in 9..15 {
+		first |= module(14 - i, 8) << i;
+	}
+
+	let mut second: u32 = 0;
+	for i in 0..8 {
+		second |= module(size - 1 - i, 8) << i;
+	}
+	for i in 8..15 {
+		second |= module(8, size - 15 + i) << i;
+	}
+
+	//=========================================================================
+	// Select the nearest valid codeword
+	let mut best: Option<(u32, u32)> = None;
+	for data in 0..32 {
+		let codeword: u32 = format_codeword(data);
+		let distance: u32 = (codeword ^ first).count_ones().min((codeword ^ second).count_ones());
+		if distance <= 3 && best.is_none_or(|(_data, best_distance)| distance < best_distance) {
+			best = Some((data, distance));
+		}
+	}
+
+	return best.map(|(data, _distance)| {
+		let ec_level: usize = match data >> 3 {
+			1 => 0,
+			0 => 1,
+			3 => 2,
+			_ => 3,
+		};
+		(ec_level, (data & 7) as usize)
+	});
+}
+
+/// Positions of the alignment patterns in each dimension.
+fn alignment_positions(version: usize) -> Vec<usize> {
+	if version == 1 {
+		return Vec::new();
+	}
+
+	let count: usize = version / 7 + 2;
+	let size: usize = 17 + 4 * version;
+	let step: usize = if version == 32 {
+		26
+	} else {
+		(version * 4 + count * 2 + 1) / (count * 2 - 2) * 2
+	};
+
+	let mut positions: Vec<usize> = (0..count - 1).map(|i| size - 7 - i * step).collect();
+	positions.push(6);
+	positions.reverse();
+
+	return positions;
+}
+
+/// Check whether an alignment pattern position collides with a finder
+/// pattern.
+fn is_finder_corner(i: usize, j: usize, last: usize) -> bool {
+	return (i == 0 && (j == 0 || j == last)) || (i == last && j == 0);
+}
+
+/// Mark the modules which do not hold data.
+fn function_modules(version: usize) -> Vec<bool> {
+	let size: usize = 17 + 4 * version;
+	let mut function: Vec<bool> = vec![false; size * size];
+
+	for y in 0..size {
+		for x in 0..size {
+			function[y * size + x] = (y < 9 && (x < 9 || x >= size - 8))
+				|| (x < 9 && y >= size - 8)
+				|| x == 6
+				|| y == 6
+				|| (version >= 7 && x >= size - 11 && x < size - 8 && y < 6)
+				|| (version >= 7 && y >= size - 11 && y < size - 8 && x < 6);
+		}
+	}
+
+	let positions: Vec<usize> = alignment_positions(version);
+	let last: usize = positions.len().saturating_sub(1);
+	for (i, cy) in positions.iter().enumerate() {
+		for (j, cx) in positions.iter().enumerate() {
+			if is_finder_corner(i, j, last) {
+				continue;
+			}
+			for y in cy - 2..=cy + 2 {
+				for x in cx - 2..=cx + 2 {
+					function[y * size + x] = true;
+				}
+			}
+		}
+	}
+
+	return function;
+}
+
+/// Check whether a mask pattern inverts a module.
+fn mask_applies(mask: usize, x: usize, y: usize) -> bool {
+	return match mask {
+		0 => (x + y).is_multiple_of(2),
+		1 => y.is_multiple_of(2),
+		2 => x.is_multiple_of(3),
+		3 => (x + y).is_multiple_of(3),
+		4 => (x / 3 + y / 2).is_multiple_of(2),
+		5 => x * y % 2 + x * y % 3 == 0,
+		6 => (x * y % 2 + x * y % 3).is_multiple_of(2),
+		_ => ((x + y) % 2 + x * y % 3).is_multiple_of(2),
+	};
+}
+
+/// Number of data modules of a version including the remainder bits.
+fn raw_data_modules(version: usize) -> usize {
+	let mut result: usize = (16 * version + 128) * version + 64;
+	if version >= 2 {
+		let count: usize = version / 7 + 2;
+		result -= (25 * count - 10) * count - 55;
+		if version >= 7 {
+			result -= 36;
+		}
+	}
+
+	return result;
+}
+
+/// Read the codewords in the zigzag order and remove the mask.
+fn read_codewords(grid: &[bool], version: usize, mask: usize) -> Vec<u8> {
+	let size: usize = 17 + 4 * version;
+	let function: Vec<bool> = function_modules(version);
+	let count: usize = raw_data_modules(version) / 8;
+
+	let mut codewords: Vec<u8> = vec![0; count];
+	let mut bit: usize = 0;
+	let mut right: i64 = size as i64 - 1;
+	while right >= 1 && bit < count * 8 {
+		if right == 6 {
+			right = 5;
+		}
+		for vertical in 0..size {
+			for j in 0..2 {
+				let x: usize = (right - j) as usize;
+				let upward: bool = (right + 1) & 2 == 0;
+				let y: usize = if upward { size - 1 - vertical } else { vertical };
+				if !function[y * size + x] && bit < count * 8 {
+					if grid[y * size + x] != mask_applies(mask, x, y) {
+						codewords[bit >> 3] |= 0x80 >> (bit & 7);
+					}
+					bit += 1;
+				}
+			}
+		}
+		right -= 2;
+	}
+
+	return codewords;
+}
+
+/// Deinterleave the codewords into blocks, correct them and return the data
+/// codewords.
+fn correct_codewords(codewords: &[u8], version: usize, ec_level: usize) -> Option<Vec<u8>> {
+	let block_count: usize = NUM_ERROR_CORRECTION_BLOCKS[ec_level][version] as usize;
+	let ecc_len: usize = ECC_CODEWORDS_PER_BLOCK[ec_level][version] as usize;
+	let short_block_count: usize = block_count - codewords.len() % block_count;
+	let short_block_len: usize = codewords.len() / block_count;
+
+	//=========================================================================
+	// Deinterleave. Short blocks skip the last data codeword of long blocks.
+	let mut blocks: Vec<Vec<u8>> = vec![Vec::with_capacity(short_block_len + 1); block_count];
+	let mut index: usize = 0;
+	for i in 0..=short_block_len {
+		for (j, block) in blocks.iter_mut().enumerate() {
+			if i == short_block_len - ecc_len && j < short_block_count {
+				continue;
+			}
+			block.push(*codewords.get(index)?);
+			index += 1;
+		}
+	}
+
+	let gf: Galois = Galois::new();
+	let mut data: Vec<u8> = Vec::new();
+	for mut block in blocks {
+		if !correct_block(&gf, block.as_mut_slice(), ecc_len) {
+			return None;
+		}
+		data.extend_from_slice(&block[..block.len() - ecc_len]);
+	}
+
+	return Some(data);
+}
+
+/// Reads bits from the data codewords.
+struct BitReader<'a> {
+	data: &'a [u8],
+	position: usize,
+}
+
+impl BitReader<'_> {
+	fn remaining(&self) -> usize {
+		return self.data.len() * 8 - self.position;
+	}
+
+	fn read(&mut self, count: usize) -> Option<u32> {
+		if count > self.remaining() {
+			return None;
+		}
+
+		let mut value: u32 = 0;
+		for _ in 0..count {
+			let bit: u8 = (self.data[self.position >> 3] >> (7 - (self.position & 7))) & 1;
+			value = (value << 1) | bit as u32;
+			self.position += 1;
+		}
+
+		return Some(value);
+	}
+}
+
+/// Parse the segments of the data codewords.
+fn parse_segments(data: &[u8], version: usize) -> Option<Vec<u8>> {
+	let size_class: usize = if version <= 9 { 0 } else if version <= 26 { 1 } else { 2 };
+	let mut reader: BitReader = BitReader { data, position: 0 };
+	let mut result: Vec<u8> = Vec::new();
+
+	while reader.remaining() >= 4 {
+		match reader.read(4)? {
+			0 => {
+				break;
+			},
+			1 => {
+				//=============================================================
+				// Numeric mode
+				let mut count: u32 = reader.read([10, 12, 14][size_class])?;
+				while count >= 3 {
+					result.extend_from_slice(format!("{:03}", reader.read(10)?).as_bytes());
+					count -= 3;
+				}
+				if count == 2 {
+					result.extend_from_slice(format!("{:02}", reader.read(7)?).as_bytes());
+				} else if count == 1 {
+					result.extend_from_slice(format!("{:01}", reader.read(4)?).as_bytes());
+				}
+			},
+			2 => {
+				//=============================================================
+				// Alphanumeric mode
+				let mut count: u32 = reader.read([9, 11, 13][size_class])?;
+				while count >= 2 {
+					let value: usize = reader.read(11)? as usize;
+					result.push(*ALPHANUMERIC_CHARSET.get(value / 45)?);
+					result.push(*ALPHANUMERIC_CHARSET.get(value % 45)?);
+					count -= 2;
+				}
+				if count == 1 {
+					result.push(*ALPHANUMERIC_CHARSET.get(reader.read(6)? as usize)?);
+				}
+			},
+			3 => {
+				//=============================================================
+				// Structured append header
+				reader.read(16)?;
+			},
+			4 => {
+				//=============================================================
+				// Byte mode
+				let count: u32 = reader.read([8, 16, 16][size_class])?;
+				for _ in 0..count {
+					result.push(reader.read(8)? as u8);
+				}
+			},
+			5 => {},
+			7 => {
+				//=============================================================
+				// Extended channel interpretation. The content is expected to
+				// be UTF-8 anyway.
+				let first: u32 = reader.read(8)?;
+				if first & 0xc0 == 0x80 {
+					reader.read(8)?;
+				} else if first & 0xe0 == 0xc0 {
+					reader.read(16)?;
+				}
+			},
+			9 => {
+				reader.read(8)?;
+			},
+			8 => {
+				//=============================================================
+				// Kanji mode is not supported, as it requires a Shift JIS
+				// table. Ciphertexts and timer URLs never contain Kanji.
+				return None;
+			},
+			_ => {
+				return None;
+			},
+		}
+	}
+
+	return Some(result);
+}
+
+/// Decode a sampled QR code of the given version.
+fn decode_grid(grid: &[bool], version: usize) -> Option<Vec<u8>> {
+	let size: usize = 17 + 4 * version;
+	let (ec_level, mask) = read_format(grid, size)?;
+	let codewords: Vec<u8> = read_codewords(grid, version, mask);
+	let data: Vec<u8> = correct_codewords(codewords.as_slice(), version, ec_level)?;
+
+	return parse_segments(data.as_slice(), version);
+}
+
+/// Locate and decode a QR code in a black and white image.
+fn decode_bitmap(bitmap: &Bitmap) -> Option<Vec<u8>> {
+	let candidates: Vec<FinderPattern> = find_finder_patterns(bitmap);
+
+	for (a, b, c) in select_triples(candidates.as_slice()) {
+		let patterns: [&FinderPattern; 3] = order_finder_patterns(&candidates[a], &candidates[b], &candidates[c]);
+		let finders: [Point; 3] = [patterns[0].center, patterns[1].center, patterns[2].center];
+		let module_size: f64 = patterns.iter().map(|pattern| pattern.module_size).sum::<f64>() / 3.0;
+
+		//=====================================================================
+		// Estimate the version from the distances of the finder patterns and
+		// try its neighbors as well. The version information takes precedence.
+		let modules: f64 = (distance(finders[0], finders[1]) + distance(finders[0], finders[2]))
+			/ 2.0 / module_size + 7.0;
+		let estimate: i64 = ((modules - 17.0) / 4.0).round() as i64;
+		let mut versions: Vec<i64> = vec![estimate, estimate - 1, estimate + 1, estimate - 2, estimate + 2];
+		if let Some(version) = read_version(bitmap, &patterns) {
+			versions.insert(0, version as i64);
+		}
+
+		for version in versions {
+			if !(1..=40).contains(&version) {
+				continue;
+			}
+
+			if let Some(grid) = sample_grid(bitmap, &finders, version as usize) {
+				if let Some(content) = decode_grid(grid.as_slice(), version as usize) {
+					return Some(content);
+				}
+			}
+		}
+	}
+
+	return None;
+}
+
+/// Check whether data is a PNG or JPEG image.
+pub fn is_image(data: &[u8]) -> bool {
+	return data.starts_with(PNG_MAGIC) || data.starts_with(JPEG_MAGIC);
+}
+
+/// Locate and decode a QR code in a PNG or JPEG image. Returns the text it
+/// contains.
+pub fn decode_image(data: &[u8]) -> Result<String, Error> {
+	let mut image: GrayImage = image::load_from_memory(data).map_err(|error| {
+		Error::Malformed(format!("unable to read the image: {}", error))
+	})?.to_luma8();
+
+	if image.width().max(image.height()) > MAX_IMAGE_DIMENSION {
+		let scale: f64 = MAX_IMAGE_DIMENSION as f64 / image.width().max(image.height()) as f64;
+		image = imageops::resize(&image,
+								 (image.width() as f64 * scale) as u32,
+								 (image.height() as f64 * scale) as u32,
+								 FilterType::Triangle);
+	}
+
+	//=========================================================================
+	// Try a global threshold first, then a local one. Both are tried with
+	// inverted colors as well.
+	for binarize in [binarize_global, binarize_local] {
+		let mut bitmap: Bitmap = binarize(&image);
+		for _inverted in 0..2 {
+			if let Some(content) = decode_bitmap(&bitmap) {
+				return String::from_utf8(content).map_err(|_error| {
+					Error::Malformed(String::from("the QR code does not contain text"))
+				});
+			}
+			bitmap.dark.iter_mut().for_each(|dark| *dark = !*dark);
+		}
+	}
+
+	return Err(Error::Malformed(String::from("no QR code has been found in the image")));
+}
+
+#[cfg(test)]
+mod tests {
+	use std::io::Cursor;
+
+	use image::{
+		imageops,
+		DynamicImage,
+		GrayImage,
+		ImageFormat,
+		Luma,
+	};
+	use qrcode::{
+		bits::Bits,
+		ec::create_error_correction_code,
+		EcLevel,
+		QrCode,
+		Version,
+	};
+
+	use super::*;
+
+	/// Error correction levels in the order of the tables.
+	const EC_LEVELS: [EcLevel; 4] = [EcLevel::L, EcLevel::M, EcLevel::Q, EcLevel::H];
+
+	/// Text filling a QR code (the alphabet of a ciphertext).
+	const TEXT: &str = "2:MjAzMC0wMS0wMVQwMDowMDowMCswMDAw:aGk=:AbCdEfGhIjKlMnOpQrStUvWxYz0123456789+/";
+
+	/// Create a QR code of a version holding as much of TEXT (repeated) as
+	/// fits in byte mode.
+	fn fill(version: i16, ec_level: EcLevel) -> (QrCode, String) {
+		let repeated: String = TEXT.repeat(40);
+		let (mut fitting, mut too_long) = (1, repeated.len());
+		while too_long - fitting > 1 {
+			let len: usize = (fitting + too_long) / 2;
+			if QrCode::with_version(&repeated[..len], Version::Normal(version), ec_level).is_ok() {
+				fitting = len;
+			} else {
+				too_long = len;
+			}
+		}
+
+		let data: String = String::from(&repeated[..fitting]);
+		return (QrCode::with_version(data.as_str(), Version::Normal(version), ec_level).unwrap(), data);
+	}
+
+	/// Render a QR code with a quiet zone using 4 pixels per module.
+	fn render(code: &QrCode) -> GrayImage {
+		return code.render::<Luma<u8>>().module_dimensions(4, 4).build();
+	}
+
+	/// Decode an image the way decode_image does after reading it as PNG.
+	fn decode(image: GrayImage) -> Result<String, Error> {
+		let mut png: Vec<u8> = Vec::new();
+		DynamicImage::ImageLuma8(image).write_to(&mut Cursor::new(&mut png), ImageFormat::Png).unwrap();
+
+		return decode_image(png.as_slice());
+	}
+
+	/// Map every pixel of an image to the pixel of the source computed by a
+	/// function. Pixels outside of the source are light.
+	fn warp(image: &GrayImage, width: u32, height: u32, source: impl Fn(f64, f64) -> (f64, f64)) -> GrayImage {
+		return GrayImage::from_fn(width, height, |x, y| {
+			let (u, v) = source(x as f64, y as f64);
+			if u < 0.0 || v < 0.0 || u >= image.width() as f64 || v >= image.height() as f64 {
+				return Luma([255]);
+			}
+			return *image.get_pixel(u as u32, v as u32);
+		});
+	}
+
+	/// Pack bit fields given as value and length into bytes.
+	fn pack(fields: &[(u32, usize)]) -> Vec<u8> {
+		let mut bytes: Vec<u8> = Vec::new();
+		let mut position: usize = 0;
+		for (value, len) in fields {
+			for i in (0..*len).rev() {
+				if position.is_multiple_of(8) {
+					bytes.push(0);
+				}
+				if (value >> i) & 1 == 1 {
+					bytes[position / 8] |= 0x80 >> (position % 8);
+				}
+				position += 1;
+			}
+		}
+
+		return bytes;
+	}
+
+	/// Encode segments using the encoder of the qrcode crate.
+	fn segments(version: i16, push: impl Fn(&mut Bits)) -> Vec<u8> {
+		let mut bits: Bits = Bits::new(Version::Normal(version));
+		push(&mut bits);
+		if bits.push_terminator(EcLevel::L).is_err() {
+			// The data exceeds the capacity of the version, which does not
+			// matter for parsing it. It ends without a terminator then.
+		}
+
+		return bits.into_bytes();
+	}
+
+	//=========================================================================
+	// Reed-Solomon error correction
+
+	#[test]
+	fn correct_block_up_to_capacity() {
+		let gf: Galois = Galois::new();
+		for (data_len, ecc_len) in [(19usize, 7usize), (16, 10), (13, 13), (9, 17), (15, 30), (118, 30)] {
+			let data: Vec<u8> = (0..data_len).map(|i| (i * 37 + 11) as u8).collect();
+			let mut original: Vec<u8> = data.clone();
+			original.extend(create_error_correction_code(data.as_slice(), ecc_len));
+
+			//=================================================================
+			// No errors, then errors spread over the block, at its start,
+			// at its end and within the error correction codewords
+			for errors in 0..=ecc_len / 2 {
+				for offset in [0, original.len() - errors, data_len.saturating_sub(errors / 2)] {
+					let mut block: Vec<u8> = original.clone();
+					for i in 0..errors {
+						let position: usize = (offset + i * 7) % block.len();
+						block[position] ^= (i as u8).wrapping_mul(29).wrapping_add(1);
+					}
+					assert!(correct_block(&gf, block.as_mut_slice(), ecc_len),
+							"{} errors of ({}, {}) at {}", errors, data_len, ecc_len, offset);
+					assert_eq!(block, original);
+				}
+			}
+
+			//=================================================================
+			// Too many errors are never corrected to the original block
+			let mut block: Vec<u8> = original.clone();
+			for byte in block.iter_mut().take(ecc_len / 2 + 1) {
+				*byte ^= 0x5a;
+			}
+			assert!(!correct_block(&gf, block.as_mut_slice(), ecc_len) || block != original);
+		}
+	}
+
+	#[test]
+	fn galois_arithmetic() {
+		let gf: Galois = Galois::new();
+		for a in 1..=255u8 {
+			assert_eq!(gf.mul(a, 1), a);
+			assert_eq!(gf.div(a, a), 1);
+			for b in [1u8, 2, 3, 29, 142, 255] {
+				assert_eq!(gf.div(gf.mul(a, b), b), a);
+			}
+		}
+		assert_eq!(gf.mul(0, 7), 0);
+		assert_eq!(gf.mul(2, 128), 0x1d);
+		assert_eq!(gf.eval(&[1, 0, 1], 2), 5);
+	}
+
+	//=========================================================================
+	// Format and version information
+
+	#[test]
+	fn format_and_version_codewords() {
+		//=====================================================================
+		// Codewords of the specification (mask 0 of each level; versions 7,
+		// 21 and 40)
+		assert_eq!(format_codeword(0b01000), 0x77c4);
+		assert_eq!(format_codeword(0b00000), 0x5412);
+		assert_eq!(format_codeword(0b11000), 0x355f);
+		assert_eq!(format_codeword(0b10000), 0x1689);
+		assert_eq!(version_codeword(7), 0x07c94);
+		assert_eq!(version_codeword(21), 0x15683);
+		assert_eq!(version_codeword(40), 0x28c69);
+	}
+
+	#[test]
+	fn read_format_with_errors() {
+		for (index, ec_level) in EC_LEVELS.iter().enumerate() {
+			let (code, _data) = fill(3, *ec_level);
+			let size: usize = code.width();
+			let mut grid: Vec<bool> = code.to_colors().iter().map(|color| *color == qrcode::Color::Dark).collect();
+			let (read_level, mask) = read_format(grid.as_slice(), size).unwrap();
+			assert_eq!(read_level, index);
+			assert!(mask < 8);
+
+			//=================================================================
+			// Three errors in each copy are corrected
+			for (x, y) in [(8, 0), (8, 3), (2, 8), (size - 1, 8), (size - 5, 8), (8, size - 2)] {
+				grid[y * size + x] = !grid[y * size + x];
+			}
+			assert_eq!(read_format(grid.as_slice(), size), Some((index, mask)));
+		}
+	}
+
+	#[test]
+	fn version_tables() {
+		assert_eq!(alignment_positions(1), Vec::<usize>::new());
+		assert_eq!(alignment_positions(2), vec![6, 18]);
+		assert_eq!(alignment_positions(7), vec![6, 22, 38]);
+		assert_eq!(alignment_positions(32), vec![6, 34, 60, 86, 112, 138]);
+		assert_eq!(alignment_positions(40), vec![6, 30, 58, 86, 114, 142, 170]);
+		assert_eq!(raw_data_modules(1), 208);
+		assert_eq!(raw_data_modules(2), 359);
+		assert_eq!(raw_data_modules(7), 1568);
+		assert_eq!(raw_data_modules(40), 29648);
+
+		//=====================================================================
+		// The modules which are not function modules hold the data
+		for version in 1..=40 {
+			let function: Vec<bool> = function_modules(version);
+			assert_eq!(function.iter().filter(|function| !**function).count(), raw_data_modules(version), "version {}", version);
+		}
+	}
+
+	//=========================================================================
+	// Segments
+
+	#[test]
+	fn parse_segment_modes() {
+		//=====================================================================
+		// Numeric, alphanumeric and byte mode in every size class of the
+		// character counts
+		for version in [1, 9, 10, 26, 27, 40] {
+			for digits in ["", "7", "42", "123", "0123456789", "99999999999"] {
+				let data: Vec<u8> = segments(version, |bits| bits.push_numeric_data(digits.as_bytes()).unwrap());
+				assert_eq!(parse_segments(data.as_slice(), version as usize).unwrap(), digits.as_bytes());
+			}
+			for text in ["A", "AB", "HELLO WORLD", "$%*+-./: 0123"] {
+				let data: Vec<u8> = segments(version, |bits| bits.push_alphanumeric_data(text.as_bytes()).unwrap());
+				assert_eq!(parse_segments(data.as_slice(), version as usize).unwrap(), text.as_bytes());
+			}
+			let bytes: Vec<u8> = (0..255).collect();
+			let data: Vec<u8> = segments(version, |bits| bits.push_byte_data(bytes.as_slice()).unwrap());
+			assert_eq!(parse_segments(data.as_slice(), version as usize).unwrap(), bytes);
+
+			//=================================================================
+			// Mixed segments
+			let data: Vec<u8> = segments(version, |bits| {
+				bits.push_byte_data(b"https://").unwrap();
+				bits.push_alphanumeric_data(b"WEBAPP.EXAMPLE").unwrap();
+				bits.push_byte_data(b"/timer.php?c=").unwrap();
+				bits.push_numeric_data(b"2").unwrap();
+			});
+			assert_eq!(parse_segments(data.as_slice(), version as usize).unwrap(), b"https://WEBAPP.EXAMPLE/timer.php?c=2");
+		}
+	}
+
+	#[test]
+	fn parse_segment_headers() {
+		//=====================================================================
+		// Extended channel interpretations of every length
+		for designator in [3, 26, 127, 128, 16383, 16384, 999999] {
+			let data: Vec<u8> = segments(1, |bits| {
+				bits.push_eci_designator(designator).unwrap();
+				bits.push_byte_data("äö".as_bytes()).unwrap();
+			});
+			assert_eq!(parse_segments(data.as_slice(), 1).unwrap(), "äö".as_bytes());
+		}
+
+		//=====================================================================
+		// FNC1 in the first and second position
+		let data: Vec<u8> = segments(1, |bits| {
+			bits.push_fnc1_first_position().unwrap();
+			bits.push_byte_data(b"01").unwrap();
+		});
+		assert_eq!(parse_segments(data.as_slice(), 1).unwrap(), b"01");
+		let data: Vec<u8> = segments(1, |bits| {
+			bits.push_fnc1_second_position(37).unwrap();
+			bits.push_byte_data(b"01").unwrap();
+		});
+		assert_eq!(parse_segments(data.as_slice(), 1).unwrap(), b"01");
+
+		//=====================================================================
+		// Structured append header
+		let data: Vec<u8> = pack(&[(3, 4), (0x1234, 16), (4, 4), (1, 8), (b'A' as u32, 8), (0, 4)]);
+		assert_eq!(parse_segments(data.as_slice(), 1).unwrap(), b"A");
+
+		//=====================================================================
+		// The terminator may be missing at the end of the data, but a
+		// segment must be complete
+		let data: Vec<u8> = pack(&[(4, 4), (1, 8), (b'A' as u32, 8)]);
+		assert_eq!(parse_segments(data.as_slice(), 1).unwrap(), b"A");
+		assert_eq!(parse_segments(&data[..2], 1), None);
+	}
+
+	#[test]
+	fn parse_segment_failures() {
+		//=====================================================================
+		// Kanji mode is not supported
+		let data: Vec<u8> = segments(1, |bits| bits.push_kanji_data(&[0x93, 0x5f, 0xe4, 0xaa]).unwrap());
+		assert_eq!(parse_segments(data.as_slice(), 1), None);
+
+		//=====================================================================
+		// Unknown modes, counts beyond the data and invalid alphanumeric
+		// values
+		assert_eq!(parse_segments(pack(&[(6, 4), (0, 12)]).as_slice(), 1), None);
+		assert_eq!(parse_segments(pack(&[(15, 4), (0, 12)]).as_slice(), 1), None);
+		assert_eq!(parse_segments(pack(&[(4, 4), (200, 8), (0, 12)]).as_slice(), 1), None);
+		assert_eq!(parse_segments(pack(&[(2, 4), (1, 9), (45, 6), (0, 5)]).as_slice(), 1), None);
+		assert_eq!(parse_segments(pack(&[(2, 4), (2, 9), (2025, 11), (0, 4)]).as_slice(), 1), None);
+	}
+
+	//=========================================================================
+	// Decoding of images
+
+	#[test]
+	fn decode_versions_and_levels() {
+		for (version, ec_level) in [(1, EcLevel::L), (1, EcLevel::H), (2, EcLevel::M), (6, EcLevel::Q),
+									(7, EcLevel::H), (10, EcLevel::L), (14, EcLevel::M), (21, EcLevel::Q),
+									(27, EcLevel::H), (32, EcLevel::L), (40, EcLevel::M)] {
+			let (code, data) = fill(version, ec_level);
+			assert_eq!(decode(render(&code)).unwrap(), data, "version {} level {:?}", version, ec_level);
+		}
+	}
+
+	#[test]
+	fn decode_rotated_and_mirrored_colors() {
+		for version in [2, 7, 25] {
+			let (code, data) = fill(version, EcLevel::M);
+			let image: GrayImage = render(&code);
+			for rotated in [imageops::rotate90(&image), imageops::rotate180(&image), imageops::rotate270(&image)] {
+				assert_eq!(decode(rotated).unwrap(), data, "version {}", version);
+			}
+
+			//=================================================================
+			// Light modules on a dark background
+			let mut inverted: GrayImage = image.clone();
+			imageops::invert(&mut inverted);
+			assert_eq!(decode(inverted).unwrap(), data, "version {}", version);
+		}
+	}
+
+	#[test]
+	fn decode_distorted() {
+		for version in [3, 8, 15] {
+			let (code, data) = fill(version, EcLevel::Q);
+			let image: GrayImage = render(&code);
+			let (width, height) = (image.width() as f64, image.height() as f64);
+
+			//=================================================================
+			// Sheared
+			let sheared: GrayImage = warp(&image, (width * 1.25) as u32, height as u32, |x, y| (x - 0.25 * y, y));
+			assert_eq!(decode(sheared).unwrap(), data, "version {}", version);
+
+			//=================================================================
+			// Rotated by an arbitrary angle
+			let angle: f64 = 0.3_f64;
+			let size: f64 = width * 1.5;
+			let rotated: GrayImage = warp(&image, size as u32, size as u32, |x, y| {
+				let (dx, dy) = (x - size / 2.0, y - size / 2.0);
+				(dx * angle.cos() - dy * angle.sin() + width / 2.0,
+				 dx * angle.sin() + dy * angle.cos() + height / 2.0)
+			});
+			assert_eq!(decode(rotated).unwrap(), data, "version {}", version);
+
+			//=================================================================
+			// Seen at an angle: the bottom is wider than the top
+			let perspective: GrayImage = warp(&image, (width * 1.25) as u32, (height * 1.25) as u32, |x, y| {
+				let scale: f64 = 1.0 + 0.2 * y / height;
+				(x / scale, y / scale)
+			});
+			assert_eq!(decode(perspective).unwrap(), data, "version {}", version);
+		}
+
+		//=====================================================================
+		// Scaled up beyond the maximum dimension
+		let (code, data) = fill(3, EcLevel::Q);
+		let scaled: GrayImage = imageops::resize(&render(&code), 2100, 2100, imageops::FilterType::Nearest);
+		assert_eq!(decode(scaled).unwrap(), data);
+	}
+
+	#[test]
+	fn decode_damaged() {
+		//=====================================================================
+		// Damaged modules are corrected up to the capacity of the level
+		let (code, data) = fill(5, EcLevel::H);
+		let mut image: GrayImage = render(&code);
+		for y in 0..4 * 5 {
+			for x in 0..4 * 6 {
+				image.put_pixel(4 * (4 + 18) + x, 4 * (4 + 14) + y, Luma([255]));
+			}
+		}
+		assert_eq!(decode(image).unwrap(), data);
+
+		let (code, _data) = fill(5, EcLevel::L);
+		let mut image: GrayImage = render(&code);
+		for y in 0..4 * 12 {
+			for x in 0..4 * 12 {
+				image.put_pixel(4 * (4 + 16) + x, 4 * (4 + 16) + y, Luma([255]));
+			}
+		}
+		assert!(decode(image).is_err());
+	}
+
+	#[test]
+	fn decode_failures() {
+		assert!(is_image(b"\x89PNG\r\n\x1a\n"));
+		assert!(is_image(b"\xff\xd8\xff\xe0"));
+		assert!(!is_image(b"2:MjAzMC0wMS0w"));
+
+		assert_eq!(decode(GrayImage::from_pixel(100, 100, Luma([255]))).unwrap_err().message(),
+				   "no QR code has been found in the image");
+		assert_eq!(decode_image(b"\x89PNG\r\n\x1a\nbroken").unwrap_err().message().split(':').next(),
+				   Some("unable to read the image"));
+
+		//=====================================================================
+		// Binary content
+		let code: QrCode = QrCode::new([0xff, 0xfe, 0x00]).unwrap();
+		assert_eq!(decode(render(&code)).unwrap_err().message(), "the QR code does not contain text");
+	}
+}
@@ -0,0 +1,334 @@
+/**
+ * This file is part of snailcrypt-cli. For more information visit
+ * https://www snailcrypt.com
+ * Copyright (C) 2022-2023  Richard Bäck <richard.baeck@icloud.com>
+ *
+ * This program is free software; you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation; either version 2 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along
+ * with this program; if not, write to the Free Software Foundation, Inc.,
+ * 51 Franklin Street, Fifth Floor, Boston, MA 02110-1301 USA.
+ */
+
+
+use std::{
+	fs::File,
+	io::{
+		Read,
+		Write,
+	},
+	rc::Rc,
+};
+
+use chrono::{
+	DateTime,
+	FixedOffset,
+	Local,
+};
+use snailcrypt::{
+	client,
+	factory,
+	util,
+};
+
+use crate::{
+	armor,
+	ciphertext,
+	error::Error,
+	payload,
+	settings,
+};
+
+/// Characters of a raw ciphertext.
+fn is_ciphertext_char(c: char) -> bool {
+	return c.is_ascii_alphanumeric() || c == '+' || c == '/' || c == '=' || c == ':';
+}
+
+/// Characters ending a URL within prose.
+fn is_url_end(c: char) -> bool {
+	return c.is_whitespace() || c == '<' || c == '>' || c == '"' || c == '\'' || c == ')' || c == ']';
+}
+
+/// Start of the annotation of a message which has not been decrypted.
+const ANNOTATION_START: &str = "[snailcrypt: ";
+
+/// Ciphertext embedded in a document.
+struct Embedded {
+	/// Byte offset of the embedded ciphertext.
+	start: usize,
+	/// Byte offset after the embedded ciphertext.
+	end: usize,
+	/// Byte offset after the annotation of a former scan following the
+	/// embedded ciphertext. It is end if there is none.
+	annotation_end: usize,
+	/// Quoting prefix of an armored message, if any.
+	block_prefix: Option<String>,
+	ciphertext: String,
+}
+
+/// Find the timer URLs and raw ciphertexts within a part of a document which
+/// does not contain armored messages.
+fn find_inline(analyzer: &Rc<dyn util::Analyzer>,
+			   client: &Rc<dyn client::Client>,
+			   text: &str,
+			   base: usize,
+			   embedded: &mut Vec<Embedded>) {
+	let mut pos: usize = 0;
+	while pos < text.len() {
+		let rest: &str = &text[pos..];
+		let at_boundary: bool = !text[..pos].ends_with(is_ciphertext_char);
+
+		//=====================================================================
+		// Timer URL
+		if rest.starts_with("https://") || rest.starts_with("http://") {
+			let mut len: usize = rest.find(is_url_end).unwrap_or(rest.len());
+			len = rest[..len].trim_end_matches(['.', ',', ';', '!', '?']).len();
+			if let Ok(ciphertext) = ciphertext::from_input(&rest[..len]) {
+				if ciphertext::lockdate(analyzer, client, ciphertext.as_str()).is_ok() {
+					embedded.push(Embedded {
+						start: base + pos,
+						end: base + pos + len,
+						annotation_end: base + pos + len,
+						block_prefix: None,
+						ciphertext,
+					});
+				}
+			}
+			pos += len;
+			continue;
+		}
+
+		//=====================================================================
+		// Raw ciphertext starting with its version
+		if at_boundary && (rest.starts_with("1:") || rest.starts_with("2:")) {
+			let mut len: usize = rest.find(|c: char| !is_ciphertext_char(c)).unwrap_or(rest.len());
+			len = rest[..len].trim_end_matches(':').len();
+			let candidate: &str = &rest[..len];
+			if ciphertext::lockdate(analyzer, client, candidate).is_ok() {
+				embedded.push(Embedded {
+					start: base + pos,
+					end: base + pos + len,
+					annotation_end: base + pos + len,
+					block_prefix: None,
+					ciphertext: String::from(candidate),
+				});
+				pos += len;
+				continue;
+			}
+		}
+
+		pos += rest.chars().next().map_or(1, char::len_utf8);
+	}
+}
+
+/// Find all embedded ciphertexts of a document in their order.
+fn find_embedded(analyzer: &Rc<dyn util::Analyzer>,
+				 client: &Rc<dyn client::Client>,
+				 document: &str)
+	-> Vec<Embedded> {
+	let mut embedded: Vec<Embedded> = Vec::new();
+	let mut pos: usize = 0;
+
+	for block in armor::find_blocks(document) {
+		find_inline(analyzer, client, &document[pos..block.start], pos, &mut embedded);
+		if let Ok(ciphertext) = armor::decode(&document[block.start..block.end]) {
+			embedded.push(Embedded {
+				start: block.start,
+				end: block.end,
+				annotation_end: block.end,
+				block_prefix: Some(block.prefix),
+				ciphertext,
+			});
+		}
+		pos = block.end;
+	}
+	find_inline(analyzer, client, &document[pos..], pos, &mut embedded);
+
+	for message in embedded.iter_mut() {
+		message.annotation_end = annotation_end(document, message.end, message.block_prefix.as_deref());
+	}
+
+	return embedded;
+}
+
+/// Get the end of the annotation following an embedded ciphertext, or the end
+/// of the ciphertext if it is not annotated. An inline message is annotated
+/// on its line, an armored message on the next line using its quoting prefix.
+fn annotation_end(document: &str, end: usize, block_prefix: Option<&str>) -> usize {
+	let rest: &str = &document[end..];
+	let offset: usize = match block_prefix {
+		Some(prefix) => {
+			let line_break: &str = if rest.starts_with("\r\n") { "\r\n" } else { "\n" };
+			if !rest.starts_with(line_break) || !rest[line_break.len()..].starts_with(prefix) {
+				return end;
+			}
+			line_break.len() + prefix.len()
+		},
+		None => {
+			if !rest.starts_with(' ') {
+				return end;
+			}
+			1
+		},
+	};
+
+	let annotation: &str = &rest[offset..];
+	if !annotation.starts_with(ANNOTATION_START) {
+		return end;
+	}
+	match annotation.find([']', '\n']) {
+		Some(len) if annotation[len..].starts_with(']') => return end + offset + len + 1,
+		_ => return end,
+	}
+}
+
+/// Annotate an embedded ciphertext which has not been decrypted.
+fn annotation(embedded: &Embedded, line_break: &str, message: &str) -> String {
+	match &embedded.block_prefix {
+		Some(prefix) => return format!("{}{}{}{}]", line_break, prefix, ANNOTATION_START, message),
+		None => return format!(" {}{}]", ANNOTATION_START, message),
+	}
+}
+
+/// Options of the scan of a document.
+pub struct ScanArg {
+	/// File the document is written to. It is created only after the document
+	/// has been read, so it may be the input file. If it is None, then the
+	/// document is written to the output descriptor.
+	pub output_filename: Option<String>,
+}
+
+/// Read a document, decrypt every embedded ciphertext (raw, armored or as
+/// timer URL) whose lock date has passed and write the document with these
+/// replaced by their plaintexts. Messages which are still locked or which
+/// failed are kept and annotated with their lock date or the failure. The
+/// first failure is returned after the document has been written.
+pub fn scan(args: &ScanArg,
+			mut in_descriptor: Box<dyn Read>,
+			mut out_descriptor: Box<dyn Write>)
+	-> Result<(), Error> {
+	let mut document: String = String::new();
+	in_descriptor.read_to_string(&mut document)?;
+
+	//=========================================================================
+	// Setup client object
+	let analyzer_factory: factory::AnalyzerFactory = 
+		factory::AnalyzerFactory::new();
+	let analyzer = analyzer_factory.create();
+	
+	let config = settings::client_config();
+	
+	let client_factory: factory::ClientFactory = 
+		factory::ClientFactory::new(Rc::clone(&analyzer),
+									Rc::clone(&config));
+	let client = client_factory.create();
+	
+	//=========================================================================
+	// Replace the embedded ciphertexts. The annotation of a former scan is
+	// replaced as well.
+	let mut result: String = String::new();
+	let mut pos: usize = 0;
+	let mut decrypted_count: usize = 0;
+	let mut locked_count: usize = 0;
+	let mut failed_count: usize = 0;
+	let mut failure: Option<Error> = None;
+	for embedded in find_embedded(&analyzer, &client, document.as_str()) {
+		result.push_str(&document[pos..embedded.start]);
+		pos = embedded.annotation_end;
+		let original: &str = &document[embedded.start..embedded.end];
+		let line_break: &str = if document[embedded.end..].starts_with("\r\n") { "\r\n" } else { "\n" };
+
+		//=====================================================================
+		// Only contact the server for messages whose lock date has passed
+		let decrypted: Result<String, Error>;
+		let lockdate: DateTime<FixedOffset> = ciphertext::lockdate(&analyzer, &client, embedded.ciphertext.as_str())?;
+		if lockdate > Local::now() {
+			decrypted = Err(Error::Locked(format!("the message is locked until {}",
+												  lockdate.format(client.get_datetime_format()))));
+		} else {
+			decrypted = ciphertext::decrypt(&analyzer, &client, embedded.ciphertext.as_str())
+				.map(|success| success.plaintext)
+				.map_err(|failure| failure.error);
+		}
+		
+		let plaintext: String = match decrypted {
+			Ok(plaintext) => plaintext,
+			Err(error) => {
+				//=============================================================
+				// Keep the message and annotate it. Other messages are still
+				// decrypted if one of them fails.
+				match error {
+					Error::Locked(message) => {
+						result.push_str(original);
+						result.push_str(annotation(&embedded, line_break, message.as_str()).as_str());
+						locked_count += 1;
+					},
+					Error::Malformed(_) => {
+						result.push_str(&document[embedded.start..embedded.annotation_end]);
+					},
+					error => {
+						result.push_str(original);
+						result.push_str(annotation(&embedded, line_break, error.message()).as_str());
+						failed_count += 1;
+						failure = failure.or(Some(error));
+					},
+				}
+				continue;
+			},
+		};
+
+		//=====================================================================
+		// Binary data cannot be embedded into a document
+		let plaintext: String = match String::from_utf8(payload::decode(plaintext)?) {
+			Ok(plaintext) => plaintext,
+			Err(_error) => {
+				result.push_str(original);
+				result.push_str(annotation(&embedded, line_break, "the message contains binary data").as_str());
+				continue;
+			},
+		};
+
+		match &embedded.block_prefix {
+			Some(prefix) => {
+				let lines: Vec<String> = plaintext.trim_end_matches(['\r', '\n'])
+					.lines()
+					.map(|line| format!("{}{}", prefix, line))
+					.collect();
+				result.push_str(lines.join(line_break).as_str());
+			},
+			None => result.push_str(plaintext.as_str()),
+		}
+		decrypted_count += 1;
+	}
+	result.push_str(&document[pos..]);
+
+	if let Some(output_filename) = &args.output_filename {
+		out_descriptor = Box::new(File::create(output_filename).map_err(|error| {
+			Error::Io(format!("unable to create the output file \"{}\": {}", output_filename, error))
+		})?);
+	}
+	out_descriptor.write_all(result.as_bytes())?;
+	if failed_count > 0 {
+		eprintln!("Decrypted {} message(s), {} message(s) still locked, {} message(s) failed.",
+				  decrypted_count, locked_count, failed_count);
+	} else {
+		eprintln!("Decrypted {} message(s), {} message(s) still locked.", decrypted_count, locked_count);
+	}
+
+	//=========================================================================
+	// The document is written even if messages failed, but the failure is
+	// reported
+	if let Some(error) = failure {
+		return Err(error);
+	}
+
+	return Ok(());
+}
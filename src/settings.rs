@@ -0,0 +1,406 @@
+/**
+ * This file is part of snailcrypt-cli. For more information visit
+ * https://www snailcrypt.com
+ * Copyright (C) 2022-2023  Richard Bäck <richard.baeck@icloud.com>
+ *
+ * This program is free software; you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation; either version 2 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along
+ * with this program; if not, write to the Free Software Foundation, Inc.,
+ * 51 Franklin Street, Fifth Floor, Boston, MA 02110-1301 USA.
+ */
+
+
+use std::{
+	env,
+	fmt,
+	fs,
+	io::{
+		self,
+		Write,
+	},
+	path::PathBuf,
+	rc::Rc,
+	sync::OnceLock,
+	time,
+};
+
+use chrono_tz::Tz;
+use serde_json::{
+	json,
+	Map,
+	Value,
+};
+use snailcrypt::config;
+use url::Url;
+
+use crate::{
+	error::Error,
+	lockdate,
+	table,
+};
+
+/// URL of the API of the public snailcrypt server.
+pub const DEFAULT_SERVER: &str = "https://api.snailcrypt.com";
+
+/// Name of the configuration file within the configuration directories.
+const CONFIG_FILENAME: &str = "config.toml";
+
+/// The effective settings, loaded once on startup.
+static SETTINGS: OnceLock<Settings> = OnceLock::new();
+
+/// A setting together with the environment variable overriding it and its
+/// default value.
+struct Key {
+	name: &'static str,
+	env: &'static str,
+	default: &'static str,
+}
+
+/// All known settings. Settings of a section of the configuration file are
+/// named "section.setting".
+const KEYS: [Key; 11] = [
+	Key { name: "server",          env: "SNAILCRYPT_SERVER",          default: DEFAULT_SERVER },
+	Key { name: "timezone",        env: "SNAILCRYPT_TIMEZONE",        default: "" },
+	Key { name: "output.armor",    env: "SNAILCRYPT_ARMOR",           default: "false" },
+	Key { name: "output.json",     env: "SNAILCRYPT_JSON",            default: "false" },
+	Key { name: "url.generate",    env: "SNAILCRYPT_URL",             default: "false" },
+	Key { name: "url.base",        env: "SNAILCRYPT_URL_BASE",        default: "" },
+	Key { name: "url.max_length",  env: "SNAILCRYPT_URL_MAX_LENGTH",  default: "8000" },
+	Key { name: "ledger.enabled",  env: "SNAILCRYPT_LEDGER",          default: "false" },
+	Key { name: "retry.initial",   env: "SNAILCRYPT_RETRY_INITIAL",   default: "1s" },
+	Key { name: "retry.max",       env: "SNAILCRYPT_RETRY_MAX",       default: "1m" },
+	Key { name: "retry.watch_max", env: "SNAILCRYPT_WATCH_RETRY_MAX", default: "10m" },
+];
+
+/// Where the value of a setting comes from.
+#[derive(Clone, PartialEq)]
+pub enum Source {
+	Default,
+	File(PathBuf),
+	Environment(&'static str),
+	Option(&'static str),
+}
+
+/// A setting given by an option of the command line.
+pub struct Flag {
+	pub name: &'static str,
+	/// Value of the setting, or None if the option is not present.
+	pub value: Option<String>,
+	/// Name of the option, e.g. "--armor".
+	pub option: &'static str,
+}
+
+/// Options of the config operation.
+pub struct ConfigArg {
+	/// Only show is available.
+	pub command: String,
+	pub json: bool,
+}
+
+/// The effective settings. Configuration files are applied in the order
+/// system, user, then the environment variables and the options of the
+/// command line.
+pub struct Settings {
+	/// The value and the source of every setting of KEYS.
+	values: Vec<(String, Source)>,
+	/// URL of the API of the snailcrypt server.
+	pub server: String,
+	/// Time zone of lock dates without one. None means the local time zone.
+	pub timezone: Option<Tz>,
+	pub armor: bool,
+	pub json: bool,
+	pub generate_url: bool,
+	pub url_base: Option<String>,
+	pub url_max_length: usize,
+	/// Whether encrypted messages are recorded in the ledger.
+	pub ledger: bool,
+	/// Delay before the first retry of --wait and watch.
+	pub retry_initial: time::Duration,
+	/// Maximum delay between two retries of --wait.
+	pub retry_max: time::Duration,
+	/// Maximum delay between two retries of watch.
+	pub watch_retry_max: time::Duration,
+}
+
+/// Configuration of the snailcrypt client using the configured server.
+struct ClientConfig {
+	api_url: String,
+}
+
+impl fmt::Display for Source {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			Source::Default => write!(f, "default"),
+			Source::File(path) => write!(f, "{}", path.display()),
+			Source::Environment(name) => write!(f, "{}", name),
+			Source::Option(name) => write!(f, "{}", name),
+		}
+	}
+}
+
+impl config::Config for ClientConfig {
+	fn get_api_url(&self) -> &str {
+		return self.api_url.as_str();
+	}
+}
+
+impl Settings {
+	/// Get the source of a setting.
+	pub fn source(&self, name: &str) -> Source {
+		return KEYS.iter()
+			.position(|key| key.name == name)
+			.map(|index| self.values[index].1.clone())
+			.unwrap_or(Source::Default);
+	}
+}
+
+/// Get the system wide configuration file.
+fn system_path() -> Option<PathBuf> {
+	if cfg!(windows) {
+		return env::var_os("PROGRAMDATA")
+			.filter(|value| !value.is_empty())
+			.map(|dir| PathBuf::from(dir).join("snailcrypt").join(CONFIG_FILENAME));
+	}
+
+	return Some(PathBuf::from("/etc/snailcrypt").join(CONFIG_FILENAME));
+}
+
+/// Get the configuration file of the user.
+fn user_path() -> Option<PathBuf> {
+	let non_empty = |name: &str| -> Option<PathBuf> {
+		return env::var_os(name)
+			.filter(|value| !value.is_empty())
+			.map(PathBuf::from);
+	};
+
+	let config_dir: Option<PathBuf>;
+	if cfg!(windows) {
+		config_dir = non_empty("APPDATA");
+	} else {
+		config_dir = non_empty("XDG_CONFIG_HOME")
+			.or_else(|| non_empty("HOME").map(|home| home.join(".config")));
+	}
+
+	return config_dir.map(|config_dir| config_dir.join("snailcrypt").join(CONFIG_FILENAME));
+}
+
+/// Apply a configuration file to the values of the settings. A missing file is
+/// ignored.
+fn apply_file(values: &mut [(String, Source)], path: PathBuf) -> Result<(), Error> {
+	let content: String = match fs::read_to_string(&path) {
+		Ok(content) => content,
+		Err(error) if error.kind() == io::ErrorKind::NotFound => {
+			return Ok(());
+		},
+		Err(error) => {
+			return Err(Error::Io(format!("unable to read the configuration file \"{}\": {}", path.display(), error)));
+		},
+	};
+	let table: toml::Table = content.parse::<toml::Table>().map_err(|error| {
+		Error::Parse(format!("unable to parse the configuration file \"{}\": {}", path.display(), error.message()))
+	})?;
+
+	//=========================================================================
+	// Flatten the sections of the file
+	let mut entries: Vec<(String, toml::Value)> = Vec::new();
+	for (name, value) in table {
+		match value {
+			toml::Value::Table(section) => {
+				for (section_name, section_value) in section {
+					entries.push((format!("{}.{}", name, section_name), section_value));
+				}
+			},
+			value => {
+				entries.push((name, value));
+			},
+		}
+	}
+
+	for (name, value) in entries {
+		let index: usize = KEYS.iter().position(|key| key.name == name).ok_or_else(|| {
+			Error::Parse(format!("unknown setting \"{}\" in the configuration file \"{}\"", name, path.display()))
+		})?;
+		let value_str: String = match value {
+			toml::Value::String(value) => value,
+			toml::Value::Boolean(value) => value.to_string(),
+			toml::Value::Integer(value) => value.to_string(),
+			_ => {
+				return Err(Error::Parse(format!("the setting \"{}\" in the configuration file \"{}\" must be a string, a boolean or an integer",
+												name, path.display())));
+			},
+		};
+		values[index] = (value_str, Source::File(path.clone()));
+	}
+
+	return Ok(());
+}
+
+/// Parse a boolean setting.
+fn parse_bool(value: &str) -> Result<bool, String> {
+	match value.trim().to_lowercase().as_str() {
+		"1" | "true" | "yes" | "on" => Ok(true),
+		"" | "0" | "false" | "no" | "off" => Ok(false),
+		_ => Err(String::from("expected true or false")),
+	}
+}
+
+/// Parse a duration setting.
+fn parse_duration(value: &str) -> Result<time::Duration, String> {
+	return lockdate::parse_duration(value)?
+		.to_std()
+		.map_err(|error| error.to_string());
+}
+
+/// Parse the URL of the API of a snailcrypt server. The URL is normalized, so
+/// it identifies the server.
+pub fn parse_server(server_str: &str) -> Result<String, String> {
+	let server: Url = Url::parse(server_str.trim()).map_err(|error| error.to_string())?;
+	if server.scheme() != "http" && server.scheme() != "https" {
+		return Err(String::from("the URL must use http or https"));
+	}
+	if server.host_str().is_none_or(str::is_empty) {
+		return Err(String::from("the URL must name a host"));
+	}
+	if server.query().is_some() || server.fragment().is_some() {
+		return Err(String::from("the URL must not contain a query or a fragment"));
+	}
+
+	return Ok(String::from(server.as_str().trim_end_matches('/')));
+}
+
+/// Parse the values of the settings.
+fn parse(values: Vec<(String, Source)>) -> Result<Settings, Error> {
+	let get = |name: &str| -> &str {
+		let index: usize = KEYS.iter().position(|key| key.name == name).unwrap_or_default();
+		return values[index].0.trim();
+	};
+	let to_error = |name: &str, error: String| -> Error {
+		let index: usize = KEYS.iter().position(|key| key.name == name).unwrap_or_default();
+		return Error::Parse(format!("invalid value \"{}\" of the setting {} from {}: {}",
+									values[index].0, name, values[index].1, error));
+	};
+
+	let server: String = parse_server(get("server")).map_err(|error| to_error("server", error))?;
+
+	let timezone: Option<Tz> = match get("timezone") {
+		"" | "local" => None,
+		tz_name => Some(tz_name.parse::<Tz>().map_err(|_error| {
+			to_error("timezone", String::from("unknown time zone"))
+		})?),
+	};
+
+	let url_max_length: usize = get("url.max_length").parse::<usize>()
+		.ok()
+		.filter(|url_max_length| *url_max_length > 0)
+		.ok_or_else(|| to_error("url.max_length", String::from("expected a positive number")))?;
+
+	return Ok(Settings {
+		server,
+		timezone,
+		armor: parse_bool(get("output.armor")).map_err(|error| to_error("output.armor", error))?,
+		json: parse_bool(get("output.json")).map_err(|error| to_error("output.json", error))?,
+		generate_url: parse_bool(get("url.generate")).map_err(|error| to_error("url.generate", error))?,
+		url_base: Some(String::from(get("url.base"))).filter(|url_base| !url_base.is_empty()),
+		url_max_length,
+		ledger: parse_bool(get("ledger.enabled")).map_err(|error| to_error("ledger.enabled", error))?,
+		retry_initial: parse_duration(get("retry.initial")).map_err(|error| to_error("retry.initial", error))?,
+		retry_max: parse_duration(get("retry.max")).map_err(|error| to_error("retry.max", error))?,
+		watch_retry_max: parse_duration(get("retry.watch_max")).map_err(|error| to_error("retry.watch_max", error))?,
+		values,
+	});
+}
+
+/// Load the settings from the system configuration file, the configuration
+/// file of the user, the environment and finally the options of the command
+/// line. Each one overrides the former.
+pub fn load(flags: &[Flag]) -> Result<Settings, Error> {
+	let mut values: Vec<(String, Source)> = KEYS.iter()
+		.map(|key| (String::from(key.default), Source::Default))
+		.collect();
+
+	for path in [system_path(), user_path()].into_iter().flatten() {
+		apply_file(values.as_mut_slice(), path)?;
+	}
+
+	for (index, key) in KEYS.iter().enumerate() {
+		if let Ok(value) = env::var(key.env) {
+			values[index] = (value, Source::Environment(key.env));
+		}
+	}
+
+	for flag in flags {
+		if let (Some(index), Some(value)) = (KEYS.iter().position(|key| key.name == flag.name), &flag.value) {
+			values[index] = (value.clone(), Source::Option(flag.option));
+		}
+	}
+
+	return parse(values);
+}
+
+/// Make the settings available to the whole program.
+pub fn init(settings: Settings) {
+	let _ = SETTINGS.set(settings);
+}
+
+/// Get the effective settings. Defaults are used if the settings have not been
+/// loaded.
+pub fn get() -> &'static Settings {
+	return SETTINGS.get_or_init(|| {
+		parse(KEYS.iter()
+			  .map(|key| (String::from(key.default), Source::Default))
+			  .collect())
+			.unwrap_or_else(|error| {
+				panic!("Error: invalid default settings: {}", error);
+			})
+	});
+}
+
+/// Create the configuration of the snailcrypt client.
+pub fn client_config() -> Rc<dyn config::Config> {
+	return Rc::new(ClientConfig {
+		api_url: get().server.clone(),
+	});
+}
+
+/// Perform the config operation, which shows the effective settings and their
+/// sources.
+pub fn config(args: &ConfigArg,
+			  mut out_descriptor: Box<dyn Write>)
+	-> Result<(), Error> {
+	if args.command != "show" {
+		return Err(Error::Argument(format!("unknown config command \"{}\" (expected show)", args.command)));
+	}
+
+	let settings: &Settings = get();
+	if args.json == true {
+		let mut report: Map<String, Value> = Map::new();
+		for (key, (value, source)) in KEYS.iter().zip(settings.values.iter()) {
+			report.insert(String::from(key.name), json!({
+				"value": value,
+				"source": source.to_string(),
+			}));
+		}
+		writeln!(out_descriptor, "{}", Value::Object(report))?;
+	} else {
+		let rows: Vec<Vec<String>> = KEYS.iter()
+			.zip(settings.values.iter())
+			.map(|(key, (value, source))| vec![
+				String::from(key.name),
+				value.clone(),
+				source.to_string(),
+			])
+			.collect();
+		table::write(out_descriptor.as_mut(), &["SETTING", "VALUE", "SOURCE"], rows.as_slice())?;
+	}
+
+	return Ok(());
+}
@@ -0,0 +1,53 @@
+/**
+ * This file is part of snailcrypt-cli. For more information visit
+ * https://www snailcrypt.com
+ * Copyright (C) 2022-2023  Richard Bäck <richard.baeck@icloud.com>
+ *
+ * This program is free software; you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation; either version 2 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along
+ * with this program; if not, write to the Free Software Foundation, Inc.,
+ * 51 Franklin Street, Fifth Floor, Boston, MA 02110-1301 USA.
+ */
+
+
+use std::io::Write;
+
+use crate::error::Error;
+
+/// Write rows as table whose columns are aligned using spaces. The last
+/// column is not padded.
+pub fn write(out_descriptor: &mut dyn Write,
+			 header: &[&str],
+			 rows: &[Vec<String>])
+	-> Result<(), Error> {
+	let mut widths: Vec<usize> = header.iter().map(|cell| cell.chars().count()).collect();
+	for row in rows {
+		for (width, cell) in widths.iter_mut().zip(row.iter()) {
+			*width = (*width).max(cell.chars().count());
+		}
+	}
+
+	let header: Vec<String> = header.iter().map(|cell| String::from(*cell)).collect();
+	for row in std::iter::once(&header).chain(rows.iter()) {
+		let mut line: String = String::new();
+		for (index, cell) in row.iter().enumerate() {
+			if index + 1 < row.len() {
+				line.push_str(format!("{:<width$}  ", cell, width = widths[index]).as_str());
+			} else {
+				line.push_str(cell.as_str());
+			}
+		}
+		writeln!(out_descriptor, "{}", line.trim_end())?;
+	}
+
+	return Ok(());
+}
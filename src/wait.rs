@@ -0,0 +1,197 @@
+/**
+ * This file is part of snailcrypt-cli. For more information visit
+ * https://www snailcrypt.com
+ * Copyright (C) 2022-2023  Richard Bäck <richard.baeck@icloud.com>
+ *
+ * This program is free software; you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation; either version 2 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along
+ * with this program; if not, write to the Free Software Foundation, Inc.,
+ * 51 Franklin Street, Fifth Floor, Boston, MA 02110-1301 USA.
+ */
+
+
+use std::{
+	io::{
+		stderr,
+		IsTerminal,
+		Write,
+	},
+	rc::Rc,
+	sync::atomic::{
+		AtomicBool,
+		Ordering,
+	},
+	thread,
+	time,
+};
+
+use chrono::{
+	DateTime,
+	FixedOffset,
+	Local,
+};
+use snailcrypt::{
+	client,
+	util,
+};
+
+use crate::{
+	ciphertext,
+	error::Error,
+	lockdate,
+	settings,
+};
+
+/// Set by the SIGINT handler.
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+/// Interval of the checks for SIGINT while sleeping.
+const SLEEP_SLICE: time::Duration = time::Duration::from_millis(100);
+
+/// Number of consecutive errors of the server which are retried. A server
+/// which keeps rejecting a message will not accept it later on.
+const SERVER_ERROR_MAX_RETRIES: usize = 3;
+
+/// Install the SIGINT handler which stops any waiting.
+pub fn install_interrupt_handler() -> Result<(), Error> {
+	return ctrlc::set_handler(|| {
+		INTERRUPTED.store(true, Ordering::SeqCst);
+	}).map_err(|error| {
+		Error::Io(format!("unable to install the SIGINT handler: {}", error))
+	});
+}
+
+/// Check whether SIGINT has been received.
+pub fn interrupted() -> bool {
+	return INTERRUPTED.load(Ordering::SeqCst);
+}
+
+/// Sleep for a duration, but return early with an error on SIGINT.
+pub fn sleep(duration: time::Duration) -> Result<(), Error> {
+	let deadline: time::Instant = time::Instant::now() + duration;
+
+	loop {
+		if interrupted() {
+			return Err(Error::Interrupted(String::from("interrupted while waiting")));
+		}
+
+		let now: time::Instant = time::Instant::now();
+		if now >= deadline {
+			return Ok(());
+		}
+		thread::sleep(SLEEP_SLICE.min(deadline - now));
+	}
+}
+
+/// Print a status line on stderr, but only if stderr is a terminal. The line
+/// is overwritten by the next status line.
+fn print_status(status: &str) {
+	if stderr().is_terminal() {
+		eprint!("\r\x1b[K{}", status);
+		let _ = stderr().flush();
+	}
+}
+
+/// Parse a maximum waiting time (e.g. "2h", "1d 12h" or "90" for 90 seconds)
+/// into the deadline it implies.
+pub fn parse_max_wait(max_wait_str: &str) -> Result<DateTime<FixedOffset>, Error> {
+	let duration: chrono::Duration = lockdate::parse_duration(max_wait_str).map_err(|error| {
+		Error::Parse(format!("unable to parse the maximum waiting time \"{}\": {}", max_wait_str, error))
+	})?;
+
+	return Ok(Local::now().fixed_offset() + duration);
+}
+
+/// Wait until the lock date of a ciphertext has passed and decrypt it as soon
+/// as the server releases the key. Failures to reach the server and messages
+/// which are still locked are retried with an exponential backoff until the
+/// deadline (if any) has passed. Errors of the server are retried a few times
+/// in a row only. The backoff is given by the settings retry.initial and
+/// retry.max.
+pub fn decrypt(analyzer: &Rc<dyn util::Analyzer>,
+			   client: &Rc<dyn client::Client>,
+			   ciphertext: &str,
+			   deadline: Option<DateTime<FixedOffset>>)
+	-> Result<client::ClientDecryptResultSuccess, Error> {
+	let lockdate: DateTime<FixedOffset> = ciphertext::lockdate(analyzer, client, ciphertext)?;
+
+	//=========================================================================
+	// Fail early if the lock date is beyond the deadline
+	if let Some(deadline) = deadline {
+		if lockdate > deadline {
+			return Err(Error::Locked(format!("the message is locked until {}, which is beyond the maximum waiting time",
+											 lockdate.format(client.get_datetime_format()))));
+		}
+	}
+
+	//=========================================================================
+	// Wait for the lock date
+	loop {
+		let remaining: chrono::Duration = lockdate.signed_duration_since(Local::now());
+		if remaining <= chrono::Duration::zero() {
+			break;
+		}
+
+		print_status(format!("Unlocks in {}",
+							 lockdate::format_duration(remaining.num_seconds().max(1))).as_str());
+		if let Err(error) = sleep(remaining.to_std().unwrap_or_default().min(time::Duration::from_secs(1))) {
+			print_status("");
+			return Err(error);
+		}
+	}
+
+	//=========================================================================
+	// Retry the decryption until the server releases the key
+	let mut backoff: time::Duration = settings::get().retry_initial;
+	let mut server_error_count: usize = 0;
+	loop {
+		print_status("Waiting for the server to release the key");
+
+		let error: Error = match ciphertext::decrypt(analyzer, client, ciphertext) {
+			Ok(success) => {
+				print_status("");
+				return Ok(success);
+			},
+			Err(failure) => failure.error,
+		};
+
+		match &error {
+			Error::Server(_) if server_error_count >= SERVER_ERROR_MAX_RETRIES => {
+				print_status("");
+				return Err(error);
+			},
+			Error::Locked(_) | Error::Network(_) | Error::Server(_) => {
+				if let Error::Server(_) = error {
+					server_error_count += 1;
+				} else {
+					server_error_count = 0;
+				}
+				if let Some(deadline) = deadline {
+					if Local::now().fixed_offset() + chrono::Duration::from_std(backoff).unwrap_or_default() > deadline {
+						print_status("");
+						return Err(error);
+					}
+				}
+			},
+			_ => {
+				print_status("");
+				return Err(error);
+			},
+		}
+
+		if let Err(error) = sleep(backoff) {
+			print_status("");
+			return Err(error);
+		}
+		backoff = (backoff * 2).min(settings::get().retry_max);
+	}
+}
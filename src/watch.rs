@@ -0,0 +1,287 @@
+/**
+ * This file is part of snailcrypt-cli. For more information visit
+ * https://www snailcrypt.com
+ * Copyright (C) 2022-2023  Richard Bäck <richard.baeck@icloud.com>
+ *
+ * This program is free software; you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation; either version 2 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along
+ * with this program; if not, write to the Free Software Foundation, Inc.,
+ * 51 Franklin Street, Fifth Floor, Boston, MA 02110-1301 USA.
+ */
+
+
+use std::{
+	collections::HashMap,
+	fs::{
+		self,
+		File,
+	},
+	path::{
+		Path,
+		PathBuf,
+	},
+	rc::Rc,
+	time::{
+		self,
+		SystemTime,
+	},
+};
+
+use chrono::{
+	DateTime,
+	FixedOffset,
+	Local,
+};
+use snailcrypt::{
+	client,
+	factory,
+	util,
+};
+
+use crate::{
+	ciphertext,
+	directory,
+	error::Error,
+	hook::{
+		Hook,
+		Unlocked,
+	},
+	settings,
+	wait,
+};
+
+/// Interval of the rescans of the watched directory.
+const RESCAN_INTERVAL: time::Duration = time::Duration::from_secs(5);
+
+/// Options of the watch operation.
+pub struct WatchArg {
+	/// Directory which is watched for encrypted files.
+	pub input_dir: PathBuf,
+	/// Directory the plaintexts are written to. If it is None, then every
+	/// plaintext is written next to its encrypted file.
+	pub output_dir: Option<PathBuf>,
+	pub on_unlock: Option<String>,
+	pub on_unlock_timeout_str: Option<String>,
+}
+
+/// State of a file of the watched directory.
+enum State {
+	/// The file waits for its lock date or for the server to release its key.
+	Pending {
+		lockdate: DateTime<FixedOffset>,
+		hint: String,
+		next_attempt: DateTime<FixedOffset>,
+		backoff: time::Duration,
+	},
+	/// The file has been decrypted, is not an encrypted message or failed for
+	/// a reason which is not worth a retry. It is looked at again once it has
+	/// been modified.
+	Settled,
+}
+
+/// A file of the watched directory.
+struct Watched {
+	modified: SystemTime,
+	state: State,
+}
+
+/// Print a line of the log on stderr.
+fn log(client: &Rc<dyn client::Client>, message: String) {
+	eprintln!("[{}] {}", Local::now().format(client.get_datetime_format()), message);
+}
+
+/// Get the time of the last modification of a file.
+fn modified(file: &Path) -> SystemTime {
+	return fs::metadata(file)
+		.and_then(|metadata| metadata.modified())
+		.unwrap_or(SystemTime::UNIX_EPOCH);
+}
+
+/// Read the lock date of a file which has been added or modified. Files
+/// whose plaintext exists already have been released before a restart.
+fn track(analyzer: &Rc<dyn util::Analyzer>,
+		 client: &Rc<dyn client::Client>,
+		 args: &WatchArg,
+		 file: &Path)
+	-> State {
+	let metadata: Result<(DateTime<FixedOffset>, String), Error> = File::open(file)
+		.map_err(Error::from)
+		.and_then(|in_descriptor| ciphertext::read(Box::new(in_descriptor)))
+		.and_then(|ciphertext::DecryptInput { ciphertext, .. }| {
+			let lockdate: DateTime<FixedOffset> = ciphertext::lockdate(analyzer, client, ciphertext.as_str())?;
+			let hint: String = ciphertext::hint(analyzer, ciphertext.as_str())?;
+			return Ok((lockdate, hint));
+		});
+
+	match metadata {
+		Ok((lockdate, hint)) => {
+			if directory::output_path(file, &args.output_dir).exists() {
+				return State::Settled;
+			}
+
+			log(client, format!("Found {}, which unlocks at {}",
+								file.display(), lockdate.format(client.get_datetime_format())));
+			return State::Pending {
+				lockdate,
+				hint,
+				next_attempt: lockdate,
+				backoff: settings::get().retry_initial,
+			};
+		},
+		Err(Error::Malformed(_)) | Err(Error::Parse(_)) => {
+			return State::Settled;
+		},
+		Err(error) => {
+			log(client, format!("Unable to read {}: {}", file.display(), error));
+			return State::Settled;
+		},
+	}
+}
+
+/// Pass the plaintext of a released file to the hook.
+fn run_hook(client: &Rc<dyn client::Client>,
+			on_unlock: &Hook,
+			file: &Path,
+			output: &Path,
+			lockdate: DateTime<FixedOffset>,
+			hint: String)
+	-> Result<(), Error> {
+	return on_unlock.run(&Unlocked {
+							 lockdate: lockdate.format(client.get_datetime_format()).to_string(),
+							 hint,
+							 file: Some(file),
+							 output: Some(output),
+						 },
+						 Box::new(File::open(output)?));
+}
+
+/// Try to decrypt a file whose lock date has passed and run the hook (if
+/// any) on its plaintext. The next state of the file is returned.
+fn release(client: &Rc<dyn client::Client>,
+		   args: &WatchArg,
+		   on_unlock: &Option<Hook>,
+		   file: &Path,
+		   lockdate: DateTime<FixedOffset>,
+		   hint: String,
+		   backoff: time::Duration)
+	-> State {
+	let output: PathBuf = directory::output_path(file, &args.output_dir);
+	match directory::decrypt_file(file, output.as_path()) {
+		Ok(()) => {
+			log(client, format!("Released {} to {}", file.display(), output.display()));
+			if let Some(on_unlock) = on_unlock {
+				if let Err(error) = run_hook(client, on_unlock, file, output.as_path(), lockdate, hint) {
+					log(client, format!("Hook of {} failed: {}", file.display(), error));
+				}
+			}
+			return State::Settled;
+		},
+		Err(error @ Error::Locked(_))
+		| Err(error @ Error::Network(_))
+		| Err(error @ Error::Server(_)) => {
+			log(client, format!("Retrying {} in {}: {}",
+								file.display(),
+								crate::lockdate::format_duration(backoff.as_secs() as i64),
+								error));
+			return State::Pending {
+				lockdate,
+				hint,
+				next_attempt: Local::now().fixed_offset()
+					+ chrono::Duration::from_std(backoff).unwrap_or_default(),
+				backoff: (backoff * 2).min(settings::get().watch_retry_max),
+			};
+		},
+		Err(error) => {
+			log(client, format!("Unable to decrypt {}: {}", file.display(), error));
+			return State::Settled;
+		},
+	}
+}
+
+/// Watch a directory for encrypted files and decrypt each of them as soon as
+/// its lock date has passed and the server released its key. The directory
+/// is rescanned regularly, so files may be added at any time. Files whose
+/// plaintext exists already are left alone, so watching survives a restart.
+/// Watching only ends on SIGINT. A failing hook is logged, but does not stop
+/// watching.
+pub fn watch(args: &WatchArg) -> Result<(), Error> {
+	if args.input_dir.is_dir() == false {
+		return Err(Error::Argument(format!("the watched path \"{}\" is not a directory",
+										   args.input_dir.display())));
+	}
+	if let Some(output_dir) = &args.output_dir {
+		fs::create_dir_all(output_dir).map_err(|error| {
+			Error::Io(format!("unable to create the output directory \"{}\": {}", output_dir.display(), error))
+		})?;
+	}
+	let mut on_unlock: Option<Hook> = None;
+	if let Some(command) = &args.on_unlock {
+		on_unlock = Some(Hook::new(command.clone(), args.on_unlock_timeout_str.as_deref())?);
+	}
+	wait::install_interrupt_handler()?;
+
+	//=========================================================================
+	// Setup client object. It is used to read the lock dates only.
+	let analyzer_factory: factory::AnalyzerFactory = 
+		factory::AnalyzerFactory::new();
+	let analyzer = analyzer_factory.create();
+	
+	let config = settings::client_config();
+	
+	let client_factory: factory::ClientFactory = 
+		factory::ClientFactory::new(Rc::clone(&analyzer),
+									Rc::clone(&config));
+	let client = client_factory.create();
+
+	log(&client, format!("Watching {}", args.input_dir.display()));
+	let input_dir: String = args.input_dir.to_string_lossy().into_owned();
+	let mut watched: HashMap<PathBuf, Watched> = HashMap::new();
+	loop {
+		//=====================================================================
+		// Rescan the directory for added, modified and removed files
+		let files: Vec<PathBuf> = directory::list_files(input_dir.as_str())?;
+		watched.retain(|file, _watched| files.contains(file));
+		for file in files {
+			let modified: SystemTime = modified(file.as_path());
+			if watched.get(&file).is_some_and(|watched| watched.modified == modified) {
+				continue;
+			}
+
+			let state: State = track(&analyzer, &client, args, file.as_path());
+			watched.insert(file, Watched { modified, state });
+		}
+
+		//=====================================================================
+		// Decrypt the files which are due
+		let mut wake_up: DateTime<FixedOffset> = Local::now().fixed_offset()
+			+ chrono::Duration::from_std(RESCAN_INTERVAL).unwrap_or_default();
+		for (file, watched) in watched.iter_mut() {
+			if let State::Pending { lockdate, hint, next_attempt, backoff } = &watched.state {
+				if *next_attempt <= Local::now() {
+					watched.state = release(&client, args, &on_unlock, file.as_path(), *lockdate, hint.clone(), *backoff);
+				}
+			}
+
+			if let State::Pending { next_attempt, .. } = &watched.state {
+				wake_up = wake_up.min(*next_attempt);
+			}
+		}
+
+		//=====================================================================
+		// Sleep until the next file is due or the next rescan
+		let remaining: chrono::Duration = wake_up.signed_duration_since(Local::now());
+		if let Err(error) = wait::sleep(remaining.to_std().unwrap_or_default()) {
+			log(&client, String::from("Stopped watching"));
+			return Err(error);
+		}
+	}
+}
@@ -19,14 +19,116 @@
  */
 
 use assert_cmd::Command;
+use predicates::prelude::*;
+use std::io::{
+	BufRead,
+	Write,
+};
+
+/// Create a command running snailcrypt-cli. The configuration of the user
+/// running the tests does not apply: the configuration directory is an empty
+/// temporary directory and the SNAILCRYPT_* environment variables are cleared.
+fn cli() -> Result<Command, assert_cmd::cargo::CargoError> {
+	let mut command: Command = Command::cargo_bin("snailcrypt-cli")?;
+	isolate(|name, value| {
+		match value {
+			Some(value) => command.env(name, value),
+			None => command.env_remove(name),
+		};
+	});
+
+	Ok(command)
+}
+
+/// Pass the environment isolating a command from the configuration of the
+/// user running the tests to a setter. A value of None removes the variable.
+fn isolate(mut set: impl FnMut(std::ffi::OsString, Option<std::path::PathBuf>)) {
+	let home: std::path::PathBuf = std::env::temp_dir().join(format!("snailcrypt-cli-home-{}", std::process::id()));
+	set("XDG_CONFIG_HOME".into(), Some(home.join(".config")));
+	set("HOME".into(), Some(home));
+	for (name, _value) in std::env::vars_os() {
+		if name.to_string_lossy().starts_with("SNAILCRYPT_") {
+			set(name, None);
+		}
+	}
+}
+
+/// A mock of the key server served by "snailcrypt-cli serve-mock" on a free
+/// port. The server is killed when it is dropped.
+struct MockServer {
+	child: std::process::Child,
+	stdin: std::process::ChildStdin,
+	stdout: std::io::BufReader<std::process::ChildStdout>,
+	url: String,
+}
+
+impl MockServer {
+	fn start() -> Result<MockServer, Box<dyn std::error::Error>> {
+		let mut command: std::process::Command = std::process::Command::new(assert_cmd::cargo::cargo_bin("snailcrypt-cli"));
+		isolate(|name, value| {
+			match value {
+				Some(value) => command.env(name, value),
+				None => command.env_remove(name),
+			};
+		});
+		let mut child: std::process::Child = command
+			.arg("serve-mock")
+			.arg("--listen")
+			.arg("127.0.0.1:0")
+			.stdin(std::process::Stdio::piped())
+			.stdout(std::process::Stdio::piped())
+			.stderr(std::process::Stdio::null())
+			.spawn()?;
+		let stdin: std::process::ChildStdin = child.stdin.take().ok_or("no stdin")?;
+		let mut stdout = std::io::BufReader::new(child.stdout.take().ok_or("no stdout")?);
+
+		let mut line: String = String::new();
+		stdout.read_line(&mut line)?;
+		let url: String = String::from(line.trim().strip_prefix("Listening on ").ok_or("the mock server did not start")?);
+
+		Ok(MockServer { child, stdin, stdout, url })
+	}
+
+	/// Send a control command (e.g. "clock 2030-01-01T00:00:00+0000" or
+	/// "fault drop") and wait for its answer.
+	fn control(&mut self, command: &str) -> Result<(), Box<dyn std::error::Error>> {
+		writeln!(self.stdin, "{}", command)?;
+		self.stdin.flush()?;
+
+		let mut answer: String = String::new();
+		self.stdout.read_line(&mut answer)?;
+		if !answer.starts_with("ok") {
+			return Err(format!("the mock server rejected \"{}\": {}", command, answer.trim()).into());
+		}
+
+		Ok(())
+	}
+
+	/// Create a command using the mock server.
+	fn command(&self) -> Result<Command, assert_cmd::cargo::CargoError> {
+		let mut command: Command = cli()?;
+		command.env("SNAILCRYPT_SERVER", self.url.as_str());
+
+		Ok(command)
+	}
+}
+
+impl Drop for MockServer {
+	fn drop(&mut self) {
+		let _ = self.child.kill();
+		let _ = self.child.wait();
+	}
+}
 
 #[test]
 fn encrypt_small_str() -> Result<(), Box<dyn std::error::Error>> {
+	let server: MockServer = MockServer::start()?;
+	
 	let plaintext: String = String::from("hello world");
 	
 	//=========================================================================
 	// Perform encryption
-    let mut cmd_encrypt = Command::cargo_bin("snailcrypt-cli")
+    let mut cmd_encrypt = server.command()
 		.unwrap_or_else(|error| {
 			panic!("Error: {:?}", error);
 		});
@@ -42,7 +144,7 @@ fn encrypt_small_str() -> Result<(), Box<dyn std::error::Error>> {
 	
 	//=========================================================================
 	// Perform decryption		   
-	let mut cmd_decrypt = Command::cargo_bin("snailcrypt-cli")
+	let mut cmd_decrypt = server.command()
 		.unwrap_or_else(|error| {
 			panic!("Error: {:?}", error);
 		});
@@ -55,7 +157,7 @@ fn encrypt_small_str() -> Result<(), Box<dyn std::error::Error>> {
 			   
 	//=========================================================================
 	// Perform hint extraction 
-	let mut cmd_hint = Command::cargo_bin("snailcrypt-cli")
+	let mut cmd_hint = server.command()
 		.unwrap_or_else(|error| {
 			panic!("Error: {:?}", error);
 		});
@@ -72,6 +174,8 @@ fn encrypt_small_str() -> Result<(), Box<dyn std::error::Error>> {
 
 #[test]
 fn encrypt_large_str() -> Result<(), Box<dyn std::error::Error>> {
+	let server: MockServer = MockServer::start()?;
+	
 	let plaintext: String = 
 		String::from("Nullam eu ante vel est convallis dignissim.  Fusce suscipit, wisi nec facilisis facilisis, est dui fermentum leo, 
 quis tempor ligula erat quis odio.  Nunc porta vulputate tellus.  
@@ -86,7 +190,7 @@ quis est convallis tempor.  Curabitur lacinia pulvinar nibh.  Nam a sapien.");
 	
 	//=========================================================================
 	// Perform encryption
-    let mut cmd_encrypt = Command::cargo_bin("snailcrypt-cli")
+    let mut cmd_encrypt = server.command()
 		.unwrap_or_else(|error| {
 			panic!("Error: {:?}", error);
 		});
@@ -102,7 +206,7 @@ quis est convallis tempor.  Curabitur lacinia pulvinar nibh.  Nam a sapien.");
 	
 	//=========================================================================
 	// Perform decryption		   
-	let mut cmd_decrypt = Command::cargo_bin("snailcrypt-cli")
+	let mut cmd_decrypt = server.command()
 		.unwrap_or_else(|error| {
 			panic!("Error: {:?}", error);
 		});
@@ -115,7 +219,7 @@ quis est convallis tempor.  Curabitur lacinia pulvinar nibh.  Nam a sapien.");
 			   
 	//=========================================================================
 	// Perform hint extraction 
-	let mut cmd_hint = Command::cargo_bin("snailcrypt-cli")
+	let mut cmd_hint = server.command()
 		.unwrap_or_else(|error| {
 			panic!("Error: {:?}", error);
 		});
@@ -132,12 +236,14 @@ quis est convallis tempor.  Curabitur lacinia pulvinar nibh.  Nam a sapien.");
 
 #[test]
 fn encrypt_small_str_hint() -> Result<(), Box<dyn std::error::Error>> {
+	let server: MockServer = MockServer::start()?;
+	
 	let plaintext: String = String::from("hello world");
 	let hint = String::from("This is a small hint.");
 	
 	//=========================================================================
 	// Perform encryption
-    let mut cmd_encrypt = Command::cargo_bin("snailcrypt-cli")
+    let mut cmd_encrypt = server.command()
 		.unwrap_or_else(|error| {
 			panic!("Error: {:?}", error);
 		});
@@ -155,7 +261,7 @@ fn encrypt_small_str_hint() -> Result<(), Box<dyn std::error::Error>> {
 	
 	//=========================================================================
 	// Perform decryption		   
-	let mut cmd_decrypt = Command::cargo_bin("snailcrypt-cli")
+	let mut cmd_decrypt = server.command()
 		.unwrap_or_else(|error| {
 			panic!("Error: {:?}", error);
 		});
@@ -168,7 +274,7 @@ fn encrypt_small_str_hint() -> Result<(), Box<dyn std::error::Error>> {
 
 	//=========================================================================
 	// Perform hint extraction 
-	let mut cmd_hint = Command::cargo_bin("snailcrypt-cli")
+	let mut cmd_hint = server.command()
 		.unwrap_or_else(|error| {
 			panic!("Error: {:?}", error);
 		});
@@ -185,6 +291,8 @@ fn encrypt_small_str_hint() -> Result<(), Box<dyn std::error::Error>> {
 
 #[test]
 fn encrypt_large_str_hint() -> Result<(), Box<dyn std::error::Error>> {
+	let server: MockServer = MockServer::start()?;
+	
 	let plaintext = 
 		String::from("Nullam eu ante vel est convallis dignissim.  Fusce suscipit, wisi nec facilisis facilisis, est dui fermentum leo, 
 quis tempor ligula erat quis odio.  Nunc porta vulputate tellus.  
@@ -210,7 +318,7 @@ quis est convallis tempor.  Curabitur lacinia pulvinar nibh.  Nam a sapien.");
 	
 	//=========================================================================
 	// Perform encryption
-    let mut cmd_encrypt = Command::cargo_bin("snailcrypt-cli")
+    let mut cmd_encrypt = server.command()
 		.unwrap_or_else(|error| {
 			panic!("Error: {:?}", error);
 		});
@@ -228,7 +336,7 @@ quis est convallis tempor.  Curabitur lacinia pulvinar nibh.  Nam a sapien.");
 	
 	//=========================================================================
 	// Perform decryption		   
-	let mut cmd_decrypt = Command::cargo_bin("snailcrypt-cli")
+	let mut cmd_decrypt = server.command()
 		.unwrap_or_else(|error| {
 			panic!("Error: {:?}", error);
 		});
@@ -241,7 +349,7 @@ quis est convallis tempor.  Curabitur lacinia pulvinar nibh.  Nam a sapien.");
 			   
 	//=========================================================================
 	// Perform hint extraction 
-	let mut cmd_hint = Command::cargo_bin("snailcrypt-cli")
+	let mut cmd_hint = server.command()
 		.unwrap_or_else(|error| {
 			panic!("Error: {:?}", error);
 		});
@@ -262,7 +370,7 @@ fn encrypt_fail_lockdate() -> Result<(), Box<dyn std::error::Error>> {
 	
 	//=========================================================================
 	// Perform encryption
-    let mut cmd_encrypt = Command::cargo_bin("snailcrypt-cli")
+    let mut cmd_encrypt = cli()
 							.unwrap_or_else(|error| {
 								panic!("Error: {:?}", error);
 							});
@@ -277,3 +385,2192 @@ fn encrypt_fail_lockdate() -> Result<(), Box<dyn std::error::Error>> {
 
     Ok(())
 }
+
+#[test]
+fn encrypt_fail_relative_lockdate() -> Result<(), Box<dyn std::error::Error>> {
+	let plaintext: String = String::from("hello world");
+	
+	//=========================================================================
+	// Perform encryption using a lock date in the past
+    let mut cmd_encrypt = cli()
+							.unwrap_or_else(|error| {
+								panic!("Error: {:?}", error);
+							});
+
+    cmd_encrypt
+    		.arg("-e")
+			.arg("-1d 2h")
+			.write_stdin(plaintext.as_str())
+			.assert()
+			.failure()
+			.stderr(predicate::str::starts_with("Lock date: "))
+			.stderr(predicate::str::contains("is in the past."));
+			
+	//=========================================================================
+	// Perform encryption using an unknown unit
+    let mut cmd_encrypt_unit = cli()
+							.unwrap_or_else(|error| {
+								panic!("Error: {:?}", error);
+							});
+
+    cmd_encrypt_unit
+    		.arg("-e")
+			.arg("in 3 fortnights")
+			.write_stdin(plaintext.as_str())
+			.assert()
+			.failure()
+			.stderr("Error: unable to parse the relative lock date \"in 3 fortnights\": unknown unit \"fortnights\"\n");
+
+    Ok(())
+}
+
+#[test]
+fn encrypt_fail_absolute_lockdate() -> Result<(), Box<dyn std::error::Error>> {
+	let plaintext: String = String::from("hello world");
+	
+	//=========================================================================
+	// Perform encryption using a RFC 3339 lock date in the past
+    let mut cmd_encrypt = cli()
+							.unwrap_or_else(|error| {
+								panic!("Error: {:?}", error);
+							});
+
+    cmd_encrypt
+    		.arg("-e")
+			.arg("2023-01-31T23:00:00Z")
+			.write_stdin(plaintext.as_str())
+			.assert()
+			.failure()
+			.stderr("Lock date: 2023-01-31T23:00:00+0000
+Error: lock date \"2023-01-31T23:00:00+0000\" is in the past.\n");
+			
+	//=========================================================================
+	// Perform encryption using a local time skipped by daylight saving time
+    let mut cmd_encrypt_dst = cli()
+							.unwrap_or_else(|error| {
+								panic!("Error: {:?}", error);
+							});
+
+    cmd_encrypt_dst
+    		.arg("-e")
+			.arg("2024-03-31 02:30 Europe/Vienna")
+			.write_stdin(plaintext.as_str())
+			.assert()
+			.failure()
+			.stderr("Error: unable to parse the lock date \"2024-03-31 02:30 Europe/Vienna\": the local time \"2024-03-31 02:30:00\" does not exist in Europe/Vienna\n");
+
+    Ok(())
+}
+
+#[test]
+fn encrypt_binary() -> Result<(), Box<dyn std::error::Error>> {
+	let server: MockServer = MockServer::start()?;
+	
+	let plaintext: Vec<u8> = vec![0x89, 0x50, 0x4e, 0x47, 0x00, 0x0d, 0x0a, 0xff, 0xfe, 0x00, 0x01];
+	
+	//=========================================================================
+	// Perform encryption
+    let mut cmd_encrypt = server.command()
+		.unwrap_or_else(|error| {
+			panic!("Error: {:?}", error);
+		});
+
+    let encrypted = cmd_encrypt
+    						   .arg("-e")
+							   .arg("2022-11-19T17:00:00+0100")			   
+							   .arg("-f")
+							   .write_stdin(plaintext.clone())
+							   .assert()
+							   .success();
+	let ciphertext: Vec<u8> = encrypted.get_output().stdout.to_owned();
+	
+	//=========================================================================
+	// Perform decryption		   
+	let mut cmd_decrypt = server.command()
+		.unwrap_or_else(|error| {
+			panic!("Error: {:?}", error);
+		});
+	
+	
+	cmd_decrypt.arg("-d")
+			   .write_stdin(ciphertext.clone())
+			   .assert()
+			   .stdout(plaintext);
+
+    Ok(())
+}
+
+#[test]
+fn encrypt_envelope() -> Result<(), Box<dyn std::error::Error>> {
+	let server: MockServer = MockServer::start()?;
+	
+	let plaintext: Vec<u8> = (0..200000).map(|i| (i % 251) as u8).collect();
+	let hint = String::from("This is a small hint.");
+	
+	//=========================================================================
+	// Perform encryption
+    let mut cmd_encrypt = server.command()
+		.unwrap_or_else(|error| {
+			panic!("Error: {:?}", error);
+		});
+
+    let encrypted = cmd_encrypt
+    						   .arg("-e")
+							   .arg("2022-11-19T17:00:00+0100")			   
+							   .arg("-t")
+							   .arg(hint.as_str())
+							   .arg("-f")
+							   .arg("--envelope")
+							   .write_stdin(plaintext.clone())
+							   .assert()
+							   .success();
+	let ciphertext: Vec<u8> = encrypted.get_output().stdout.to_owned();
+	
+	//=========================================================================
+	// Perform decryption		   
+	let mut cmd_decrypt = server.command()
+		.unwrap_or_else(|error| {
+			panic!("Error: {:?}", error);
+		});
+	
+	
+	cmd_decrypt.arg("-d")
+			   .write_stdin(ciphertext.clone())
+			   .assert()
+			   .stdout(plaintext);
+			   
+	//=========================================================================
+	// Perform hint extraction 
+	let mut cmd_hint = server.command()
+		.unwrap_or_else(|error| {
+			panic!("Error: {:?}", error);
+		});
+	
+	
+	cmd_hint
+		.arg("-dT")
+		.write_stdin(ciphertext.clone())
+		.assert()
+		.stdout(hint);
+
+    Ok(())
+}
+
+#[test]
+fn encrypt_fail_envelope_url() -> Result<(), Box<dyn std::error::Error>> {
+	let plaintext: String = String::from("hello world");
+	
+	//=========================================================================
+	// Perform encryption
+    let mut cmd_encrypt = cli()
+							.unwrap_or_else(|error| {
+								panic!("Error: {:?}", error);
+							});
+
+    cmd_encrypt
+    		.arg("-e")
+			.arg("2022-11-19T17:00:00+0100")
+			.arg("-f")
+			.arg("-u")
+			.arg("--envelope")
+			.write_stdin(plaintext.as_str())
+			.assert()
+			.failure()
+			.stderr("Error: an envelope container cannot be embedded in a URL\n");
+
+    Ok(())
+}
+
+#[test]
+fn inspect_small_str_hint() -> Result<(), Box<dyn std::error::Error>> {
+	let server: MockServer = MockServer::start()?;
+	
+	let plaintext: String = String::from("hello world");
+	let hint = String::from("This is a small hint.");
+	
+	//=========================================================================
+	// Perform encryption
+    let mut cmd_encrypt = server.command()
+		.unwrap_or_else(|error| {
+			panic!("Error: {:?}", error);
+		});
+
+    let encrypted = cmd_encrypt
+    						   .arg("-e")
+							   .arg("2022-11-19T17:00:00+0100")			   
+							   .arg("-t")
+							   .arg(hint.as_str())
+							   .arg("-f")
+							   .write_stdin(plaintext.as_str())
+							   .assert()
+							   .success();
+	let ciphertext: Vec<u8> = encrypted.get_output().stdout.to_owned();
+	
+	//=========================================================================
+	// Perform inspection
+	let mut cmd_inspect = server.command()
+		.unwrap_or_else(|error| {
+			panic!("Error: {:?}", error);
+		});
+	
+	cmd_inspect.arg("inspect")
+			   .arg("--json")
+			   .write_stdin(ciphertext.clone())
+			   .assert()
+			   .success()
+			   .stdout(predicate::str::contains("\"lockdate_utc\":\"2022-11-19T16:00:00+0000\""))
+			   .stdout(predicate::str::contains("\"hint\":\"This is a small hint.\""))
+			   .stdout(predicate::str::contains("\"decryptable\":true"));
+
+    Ok(())
+}
+
+#[test]
+fn inspect_input_formats() -> Result<(), Box<dyn std::error::Error>> {
+	let mut server: MockServer = MockServer::start()?;
+
+	//=========================================================================
+	// QR code image, timer URL and armored message are accepted like for -d.
+	// Messages which are still locked are inspected without contacting the
+	// server.
+	server.control("fault 4 drop")?;
+	for input in [std::fs::read("tests/data/locked.png")?,
+				  Vec::from("https://intranet.example/timer.php?c=2:MjAzMC0wMS0wMVQwMDowMDowMCswMDAw:AA+A:aGk="),
+				  Vec::from("-----BEGIN SNAILCRYPT MESSAGE-----\nVersion: 2\n\n2:MjAzMC0wMS0wMVQwMDowMDowMCswMDAw:AA+A:aGk=\n-----END SNAILCRYPT MESSAGE-----\n")] {
+	    server.command()?
+	    	.arg("inspect")
+	    	.arg("--json")
+			.write_stdin(input)
+			.assert()
+			.success()
+			.stdout(predicate::str::contains("\"lockdate_utc\":\"2030-01-01T00:00:00+0000\""))
+			.stdout(predicate::str::contains("\"hint\":\"hi\""))
+			.stdout(predicate::str::contains("\"decryptable\":false"));
+	}
+
+	//=========================================================================
+	// The server of an armored message is checked
+    server.command()?
+    	.arg("inspect")
+		.write_stdin("-----BEGIN SNAILCRYPT MESSAGE-----\nVersion: 2\nServer: https://staging.example/api\n\n2:MjAzMC0wMS0wMVQwMDowMDowMCswMDAw:AA+A:aGk=\n-----END SNAILCRYPT MESSAGE-----\n")
+		.assert()
+		.success()
+		.stderr(format!("Warning: the message has been encrypted with the server https://staging.example/api, but the server {} is used.\n",
+						server.url));
+
+    Ok(())
+}
+
+#[test]
+fn inspect_fail_version() -> Result<(), Box<dyn std::error::Error>> {
+	//=========================================================================
+	// Perform inspection
+	let mut cmd_inspect = cli()
+		.unwrap_or_else(|error| {
+			panic!("Error: {:?}", error);
+		});
+	
+	cmd_inspect.arg("inspect")
+			   .write_stdin("3:MjAyMi0xMS0xOVQxNzowMDowMCswMTAw:AAAA")
+			   .assert()
+			   .failure()
+			   .stderr("Error: Unknown client version: 3\n");
+
+    Ok(())
+}
+
+#[test]
+fn exit_codes() -> Result<(), Box<dyn std::error::Error>> {
+	//=========================================================================
+	// Argument error: lock date in the past
+    cli()?
+    	.arg("-e")
+		.arg("2022-11-19T17:00:00+0100")
+		.write_stdin("hello world")
+		.assert()
+		.code(2);
+		
+	//=========================================================================
+	// I/O error: missing input file
+    cli()?
+    	.arg("-d")
+		.arg("-i")
+		.arg("does-not-exist.txt")
+		.assert()
+		.code(3)
+		.stderr(predicate::str::starts_with("Error: unable to open the input file \"does-not-exist.txt\""));
+		
+	//=========================================================================
+	// Parse error: unknown lock date format
+    cli()?
+    	.arg("-e")
+		.arg("tomorrow-ish")
+		.write_stdin("hello world")
+		.assert()
+		.code(4)
+		.stderr("Error: unable to parse the lock date \"tomorrow-ish\": unknown date format\n");
+		
+	//=========================================================================
+	// Malformed ciphertext
+    cli()?
+    	.arg("-d")
+		.write_stdin("2:MjAyMi0xMS0xOVQxNzowMDowMCswMTAw:AAAA")
+		.assert()
+		.code(8)
+		.stderr("Error: the ciphertext must consist of 4 components separated by a colon\n");
+
+    Ok(())
+}
+
+#[test]
+fn decrypt_fail_max_wait() -> Result<(), Box<dyn std::error::Error>> {
+	//=========================================================================
+	// Lock date beyond the maximum waiting time
+    cli()?
+    	.arg("-d")
+    	.arg("--wait")
+    	.arg("--max-wait")
+    	.arg("1m")
+		.write_stdin("2:MjAzMC0wMS0wMVQwMDowMDowMCswMDAw:AAAA:aGk=")
+		.assert()
+		.code(7)
+		.stderr("Error: the message is locked until 2030-01-01T00:00:00+0000, which is beyond the maximum waiting time\n");
+		
+	//=========================================================================
+	// Unknown maximum waiting time
+    cli()?
+    	.arg("-d")
+    	.arg("--wait")
+    	.arg("--max-wait")
+    	.arg("forever")
+		.write_stdin("2:MjAzMC0wMS0wMVQwMDowMDowMCswMDAw:AAAA:aGk=")
+		.assert()
+		.code(4)
+		.stderr(predicate::str::starts_with("Error: unable to parse the maximum waiting time \"forever\""));
+
+    Ok(())
+}
+
+#[test]
+fn encrypt_url() -> Result<(), Box<dyn std::error::Error>> {
+	let server: MockServer = MockServer::start()?;
+	
+	let plaintext = "Hello world";
+	
+	//=========================================================================
+	// Perform encryption
+    let mut cmd_encrypt = server.command()
+		.unwrap_or_else(|error| {
+			panic!("Error: {:?}", error);
+		});
+
+    let encrypted = cmd_encrypt
+    						   .arg("-e")
+							   .arg("2022-11-19T17:00:00+0100")			   
+							   .arg("-f")
+							   .arg("-u")
+							   .write_stdin(plaintext)
+							   .assert()
+							   .success();
+	let url: String = String::from_utf8(encrypted.get_output().stdout.to_owned())?;
+	
+	//=========================================================================
+	// Perform decryption		   
+	let mut cmd_decrypt = server.command()
+		.unwrap_or_else(|error| {
+			panic!("Error: {:?}", error);
+		});
+	
+	cmd_decrypt.arg("-d")
+			   .write_stdin(url)
+			   .assert()
+			   .stdout(plaintext);
+
+    Ok(())
+}
+
+#[test]
+fn decrypt_url_formats() -> Result<(), Box<dyn std::error::Error>> {
+	let server: MockServer = MockServer::start()?;
+
+	//=========================================================================
+	// URL of another host with extra parameters and a fragment, not
+	// percent-encoded. The '+' of the ciphertext must survive.
+	let mut ciphertext: String = String::new();
+	while !ciphertext.contains('+') {
+	    let encrypted = server.command()?
+	    	.arg("-e")
+			.arg("2022-11-19T17:00:00+0100")
+			.arg("-f")
+			.write_stdin("Hello world")
+			.assert()
+			.success();
+		ciphertext = String::from_utf8(encrypted.get_output().stdout.to_owned())?;
+	}
+    server.command()?
+    	.arg("-d")
+		.write_stdin(format!("https://intranet.example/snailcrypt/timer.php?lang=de&c={}#top", ciphertext.trim()))
+		.assert()
+		.success()
+		.stdout("Hello world");
+
+    cli()?
+    	.arg("-d")
+    	.arg("--wait")
+    	.arg("--max-wait")
+    	.arg("1m")
+		.write_stdin("https://intranet.example/snailcrypt/timer.php?lang=de&c=2:MjAzMC0wMS0wMVQwMDowMDowMCswMDAw:AA+A:aGk=#top")
+		.assert()
+		.code(7)
+		.stderr("Error: the message is locked until 2030-01-01T00:00:00+0000, which is beyond the maximum waiting time\n");
+		
+	//=========================================================================
+	// Percent-encoded URL
+    cli()?
+    	.arg("-d")
+    	.arg("--wait")
+    	.arg("--max-wait")
+    	.arg("1m")
+		.write_stdin("https://webapp.snailcrypt.com/timer.php?c=2%3AMjAzMC0wMS0wMVQwMDowMDowMCswMDAw%3AAA%2BA%3AaGk%3D")
+		.assert()
+		.code(7)
+		.stderr("Error: the message is locked until 2030-01-01T00:00:00+0000, which is beyond the maximum waiting time\n");
+		
+	//=========================================================================
+	// URL without a ciphertext
+    cli()?
+    	.arg("-d")
+		.write_stdin("https://webapp.snailcrypt.com/timer.php?lang=de")
+		.assert()
+		.code(8)
+		.stderr("Error: the URL does not contain a \"c\" parameter holding the ciphertext\n");
+
+    Ok(())
+}
+
+#[test]
+fn encrypt_url_base() -> Result<(), Box<dyn std::error::Error>> {
+	let server: MockServer = MockServer::start()?;
+	
+	let plaintext = "Hello world";
+	
+	//=========================================================================
+	// Perform encryption
+    let mut cmd_encrypt = server.command()
+		.unwrap_or_else(|error| {
+			panic!("Error: {:?}", error);
+		});
+
+    let encrypted = cmd_encrypt
+    						   .arg("-e")
+							   .arg("2022-11-19T17:00:00+0100")			   
+							   .arg("-f")
+							   .arg("-u")
+							   .arg("--url-base")
+							   .arg("https://intranet.example/snailcrypt/timer.php?lang=de")
+							   .write_stdin(plaintext)
+							   .assert()
+							   .success()
+							   .stdout(predicate::str::starts_with("https://intranet.example/snailcrypt/timer.php?lang=de&c="));
+	let url: String = String::from_utf8(encrypted.get_output().stdout.to_owned())?;
+	
+	//=========================================================================
+	// Perform decryption		   
+	let mut cmd_decrypt = server.command()
+		.unwrap_or_else(|error| {
+			panic!("Error: {:?}", error);
+		});
+	
+	cmd_decrypt.arg("-d")
+			   .write_stdin(url)
+			   .assert()
+			   .stdout(plaintext);
+
+    Ok(())
+}
+
+#[test]
+fn encrypt_fail_url_base() -> Result<(), Box<dyn std::error::Error>> {
+	//=========================================================================
+	// Unsupported scheme
+    cli()?
+    	.arg("-e")
+		.arg("+1d")
+		.arg("-u")
+		.arg("--url-base")
+		.arg("ftp://intranet.example/timer.php")
+		.write_stdin("hello world")
+		.assert()
+		.code(2)
+		.stderr(predicate::str::ends_with("Error: the URL base \"ftp://intranet.example/timer.php\" must use http or https\n"));
+		
+	//=========================================================================
+	// Invalid URL given by the environment
+    cli()?
+    	.arg("-e")
+		.arg("+1d")
+		.arg("-u")
+		.env("SNAILCRYPT_URL_BASE", "not a url")
+		.write_stdin("hello world")
+		.assert()
+		.code(4)
+		.stderr(predicate::str::contains("Error: unable to parse the URL base \"not a url\""));
+
+    Ok(())
+}
+
+#[test]
+fn encrypt_qr() -> Result<(), Box<dyn std::error::Error>> {
+	let server: MockServer = MockServer::start()?;
+	
+	//=========================================================================
+	// Perform encryption
+    let mut cmd_encrypt = server.command()
+		.unwrap_or_else(|error| {
+			panic!("Error: {:?}", error);
+		});
+
+    cmd_encrypt.arg("-e")
+			   .arg("2022-11-19T17:00:00+0100")			   
+			   .arg("-f")
+			   .arg("-u")
+			   .arg("--qr")
+			   .arg("svg")
+			   .write_stdin("Hello world")
+			   .assert()
+			   .success()
+			   .stdout(predicate::str::starts_with("<?xml"));
+
+    Ok(())
+}
+
+#[test]
+fn encrypt_fail_qr() -> Result<(), Box<dyn std::error::Error>> {
+	//=========================================================================
+	// Unknown format
+    cli()?
+    	.arg("-e")
+		.arg("+1d")
+		.arg("--qr")
+		.arg("gif")
+		.write_stdin("hello world")
+		.assert()
+		.code(2)
+		.stderr(predicate::str::ends_with("Error: unknown QR code format \"gif\" (expected png, svg, terminal or ansi)\n"));
+		
+	//=========================================================================
+	// Unknown error correction level
+    cli()?
+    	.arg("-e")
+		.arg("+1d")
+		.arg("--qr")
+		.arg("png")
+		.arg("--qr-level")
+		.arg("X")
+		.write_stdin("hello world")
+		.assert()
+		.code(2)
+		.stderr(predicate::str::ends_with("Error: unknown error correction level \"X\" (expected L, M, Q or H)\n"));
+		
+	//=========================================================================
+	// Envelope container
+    cli()?
+    	.arg("-e")
+		.arg("+1d")
+		.arg("--envelope")
+		.arg("--qr")
+		.arg("png")
+		.write_stdin("hello world")
+		.assert()
+		.code(2)
+		.stderr(predicate::str::ends_with("Error: an envelope container cannot be embedded in a QR code\n"));
+
+    Ok(())
+}
+
+#[test]
+fn decrypt_qr_image() -> Result<(), Box<dyn std::error::Error>> {
+	//=========================================================================
+	// Rendered PNG image
+    cli()?
+    	.arg("-d")
+    	.arg("--wait")
+    	.arg("--max-wait")
+    	.arg("1m")
+    	.arg("-i")
+    	.arg("tests/data/locked.png")
+		.assert()
+		.code(7)
+		.stderr("Error: the message is locked until 2030-01-01T00:00:00+0000, which is beyond the maximum waiting time\n");
+		
+	//=========================================================================
+	// Rotated and distorted JPEG image
+    cli()?
+    	.arg("-d")
+    	.arg("--wait")
+    	.arg("--max-wait")
+    	.arg("1m")
+    	.arg("-i")
+    	.arg("tests/data/locked.jpg")
+		.assert()
+		.code(7)
+		.stderr("Error: the message is locked until 2030-01-01T00:00:00+0000, which is beyond the maximum waiting time\n");
+		
+	//=========================================================================
+	// Image without a QR code
+    cli()?
+    	.arg("-d")
+    	.arg("-i")
+    	.arg("tests/data/blank.png")
+		.assert()
+		.code(8)
+		.stderr("Error: no QR code has been found in the image\n");
+
+    Ok(())
+}
+
+#[test]
+fn encrypt_armor() -> Result<(), Box<dyn std::error::Error>> {
+	let server: MockServer = MockServer::start()?;
+	
+	let plaintext = "Hello world";
+	
+	//=========================================================================
+	// Perform encryption
+    let mut cmd_encrypt = server.command()
+		.unwrap_or_else(|error| {
+			panic!("Error: {:?}", error);
+		});
+
+    let encrypted = cmd_encrypt
+    						   .arg("-e")
+							   .arg("2022-11-19T17:00:00+0100")			   
+							   .arg("-f")
+							   .arg("-a")
+							   .arg("-t")
+							   .arg("hint")
+							   .write_stdin(plaintext)
+							   .assert()
+							   .success()
+							   .stdout(predicate::str::starts_with(format!("-----BEGIN SNAILCRYPT MESSAGE-----\nVersion: 2\nLock-Date: 2022-11-19T17:00:00+0100\nHint: hint\nServer: {}\n\n", server.url)));
+	let armored: String = String::from_utf8(encrypted.get_output().stdout.to_owned())?;
+	
+	//=========================================================================
+	// Perform decryption of the quoted message using CRLF line endings
+	let mut cmd_decrypt = server.command()
+		.unwrap_or_else(|error| {
+			panic!("Error: {:?}", error);
+		});
+	
+	let quoted: String = armored.lines()
+		.map(|line| format!("> {}\r\n", line))
+		.collect();
+	cmd_decrypt.arg("-d")
+			   .write_stdin(format!("Hello,\r\n\r\n{}\r\nBye\r\n", quoted))
+			   .assert()
+			   .stdout(plaintext);
+
+    Ok(())
+}
+
+#[test]
+fn decrypt_armor() -> Result<(), Box<dyn std::error::Error>> {
+	let armored = "Hi,\r\n\r\n> -----BEGIN SNAILCRYPT MESSAGE-----\r\n> Version: 2\r\n> Lock-Date: 2030-01-01T00:00:00+0000\r\n>\r\n>   2:MjAzMC0wMS0wMVQwMDowMDowMCsw\r\n> MDAw:AA+A:aGk=\r\n> =l1+4\r\n> -----END SNAILCRYPT MESSAGE-----\r\n\r\nBye\r\n";
+	
+	//=========================================================================
+	// Quoted message within surrounding text
+    cli()?
+    	.arg("-d")
+    	.arg("--wait")
+    	.arg("--max-wait")
+    	.arg("1m")
+		.write_stdin(armored)
+		.assert()
+		.code(7)
+		.stderr("Error: the message is locked until 2030-01-01T00:00:00+0000, which is beyond the maximum waiting time\n");
+		
+	//=========================================================================
+	// Checksum mismatch
+    cli()?
+    	.arg("-d")
+		.write_stdin(armored.replace("AA+A", "AB+A"))
+		.assert()
+		.code(8)
+		.stderr("Error: the checksum of the armored message does not match\n");
+		
+	//=========================================================================
+	// Missing END line
+    cli()?
+    	.arg("-d")
+		.write_stdin(armored.replace("-----END SNAILCRYPT MESSAGE-----", ""))
+		.assert()
+		.code(8)
+		.stderr("Error: the armored message is truncated\n");
+		
+	//=========================================================================
+	// Armor within a URL
+    cli()?
+    	.arg("-e")
+		.arg("+1d")
+		.arg("-a")
+		.arg("-u")
+		.write_stdin("hello world")
+		.assert()
+		.code(2)
+		.stderr(predicate::str::ends_with("Error: an armored message cannot be embedded in a URL or a QR code\n"));
+
+    Ok(())
+}
+
+#[test]
+fn scan_document() -> Result<(), Box<dyn std::error::Error>> {
+	let server: MockServer = MockServer::start()?;
+	
+	let plaintext = "Hello world";
+	
+	//=========================================================================
+	// Perform encryption
+    let mut cmd_encrypt = server.command()
+		.unwrap_or_else(|error| {
+			panic!("Error: {:?}", error);
+		});
+
+    let encrypted = cmd_encrypt
+    						   .arg("-e")
+							   .arg("2022-11-19T17:00:00+0100")			   
+							   .arg("-f")
+							   .write_stdin(plaintext)
+							   .assert()
+							   .success();
+	let ciphertext: String = String::from_utf8(encrypted.get_output().stdout.to_owned())?;
+	
+	//=========================================================================
+	// Perform scan
+	let mut cmd_scan = server.command()
+		.unwrap_or_else(|error| {
+			panic!("Error: {:?}", error);
+		});
+	
+	cmd_scan.arg("scan")
+			.write_stdin(format!("Unlocked: {}\nLocked: 2:MjAzMC0wMS0wMVQwMDowMDowMCswMDAw:AA+A:aGk=\n", ciphertext))
+			.assert()
+			.success()
+			.stdout("Unlocked: Hello world\nLocked: 2:MjAzMC0wMS0wMVQwMDowMDowMCswMDAw:AA+A:aGk= [snailcrypt: the message is locked until 2030-01-01T00:00:00+0000]\n");
+
+	//=========================================================================
+	// Perform scan of a document in place
+	let document: std::path::PathBuf = std::env::temp_dir().join(format!("snailcrypt-cli-scan-{}.md", std::process::id()));
+	std::fs::write(&document, format!("Unlocked: {}\n", ciphertext))?;
+
+	server.command()?
+		.arg("scan")
+		.arg("-i")
+		.arg(&document)
+		.arg("-o")
+		.arg(&document)
+		.assert()
+		.success()
+		.stderr("Decrypted 1 message(s), 0 message(s) still locked.\n");
+	assert_eq!(std::fs::read_to_string(&document)?, "Unlocked: Hello world\n");
+
+	std::fs::remove_file(&document)?;
+
+    Ok(())
+}
+
+#[test]
+fn scan_document_repeatedly() -> Result<(), Box<dyn std::error::Error>> {
+	let server: MockServer = MockServer::start()?;
+
+	//=========================================================================
+	// Perform encryption of a raw and an armored message unlocking shortly
+	let mut ciphertexts: Vec<String> = Vec::new();
+	for armor in [false, true] {
+		let mut command: Command = server.command()?;
+		command.arg("-e").arg("+5s");
+		if armor == true {
+			command.arg("-a");
+		}
+		let encrypted = command
+			.write_stdin("Hello world")
+			.assert()
+			.success();
+		ciphertexts.push(String::from_utf8(encrypted.get_output().stdout.to_owned())?);
+	}
+	let armored: String = ciphertexts[1].lines()
+		.map(|line| format!("> {}\n", line))
+		.collect();
+
+	let document: std::path::PathBuf = std::env::temp_dir().join(format!("snailcrypt-cli-scan-repeatedly-{}.md", std::process::id()));
+	std::fs::write(&document, format!("Raw: {}\n{}Bye\n", ciphertexts[0].trim(), armored))?;
+
+	//=========================================================================
+	// Each scan replaces the annotation of the former one
+	for _ in 0..2 {
+		server.command()?
+			.arg("scan")
+			.arg("-i")
+			.arg(&document)
+			.arg("-o")
+			.arg(&document)
+			.assert()
+			.success()
+			.stderr("Decrypted 0 message(s), 2 message(s) still locked.\n");
+		let scanned: String = std::fs::read_to_string(&document)?;
+		assert_eq!(scanned.matches("[snailcrypt: the message is locked until").count(), 2, "{}", scanned);
+		assert!(scanned.contains(&format!("{}\n> [snailcrypt: ", armored.trim_end())), "{}", scanned);
+	}
+
+	//=========================================================================
+	// The annotations are removed once the messages are decrypted
+	std::thread::sleep(std::time::Duration::from_secs(6));
+	server.command()?
+		.arg("scan")
+		.arg("-i")
+		.arg(&document)
+		.arg("-o")
+		.arg(&document)
+		.assert()
+		.success()
+		.stderr("Decrypted 2 message(s), 0 message(s) still locked.\n");
+	assert_eq!(std::fs::read_to_string(&document)?, "Raw: Hello world\n> Hello world\nBye\n");
+
+	std::fs::remove_file(&document)?;
+
+    Ok(())
+}
+
+#[test]
+fn scan_document_failure() -> Result<(), Box<dyn std::error::Error>> {
+	let mut server: MockServer = MockServer::start()?;
+
+	let encrypted = server.command()?
+		.arg("-e")
+		.arg("2022-11-19T17:00:00+0100")
+		.arg("-f")
+		.write_stdin("Hello world")
+		.assert()
+		.success();
+	let ciphertext: String = String::from_utf8(encrypted.get_output().stdout.to_owned())?;
+
+	//=========================================================================
+	// A failing message is annotated, the other ones are still decrypted
+	server.control("fault drop")?;
+	let scanned = server.command()?
+		.arg("scan")
+		.write_stdin(format!("First: {}\nSecond: {}\n", ciphertext.trim(), ciphertext.trim()))
+		.assert()
+		.code(5)
+		.stderr(predicate::str::starts_with("Decrypted 1 message(s), 0 message(s) still locked, 1 message(s) failed.\nError: unable to reach the snailcrypt server: "));
+	let scanned: String = String::from_utf8(scanned.get_output().stdout.to_owned())?;
+	assert!(scanned.starts_with(&format!("First: {} [snailcrypt: unable to reach the snailcrypt server: ", ciphertext.trim())), "{}", scanned);
+	assert!(scanned.ends_with("]\nSecond: Hello world\n"), "{}", scanned);
+
+    Ok(())
+}
+
+#[test]
+fn scan_locked_document() -> Result<(), Box<dyn std::error::Error>> {
+	//=========================================================================
+	// Perform scan of raw, URL and armored messages which are still locked
+    cli()?
+    	.arg("scan")
+		.write_stdin("Meeting at 1:30 pm.\n\
+					  Raw: 2:MjAzMC0wMS0wMVQwMDowMDowMCswMDAw:AA+A:aGk=.\n\
+					  Link (https://intranet.example/timer.php?c=2:MjAzMC0wMS0wMVQwMDowMDowMCswMDAw:AA%2BA:aGk%3D).\n\
+					  > -----BEGIN SNAILCRYPT MESSAGE-----\r\n\
+					  > 2:MjAzMC0wMS0wMVQwMDowMDowMCswMDAw:AA+A:aGk=\r\n\
+					  > -----END SNAILCRYPT MESSAGE-----\r\n\
+					  Bye\n")
+		.assert()
+		.success()
+		.stdout("Meeting at 1:30 pm.\n\
+				 Raw: 2:MjAzMC0wMS0wMVQwMDowMDowMCswMDAw:AA+A:aGk= [snailcrypt: the message is locked until 2030-01-01T00:00:00+0000].\n\
+				 Link (https://intranet.example/timer.php?c=2:MjAzMC0wMS0wMVQwMDowMDowMCswMDAw:AA%2BA:aGk%3D [snailcrypt: the message is locked until 2030-01-01T00:00:00+0000]).\n\
+				 > -----BEGIN SNAILCRYPT MESSAGE-----\r\n\
+				 > 2:MjAzMC0wMS0wMVQwMDowMDowMCswMDAw:AA+A:aGk=\r\n\
+				 > -----END SNAILCRYPT MESSAGE-----\r\n\
+				 > [snailcrypt: the message is locked until 2030-01-01T00:00:00+0000]\r\n\
+				 Bye\n")
+		.stderr("Decrypted 0 message(s), 3 message(s) still locked.\n");
+
+    Ok(())
+}
+
+#[test]
+fn encrypt_json() -> Result<(), Box<dyn std::error::Error>> {
+	let server: MockServer = MockServer::start()?;
+	
+	let plaintext = "Hello world";
+	
+	//=========================================================================
+	// Perform encryption
+    let encrypted = server.command()?
+    	.arg("-e")
+		.arg("2022-11-19T17:00:00+0100")
+		.arg("-f")
+		.arg("-u")
+		.arg("-t")
+		.arg("hint")
+		.arg("--json")
+		.write_stdin(plaintext)
+		.assert()
+		.success();
+	let report: serde_json::Value = serde_json::from_slice(encrypted.get_output().stdout.as_slice())?;
+	assert_eq!(report["lockdate"], "2022-11-19T17:00:00+0100");
+	assert_eq!(report["hint"], "hint");
+	assert_eq!(report["armored"], serde_json::Value::Null);
+	let url: &str = report["url"].as_str().unwrap_or_default();
+	assert!(url.starts_with("https://webapp.snailcrypt.com/timer.php?c="));
+	assert_eq!(report["url_length"], url.len());
+	
+	//=========================================================================
+	// Perform decryption
+    let decrypted = server.command()?
+    	.arg("-d")
+		.arg("--json")
+		.write_stdin(report["ciphertext"].as_str().unwrap_or_default().to_owned())
+		.assert()
+		.success();
+	let report: serde_json::Value = serde_json::from_slice(decrypted.get_output().stdout.as_slice())?;
+	assert_eq!(report["plaintext"], plaintext);
+	assert_eq!(report["encoding"], "utf-8");
+	assert_eq!(report["hint"], "hint");
+	assert_eq!(report["lockdate"], "2022-11-19T17:00:00+0100");
+	assert_eq!(report["status"], "decrypted");
+
+    Ok(())
+}
+
+#[test]
+fn decrypt_json_binary() -> Result<(), Box<dyn std::error::Error>> {
+	let server: MockServer = MockServer::start()?;
+	
+	let data: Vec<u8> = vec![0x89, 0x50, 0x4e, 0x47, 0x00, 0xff, 0xfe];
+	
+	//=========================================================================
+	// Perform encryption
+    let encrypted = server.command()?
+    	.arg("-e")
+		.arg("2022-11-19T17:00:00+0100")
+		.arg("-f")
+		.write_stdin(data.clone())
+		.assert()
+		.success();
+	
+	//=========================================================================
+	// Perform decryption
+    let decrypted = server.command()?
+    	.arg("-d")
+		.arg("--json")
+		.write_stdin(encrypted.get_output().stdout.to_owned())
+		.assert()
+		.success();
+	let report: serde_json::Value = serde_json::from_slice(decrypted.get_output().stdout.as_slice())?;
+	assert_eq!(report["plaintext"], "iVBORwD//g==");
+	assert_eq!(report["encoding"], "base64");
+
+    Ok(())
+}
+
+#[test]
+fn json_errors() -> Result<(), Box<dyn std::error::Error>> {
+	//=========================================================================
+	// Malformed ciphertext
+    cli()?
+    	.arg("-d")
+		.arg("--json")
+		.write_stdin("garbage")
+		.assert()
+		.code(8)
+		.stdout("")
+		.stderr("{\"error\":{\"category\":\"malformed\",\"code\":8,\"message\":\"Unknown client version: garbage\"}}\n");
+		
+	//=========================================================================
+	// Lock date beyond the maximum waiting time
+    cli()?
+    	.arg("-d")
+    	.arg("--wait")
+    	.arg("--max-wait")
+    	.arg("1m")
+		.arg("--json")
+		.write_stdin("2:MjAzMC0wMS0wMVQwMDowMDowMCswMDAw:AA+A:aGk=")
+		.assert()
+		.code(7)
+		.stderr(predicate::str::starts_with("{\"error\":{\"category\":\"locked\",\"code\":7,"));
+		
+	//=========================================================================
+	// Unreadable input file
+    cli()?
+    	.arg("-d")
+		.arg("--json")
+		.arg("-i")
+		.arg("tests/data/does-not-exist")
+		.assert()
+		.code(3)
+		.stderr(predicate::str::starts_with("{\"error\":{\"category\":\"io\",\"code\":3,"));
+		
+	//=========================================================================
+	// QR codes cannot be printed as JSON
+    cli()?
+    	.arg("-e")
+		.arg("+1d")
+		.arg("--qr")
+		.arg("png")
+		.arg("--json")
+		.write_stdin("Hello world")
+		.assert()
+		.code(2)
+		.stderr("{\"error\":{\"category\":\"argument\",\"code\":2,\"message\":\"a QR code cannot be printed as JSON\"}}\n");
+
+    Ok(())
+}
+
+#[test]
+fn batch() -> Result<(), Box<dyn std::error::Error>> {
+	let server: MockServer = MockServer::start()?;
+	
+	//=========================================================================
+	// Encrypt two messages and decrypt them within the same batch
+    let encrypted = server.command()?
+    	.arg("--batch")
+		.write_stdin("{\"id\":1,\"op\":\"encrypt\",\"plaintext\":\"Hello world\",\"lockdate\":\"2022-11-19T17:00:00+0100\",\"hint\":\"hint\",\"force\":true}\n\
+					  {\"id\":2,\"op\":\"encrypt\",\"plaintext\":\"AP8=\",\"encoding\":\"base64\",\"lockdate\":\"2022-11-19T17:00:00+0100\",\"force\":true}\n")
+		.assert()
+		.success();
+	let responses: Vec<serde_json::Value> = String::from_utf8(encrypted.get_output().stdout.to_owned())?
+		.lines()
+		.map(serde_json::from_str)
+		.collect::<Result<Vec<serde_json::Value>, serde_json::Error>>()?;
+	assert_eq!(responses.len(), 2);
+	assert_eq!(responses[0]["id"], 1);
+	assert_eq!(responses[0]["ok"], true);
+	assert_eq!(responses[0]["lockdate"], "2022-11-19T17:00:00+0100");
+	
+	let requests: String = responses.iter()
+		.map(|response| format!("{}\n", serde_json::json!({
+			"id": response["id"],
+			"op": "decrypt",
+			"ciphertext": response["ciphertext"],
+		})))
+		.collect();
+    let decrypted = server.command()?
+    	.arg("--batch")
+		.write_stdin(requests)
+		.assert()
+		.success();
+	let responses: Vec<serde_json::Value> = String::from_utf8(decrypted.get_output().stdout.to_owned())?
+		.lines()
+		.map(serde_json::from_str)
+		.collect::<Result<Vec<serde_json::Value>, serde_json::Error>>()?;
+	assert_eq!(responses[0]["plaintext"], "Hello world");
+	assert_eq!(responses[0]["hint"], "hint");
+	assert_eq!(responses[1]["plaintext"], "AP8=");
+	assert_eq!(responses[1]["encoding"], "base64");
+
+    Ok(())
+}
+
+#[test]
+fn batch_errors() -> Result<(), Box<dyn std::error::Error>> {
+	//=========================================================================
+	// Failing requests are answered without aborting the batch
+    cli()?
+    	.arg("--batch")
+		.write_stdin("{\"id\":\"a\",\"op\":\"decrypt\",\"ciphertext\":\"2:MjAzMC0wMS0wMVQwMDowMDowMCswMDAw:AA+A:aGk=\"}\n\
+					  not json\n\
+					  \n\
+					  {\"op\":\"decrypt\",\"ciphertext\":\"garbage\"}\n\
+					  {\"op\":\"encrypt\",\"plaintext\":\"Hello world\"}\n\
+					  {\"op\":\"sign\"}\n\
+					  {\"op\":\"encrypt\",\"plaintext\":\"Hello world\",\"lockdate\":\"+1d\",\"force\":\"yes\"}\n")
+		.assert()
+		.success()
+		.stdout("{\"error\":{\"category\":\"locked\",\"code\":7,\"message\":\"the message is locked until 2030-01-01T00:00:00+0000\"},\"id\":\"a\",\"ok\":false}\n\
+				 {\"error\":{\"category\":\"parse\",\"code\":4,\"message\":\"unable to parse the request: expected ident at line 1 column 2\"},\"id\":2,\"ok\":false}\n\
+				 {\"error\":{\"category\":\"malformed\",\"code\":8,\"message\":\"Unknown client version: garbage\"},\"id\":4,\"ok\":false}\n\
+				 {\"error\":{\"category\":\"argument\",\"code\":2,\"message\":\"the member \\\"lockdate\\\" is missing\"},\"id\":5,\"ok\":false}\n\
+				 {\"error\":{\"category\":\"argument\",\"code\":2,\"message\":\"unknown operation \\\"sign\\\" (expected encrypt or decrypt)\"},\"id\":6,\"ok\":false}\n\
+				 {\"error\":{\"category\":\"argument\",\"code\":2,\"message\":\"the member \\\"force\\\" must be a boolean\"},\"id\":7,\"ok\":false}\n");
+	
+	//=========================================================================
+	// A batch cannot be combined with another operation
+    cli()?
+    	.arg("--batch")
+    	.arg("-d")
+		.assert()
+		.code(2);
+
+    Ok(())
+}
+
+#[test]
+fn seal_manifest() -> Result<(), Box<dyn std::error::Error>> {
+	let server: MockServer = MockServer::start()?;
+	
+	let plaintext = "Hello world";
+	let dir: std::path::PathBuf = std::env::temp_dir().join(format!("snailcrypt-cli-seal-{}", std::process::id()));
+	std::fs::create_dir_all(&dir)?;
+	std::fs::write(dir.join("exam.txt"), plaintext)?;
+	std::fs::write(dir.join("manifest.json"),
+				   "{\"entries\": [{\"input\": \"exam.txt\", \"output\": \"exam.txt.snail\", \"lockdate\": \"2022-11-19T17:00:00+0100\", \"hint\": \"hint\", \"force\": true}]}")?;
+	
+	//=========================================================================
+	// Perform encryption of the manifest
+    server.command()?
+    	.arg("seal")
+		.arg("-i")
+		.arg(dir.join("manifest.json"))
+		.assert()
+		.success()
+		.stdout(predicate::str::contains("\"encrypted\": 1"));
+	
+	//=========================================================================
+	// Perform decryption of the output file
+    server.command()?
+    	.arg("-d")
+		.arg("-i")
+		.arg(dir.join("exam.txt.snail"))
+		.assert()
+		.success()
+		.stdout(plaintext);
+	
+	std::fs::remove_dir_all(&dir)?;
+
+    Ok(())
+}
+
+#[test]
+fn seal_manifest_errors() -> Result<(), Box<dyn std::error::Error>> {
+	//=========================================================================
+	// The first failing entry stops the run
+    cli()?
+    	.arg("seal")
+		.arg("-i")
+		.arg("tests/data/manifest.toml")
+		.assert()
+		.code(2)
+		.stdout(predicate::str::contains("\"failed\": 1")
+				.and(predicate::str::contains("\"skipped\": 2")))
+		.stderr(predicate::str::ends_with("Encrypted 0 of 3 file(s), 1 failed, 2 skipped.\nError: lock date \"2000-01-01T00:00:00+0000\" is in the past.\n"));
+	
+	//=========================================================================
+	// Continue with the remaining entries
+    cli()?
+    	.arg("seal")
+		.arg("--continue-on-error")
+		.arg("-i")
+		.arg("tests/data/manifest.toml")
+		.assert()
+		.code(2)
+		.stdout(predicate::str::contains("\"failed\": 3")
+				.and(predicate::str::contains("\"category\": \"io\""))
+				.and(predicate::str::contains("the member \\\"lockdate\\\" is missing")))
+		.stderr(predicate::str::contains("Encrypted 0 of 3 file(s), 3 failed, 0 skipped."));
+	assert!(!std::path::Path::new("tests/data/blank.png.snail.part").exists());
+	
+	//=========================================================================
+	// Malformed manifest
+    cli()?
+    	.arg("seal")
+		.write_stdin("{\"files\": []}")
+		.assert()
+		.code(4)
+		.stderr("Error: the manifest does not contain a list of \"entries\"\n");
+
+    Ok(())
+}
+
+#[test]
+fn decrypt_directory() -> Result<(), Box<dyn std::error::Error>> {
+	let server: MockServer = MockServer::start()?;
+	
+	let plaintext = "Hello world";
+	let dir: std::path::PathBuf = std::env::temp_dir().join(format!("snailcrypt-cli-directory-{}", std::process::id()));
+	std::fs::create_dir_all(&dir)?;
+	std::fs::write(dir.join("locked.snail"), "2:MjAzMC0wMS0wMVQwMDowMDowMCswMDAw:AA+A:aGk=")?;
+	std::fs::write(dir.join("notes.txt"), "no ciphertext")?;
+	
+	//=========================================================================
+	// Perform encryption
+    server.command()?
+    	.arg("-e")
+		.arg("2022-11-19T17:00:00+0100")
+		.arg("-f")
+		.arg("-o")
+		.arg(dir.join("message.txt.snail"))
+		.write_stdin(plaintext)
+		.assert()
+		.success();
+	
+	//=========================================================================
+	// Perform decryption of the directory
+    server.command()?
+    	.arg("-d")
+		.arg("-i")
+		.arg(&dir)
+		.arg("-o")
+		.arg(dir.join("plain"))
+		.assert()
+		.success()
+		.stdout(predicate::str::contains("locked.snail  2030-01-01T00:00:00+0000"))
+		.stderr(predicate::str::ends_with("Decrypted 1 file(s), 1 still locked, 0 failed, 1 skipped.\n"));
+	assert_eq!(std::fs::read_to_string(dir.join("plain").join("message.txt"))?, plaintext);
+	
+	std::fs::remove_dir_all(&dir)?;
+
+    Ok(())
+}
+
+#[test]
+fn decrypt_directory_locked() -> Result<(), Box<dyn std::error::Error>> {
+	//=========================================================================
+	// Files which are still locked are listed without contacting the server
+    cli()?
+    	.arg("-d")
+		.arg("-i")
+		.arg("tests/data/locked.*")
+		.assert()
+		.success()
+		.stdout(predicate::str::starts_with("FILE                   LOCK DATE                 REMAINING")
+				.and(predicate::str::contains("tests/data/locked.jpg  2030-01-01T00:00:00+0000"))
+				.and(predicate::str::contains("tests/data/locked.png  2030-01-01T00:00:00+0000")))
+		.stderr("Decrypted 0 file(s), 2 still locked, 0 failed, 0 skipped.\n");
+	
+	//=========================================================================
+	// Files which are not encrypted messages are skipped
+    cli()?
+    	.arg("-d")
+		.arg("--json")
+		.arg("-i")
+		.arg("tests/data")
+		.assert()
+		.success()
+		.stdout(predicate::str::contains("{\"file\":\"tests/data/locked.png\",\"hint\":\"hi\",\"lockdate\":\"2030-01-01T00:00:00+0000\"}")
+				.and(predicate::str::contains("{\"file\":\"tests/data/blank.png\",\"reason\":\"no QR code has been found in the image\"}")));
+	
+	//=========================================================================
+	// Pattern without any match
+    cli()?
+    	.arg("-d")
+		.arg("-i")
+		.arg("tests/data/*.nothing")
+		.assert()
+		.code(2)
+		.stderr("Error: no file matches the pattern \"tests/data/*.nothing\"\n");
+
+    Ok(())
+}
+
+#[test]
+fn watch() -> Result<(), Box<dyn std::error::Error>> {
+	let dir: std::path::PathBuf = std::env::temp_dir().join(format!("snailcrypt-cli-watch-{}", std::process::id()));
+	std::fs::create_dir_all(&dir)?;
+	std::fs::write(dir.join("locked.snail"), "2:MjAzMC0wMS0wMVQwMDowMDowMCswMDAw:AA+A:aGk=")?;
+	std::fs::write(dir.join("notes.txt"), "no ciphertext")?;
+	
+	//=========================================================================
+	// Watching only ends when the process is stopped
+    cli()?
+    	.arg("watch")
+		.arg("-i")
+		.arg(&dir)
+		.timeout(std::time::Duration::from_secs(2))
+		.assert()
+		.interrupted()
+		.stderr(predicate::str::contains("locked.snail, which unlocks at 2030-01-01T00:00:00+0000")
+				.and(predicate::str::contains("notes.txt").not()));
+	
+	std::fs::remove_dir_all(&dir)?;
+	
+	//=========================================================================
+	// The input must be a directory
+    cli()?
+    	.arg("watch")
+		.arg("-i")
+		.arg("tests/data/locked.png")
+		.assert()
+		.code(2)
+		.stderr("Error: the watched path \"tests/data/locked.png\" is not a directory\n");
+    cli()?
+    	.arg("watch")
+		.assert()
+		.code(2);
+
+    Ok(())
+}
+
+#[test]
+fn decrypt_on_unlock() -> Result<(), Box<dyn std::error::Error>> {
+	let server: MockServer = MockServer::start()?;
+	
+	let plaintext = "Hello world";
+	
+	//=========================================================================
+	// Perform encryption
+    let encrypted = server.command()?
+    	.arg("-e")
+		.arg("2022-11-19T17:00:00+0100")
+		.arg("-f")
+		.arg("-t")
+		.arg("hint")
+		.write_stdin(plaintext)
+		.assert()
+		.success();
+	let ciphertext: String = String::from_utf8(encrypted.get_output().stdout.to_owned())?;
+	
+	//=========================================================================
+	// The hook receives the plaintext and the metadata. Its output is
+	// written to stderr.
+    server.command()?
+    	.arg("-d")
+		.arg("--wait")
+		.arg("--on-unlock")
+		.arg("echo \"$SNAILCRYPT_LOCKDATE $SNAILCRYPT_HINT $(cat)\"")
+		.write_stdin(ciphertext.clone())
+		.assert()
+		.success()
+		.stdout(plaintext)
+		.stderr("2022-11-19T17:00:00+0100 hint Hello world\n");
+	
+	//=========================================================================
+	// A failing hook
+    server.command()?
+    	.arg("-d")
+		.arg("--wait")
+		.arg("--on-unlock")
+		.arg("exit 3")
+		.write_stdin(ciphertext.clone())
+		.assert()
+		.code(9)
+		.stderr("Error: the hook \"exit 3\" failed with exit status: 3\n");
+	
+	//=========================================================================
+	// A hook exceeding its timeout
+    server.command()?
+    	.arg("-d")
+		.arg("--wait")
+		.arg("--on-unlock")
+		.arg("sleep 10")
+		.arg("--on-unlock-timeout")
+		.arg("1s")
+		.write_stdin(ciphertext.clone())
+		.assert()
+		.code(9)
+		.stderr("Error: the hook \"sleep 10\" has been killed after 1 second\n");
+	
+	//=========================================================================
+	// The processes started by a hook are killed together with it
+	if cfg!(unix) {
+		let started: std::path::PathBuf = std::env::temp_dir().join(format!("snailcrypt-cli-hook-{}", std::process::id()));
+		let _ = std::fs::remove_file(&started);
+	    server.command()?
+	    	.arg("-d")
+			.arg("--wait")
+			.arg("--on-unlock")
+			.arg(format!("(sleep 2; touch {}) & sleep 10", started.display()))
+			.arg("--on-unlock-timeout")
+			.arg("1s")
+			.write_stdin(ciphertext)
+			.assert()
+			.code(9);
+		std::thread::sleep(std::time::Duration::from_secs(2));
+		assert!(started.exists() == false);
+	}
+
+    Ok(())
+}
+
+#[test]
+fn decrypt_fail_on_unlock() -> Result<(), Box<dyn std::error::Error>> {
+	//=========================================================================
+	// A hook requires waiting
+    cli()?
+    	.arg("-d")
+		.arg("--on-unlock")
+		.arg("cat")
+		.write_stdin("2:MjAzMC0wMS0wMVQwMDowMDowMCswMDAw:AA+A:aGk=")
+		.assert()
+		.code(2)
+		.stderr("Error: option 'on-unlock' is only used for --wait and watch\n");
+	
+	//=========================================================================
+	// Unknown timeout
+    cli()?
+    	.arg("-d")
+		.arg("--wait")
+		.arg("--on-unlock")
+		.arg("cat")
+		.arg("--on-unlock-timeout")
+		.arg("forever")
+		.write_stdin("2:MjAzMC0wMS0wMVQwMDowMDowMCswMDAw:AA+A:aGk=")
+		.assert()
+		.code(4)
+		.stderr(predicate::str::starts_with("Error: unable to parse the timeout of the hook \"forever\""));
+
+    Ok(())
+}
+
+#[test]
+fn ledger() -> Result<(), Box<dyn std::error::Error>> {
+	let dir: std::path::PathBuf = std::env::temp_dir().join(format!("snailcrypt-cli-ledger-{}", std::process::id()));
+	std::fs::create_dir_all(dir.join("snailcrypt"))?;
+	std::fs::write(dir.join("snailcrypt").join("ledger.jsonl"),
+				   "{\"created\":\"2024-01-01T00:00:00+0000\",\"fingerprint\":\"aaaa1111bbbb2222cccc3333dddd4444eeee5555ffff6666aaaa1111bbbb2222\",\"hint\":\"Math exam\",\"label\":\"exams\",\"lockdate\":\"2030-06-03T08:00:00+0200\",\"output\":\"/srv/exams/math.snail\"}\n\
+					{\"created\":\"2024-01-01T00:00:00+0000\",\"fingerprint\":\"aaaa9999bbbb2222cccc3333dddd4444eeee5555ffff6666aaaa1111bbbb2222\",\"hint\":\"Old\",\"label\":null,\"lockdate\":\"2020-01-01T00:00:00+0000\",\"output\":null}\n\
+					{\"created\":\"2024-01-01T00:00:00+0000\",\"fingerprint\":\"1234567890ab2222cccc3333dddd4444eeee5555ffff6666aaaa1111bbbb2222\",\"hint\":\"Physics exam\",\"label\":\"exams\",\"lockdate\":\"2030-06-04T08:00:00+0200\",\"output\":\"/srv/exams/physics.snail\"}\n")?;
+	
+	//=========================================================================
+	// List the messages ordered by lock date
+    cli()?
+    	.env("XDG_DATA_HOME", &dir)
+    	.arg("ledger")
+		.arg("list")
+		.assert()
+		.success()
+		.stdout(predicate::str::is_match("^ID +LOCK DATE +REMAINING +LABEL +HINT +OUTPUT\n\
+										  aaaa9999bbbb  2020-01-01T00:00:00\\+0000  unlocked +Old\n\
+										  aaaa1111bbbb  2030-06-03T08:00:00\\+0200  .+  exams  Math exam +/srv/exams/math.snail\n\
+										  1234567890ab  2030-06-04T08:00:00\\+0200  .+  exams  Physics exam  /srv/exams/physics.snail\n$")?);
+	
+	//=========================================================================
+	// List the upcoming messages within a range of lock dates
+    cli()?
+    	.env("XDG_DATA_HOME", &dir)
+    	.arg("ledger")
+		.arg("upcoming")
+		.arg("--label")
+		.arg("exams")
+		.arg("--until")
+		.arg("2030-06-03T12:00:00+0200")
+		.arg("--json")
+		.assert()
+		.success()
+		.stdout("[{\"created\":\"2024-01-01T00:00:00+0000\",\"fingerprint\":\"aaaa1111bbbb2222cccc3333dddd4444eeee5555ffff6666aaaa1111bbbb2222\",\"hint\":\"Math exam\",\"label\":\"exams\",\"lockdate\":\"2030-06-03T08:00:00+0200\",\"output\":\"/srv/exams/math.snail\",\"server\":null}]\n");
+	
+	//=========================================================================
+	// Show a message by a prefix of its fingerprint
+    cli()?
+    	.env("XDG_DATA_HOME", &dir)
+    	.arg("ledger")
+		.arg("show")
+		.arg("1234")
+		.assert()
+		.success()
+		.stdout(predicate::str::contains("Hint:        Physics exam\n"));
+    cli()?
+    	.env("XDG_DATA_HOME", &dir)
+    	.arg("ledger")
+		.arg("show")
+		.arg("aaaa")
+		.assert()
+		.code(2)
+		.stderr("Error: the id \"aaaa\" matches 2 messages of the ledger\n");
+	
+	//=========================================================================
+	// Forget a message
+    cli()?
+    	.env("XDG_DATA_HOME", &dir)
+    	.arg("ledger")
+		.arg("forget")
+		.arg("aaaa9")
+		.assert()
+		.success()
+		.stderr("Forgot the message aaaa9999bbbb.\n");
+    cli()?
+    	.env("XDG_DATA_HOME", &dir)
+    	.arg("ledger")
+		.arg("show")
+		.arg("aaaa9")
+		.assert()
+		.code(2);
+	
+	std::fs::remove_dir_all(&dir)?;
+
+    Ok(())
+}
+
+#[test]
+fn encrypt_ledger() -> Result<(), Box<dyn std::error::Error>> {
+	let server: MockServer = MockServer::start()?;
+	
+	let dir: std::path::PathBuf = std::env::temp_dir().join(format!("snailcrypt-cli-encrypt-ledger-{}", std::process::id()));
+	
+	//=========================================================================
+	// Perform encryption
+    server.command()?
+    	.env("XDG_DATA_HOME", &dir)
+    	.arg("-e")
+		.arg("+1d")
+		.arg("-t")
+		.arg("hint")
+		.arg("--ledger")
+		.arg("--label")
+		.arg("release")
+		.write_stdin("Hello world")
+		.assert()
+		.success();
+	
+	//=========================================================================
+	// The message is listed as upcoming
+    server.command()?
+    	.env("XDG_DATA_HOME", &dir)
+    	.arg("ledger")
+		.arg("upcoming")
+		.arg("--label")
+		.arg("release")
+		.assert()
+		.success()
+		.stdout(predicate::str::contains("release  hint"));
+	
+	std::fs::remove_dir_all(&dir)?;
+
+    Ok(())
+}
+
+#[test]
+fn ledger_ics() -> Result<(), Box<dyn std::error::Error>> {
+	let dir: std::path::PathBuf = std::env::temp_dir().join(format!("snailcrypt-cli-ledger-ics-{}", std::process::id()));
+	std::fs::create_dir_all(dir.join("snailcrypt"))?;
+	std::fs::write(dir.join("snailcrypt").join("ledger.jsonl"),
+				   "{\"created\":\"2024-01-01T00:00:00+0000\",\"fingerprint\":\"aaaa1111bbbb2222cccc3333dddd4444eeee5555ffff6666aaaa1111bbbb2222\",\"hint\":\"Math exam; part 1, a hint long enough to fold the summary\",\"label\":\"exams\",\"lockdate\":\"2030-06-03T08:00:00+0200\",\"output\":\"/srv/exams/math.snail\"}\n")?;
+	
+	//=========================================================================
+	// Export the messages of the ledger as events
+    cli()?
+    	.env("XDG_DATA_HOME", &dir)
+    	.arg("ledger")
+		.arg("ics")
+		.arg("--ics-alarm")
+		.arg("1d2h")
+		.assert()
+		.success()
+		.stdout(predicate::str::starts_with("BEGIN:VCALENDAR\r\nVERSION:2.0\r\n")
+				.and(predicate::str::contains("UID:aaaa1111bbbb2222cccc3333dddd4444eeee5555ffff6666aaaa1111bbbb2222@snailc\r\n rypt.com\r\n"))
+				.and(predicate::str::contains("DTSTART:20300603T060000Z\r\n"))
+				.and(predicate::str::contains("SUMMARY:Math exam\\; part 1\\, a hint long enough to fold the summary\r\n"))
+				.and(predicate::str::contains("TRIGGER:-P1DT2H\r\n"))
+				.and(predicate::str::ends_with("END:VEVENT\r\nEND:VCALENDAR\r\n")));
+	
+	std::fs::remove_dir_all(&dir)?;
+	
+	//=========================================================================
+	// Unknown alarm
+    cli()?
+    	.arg("-e")
+		.arg("+1d")
+		.arg("--ics")
+		.arg("message.ics")
+		.arg("--ics-alarm")
+		.arg("soon")
+		.write_stdin("Hello world")
+		.assert()
+		.code(4)
+		.stderr(predicate::str::contains("Error: unable to parse the alarm \"soon\""));
+
+    Ok(())
+}
+
+#[test]
+fn encrypt_ics() -> Result<(), Box<dyn std::error::Error>> {
+	let server: MockServer = MockServer::start()?;
+	
+	let ics_file: std::path::PathBuf = std::env::temp_dir().join(format!("snailcrypt-cli-{}.ics", std::process::id()));
+	
+	//=========================================================================
+	// Perform encryption
+    let encrypted = server.command()?
+    	.arg("-e")
+		.arg("2022-11-19T17:00:00+0100")
+		.arg("-f")
+		.arg("-u")
+		.arg("-t")
+		.arg("hint")
+		.arg("--ics")
+		.arg(&ics_file)
+		.write_stdin("Hello world")
+		.assert()
+		.success();
+	let url: String = String::from_utf8(encrypted.get_output().stdout.to_owned())?;
+	
+	//=========================================================================
+	// The event is placed at the lock date and links the timer
+	let ics: String = std::fs::read_to_string(&ics_file)?.replace("\r\n ", "");
+	assert!(ics.contains("DTSTART:20221119T160000Z\r\n"));
+	assert!(ics.contains("SUMMARY:hint\r\n"));
+	assert!(ics.contains(format!("URL;VALUE=URI:{}\r\n", url).as_str()));
+	
+	std::fs::remove_file(&ics_file)?;
+
+    Ok(())
+}
+
+#[test]
+fn encrypt_ics_lockdate() -> Result<(), Box<dyn std::error::Error>> {
+	//=========================================================================
+	// Select an event by its UID and by its summary
+	for (event, lockdate) in [("launch-1@example.com", "2030-06-03T08:00:00+0200"),
+							  ("with guests", "2030-06-04T18:00:00+0000")] {
+	    cli()?
+	    	.arg("-e")
+			.arg(format!("tests/data/events.ics#{}", event))
+			.write_stdin("Hello world")
+			.assert()
+			.stderr(predicate::str::contains(format!("Lock date: {}\n", lockdate)));
+	}
+
+	//=========================================================================
+	// Select an occurrence of a recurring event. The occurrence on 2030-01-14
+	// has been moved.
+	for (occurrence, lockdate) in [("2030-01-17", "2030-01-17T09:30:00-0500"),
+								   ("2030-01-14", "2030-01-14T11:00:00-0500"),
+								   ("2030-03-14", "2030-03-14T09:30:00-0400")] {
+	    cli()?
+	    	.arg("-e")
+			.arg("tests/data/events.ics#standup@example.com")
+			.arg("--occurrence")
+			.arg(occurrence)
+			.write_stdin("Hello world")
+			.assert()
+			.stderr(predicate::str::contains(format!("Lock date: {}\n", lockdate)));
+	}
+
+	//=========================================================================
+	// Excluded occurrences and occurrences beyond the count of the rule
+	for occurrence in ["2030-01-10", "2030-01-15", "2030-03-18"] {
+	    cli()?
+	    	.arg("-e")
+			.arg("tests/data/events.ics#Weekly standup")
+			.arg("--occurrence")
+			.arg(occurrence)
+			.write_stdin("Hello world")
+			.assert()
+			.code(2)
+			.stderr(format!("Error: the event \"Weekly standup\" does not occur on {}\n", occurrence));
+	}
+
+	//=========================================================================
+	// Recurring event without an occurrence
+    cli()?
+    	.arg("-e")
+		.arg("tests/data/events.ics#standup")
+		.write_stdin("Hello world")
+		.assert()
+		.code(2)
+		.stderr("Error: the event \"Weekly standup\" is recurring; select an occurrence using --occurrence (e.g. \"2030-01-07\")\n");
+
+	//=========================================================================
+	// Ambiguous and missing events
+    cli()?
+    	.arg("-e")
+		.arg("tests/data/events.ics#launch")
+		.write_stdin("Hello world")
+		.assert()
+		.code(2)
+		.stderr(predicate::str::contains("2 events of the calendar \"tests/data/events.ics\" match \"launch\""));
+    cli()?
+    	.arg("-e")
+		.arg("tests/data/events.ics")
+		.write_stdin("Hello world")
+		.assert()
+		.code(2)
+		.stderr(predicate::str::contains("the calendar \"tests/data/events.ics\" contains 3 events"));
+    cli()?
+    	.arg("-e")
+		.arg("tests/data/events.ics#nothing")
+		.write_stdin("Hello world")
+		.assert()
+		.code(2)
+		.stderr("Error: no event of the calendar \"tests/data/events.ics\" matches \"nothing\"\n");
+
+	//=========================================================================
+	// Intervals beyond the range of dates
+	let ics_file: std::path::PathBuf = std::env::temp_dir().join(format!("snailcrypt-cli-interval-{}.ics", std::process::id()));
+	for rrule in ["FREQ=DAILY;INTERVAL=100000000", "FREQ=WEEKLY;INTERVAL=4000000000",
+				  "FREQ=WEEKLY;BYDAY=MO;INTERVAL=4000000000", "FREQ=YEARLY;INTERVAL=4000000000"] {
+		std::fs::write(&ics_file,
+					   format!("BEGIN:VCALENDAR\r\nBEGIN:VEVENT\r\nUID:a\r\nDTSTART:20291231T120000Z\r\nRRULE:{}\r\nEND:VEVENT\r\nEND:VCALENDAR\r\n",
+							   rrule))?;
+	    cli()?
+	    	.arg("-e")
+			.arg(format!("{}#a", ics_file.display()))
+			.arg("--occurrence")
+			.arg("2030-01-01")
+			.write_stdin("Hello world")
+			.assert()
+			.code(2)
+			.stderr("Error: the event \"a\" does not occur on 2030-01-01\n");
+	}
+
+	//=========================================================================
+	// The first day of the week delimits the weeks of a weekly rule. The event
+	// starts on a Tuesday.
+	for (wkst, occurrence, other) in [("MO", "2030-01-06", "2030-01-13"), ("SU", "2030-01-13", "2030-01-06")] {
+		std::fs::write(&ics_file,
+					   format!("BEGIN:VCALENDAR\r\nBEGIN:VEVENT\r\nUID:a\r\nDTSTART:20300101T120000Z\r\nRRULE:FREQ=WEEKLY;INTERVAL=2;BYDAY=TU,SU;WKST={}\r\nEND:VEVENT\r\nEND:VCALENDAR\r\n",
+							   wkst))?;
+	    cli()?
+	    	.arg("-e")
+			.arg(format!("{}#a", ics_file.display()))
+			.arg("--occurrence")
+			.arg(occurrence)
+			.write_stdin("Hello world")
+			.assert()
+			.stderr(predicate::str::contains(format!("Lock date: {}T12:00:00+0000\n", occurrence)));
+	    cli()?
+	    	.arg("-e")
+			.arg(format!("{}#a", ics_file.display()))
+			.arg("--occurrence")
+			.arg(other)
+			.write_stdin("Hello world")
+			.assert()
+			.code(2)
+			.stderr(format!("Error: the event \"a\" does not occur on {}\n", other));
+	}
+
+	//=========================================================================
+	// Parts of a rule beyond the supported ones are rejected
+	for (rrule, message) in [("FREQ=MONTHLY;BYMONTHDAY=1", "the recurrence rule part \"BYMONTHDAY=1\" of the event is not supported"),
+							 ("FREQ=YEARLY;BYMONTH=6", "the recurrence rule part \"BYMONTH=6\" of the event is not supported"),
+							 ("FREQ=MONTHLY;BYDAY=MO,TU;BYSETPOS=-1", "the recurrence rule part \"BYSETPOS=-1\" of the event is not supported"),
+							 ("FREQ=MONTHLY;BYDAY=MO", "the recurrence rule part \"BYDAY\" of the event is not supported"),
+							 ("FREQ=WEEKLY;BYDAY=1MO", "the recurrence rule part \"BYDAY=1MO\" of the event is not supported"),
+							 ("FREQ=WEEKLY;BYDAY=-1FR", "the recurrence rule part \"BYDAY=-1FR\" of the event is not supported"),
+							 ("FREQ=WEEKLY;WKST=XX", "the recurrence rule part \"WKST=XX\" of the event is not supported"),
+							 ("FREQ=DAILY;BYHOUR=9", "the recurrence rule part \"BYHOUR=9\" of the event is not supported"),
+							 ("FREQ=DAILY;COUNT", "the recurrence rule part \"COUNT\" of the event is not supported"),
+							 ("FREQ=DAILY;FREQ=WEEKLY", "the recurrence rule part \"FREQ\" of the event occurs more than once"),
+							 ("FREQ=HOURLY", "the recurrence frequency \"HOURLY\" of the event is not supported")] {
+		std::fs::write(&ics_file,
+					   format!("BEGIN:VCALENDAR\r\nBEGIN:VEVENT\r\nUID:a\r\nDTSTART:20300101T120000Z\r\nRRULE:{}\r\nEND:VEVENT\r\nEND:VCALENDAR\r\n",
+							   rrule))?;
+	    cli()?
+	    	.arg("-e")
+			.arg(format!("{}#a", ics_file.display()))
+			.arg("--occurrence")
+			.arg("2030-01-08")
+			.write_stdin("Hello world")
+			.assert()
+			.code(4)
+			.stderr(format!("Error: {}\n", message));
+	}
+	std::fs::remove_file(&ics_file)?;
+
+	//=========================================================================
+	// An occurrence requires a calendar event
+    cli()?
+    	.arg("-e")
+		.arg("+1d")
+		.arg("--occurrence")
+		.arg("2030-01-17")
+		.write_stdin("Hello world")
+		.assert()
+		.code(2);
+
+    Ok(())
+}
+
+#[test]
+fn config_show() -> Result<(), Box<dyn std::error::Error>> {
+	let dir: std::path::PathBuf = std::env::temp_dir().join(format!("snailcrypt-cli-config-{}", std::process::id()));
+	std::fs::create_dir_all(dir.join("snailcrypt"))?;
+	std::fs::write(dir.join("snailcrypt").join("config.toml"),
+				   "timezone = \"Europe/Vienna\"\n\n[output]\narmor = true\n\n[retry]\ninitial = \"2s\"\n")?;
+	let config_file: String = dir.join("snailcrypt").join("config.toml").display().to_string();
+
+	//=========================================================================
+	// The environment overrides the file, options override the environment
+    cli()?
+    	.env("XDG_CONFIG_HOME", &dir)
+    	.env("SNAILCRYPT_URL_MAX_LENGTH", "2000")
+    	.env("SNAILCRYPT_ARMOR", "false")
+    	.env("SNAILCRYPT_LEDGER", "1")
+    	.arg("config")
+		.arg("show")
+		.arg("-a")
+		.assert()
+		.success()
+		.stdout(predicate::str::starts_with("SETTING          VALUE                       SOURCE\n")
+				.and(predicate::str::contains(format!("timezone         Europe/Vienna               {}\n", config_file)))
+				.and(predicate::str::contains("output.armor     true                        --armor\n"))
+				.and(predicate::str::contains("url.max_length   2000                        SNAILCRYPT_URL_MAX_LENGTH\n"))
+				.and(predicate::str::contains("ledger.enabled   1                           SNAILCRYPT_LEDGER\n"))
+				.and(predicate::str::contains("retry.max        1m                          default\n")));
+
+    let output = cli()?
+    	.env("XDG_CONFIG_HOME", &dir)
+    	.arg("config")
+		.arg("--json")
+		.output()?;
+	let settings: serde_json::Value = serde_json::from_slice(output.stdout.as_slice())?;
+	assert_eq!(settings["retry.initial"]["value"], "2s");
+	assert_eq!(settings["retry.initial"]["source"], config_file.as_str());
+	assert_eq!(settings["output.json"]["source"], "--json");
+
+	//=========================================================================
+	// The configured time zone applies to lock dates without one
+    cli()?
+    	.env("XDG_CONFIG_HOME", &dir)
+    	.arg("-e")
+		.arg("2030-06-03 08:00")
+		.write_stdin("Hello world")
+		.assert()
+		.stderr(predicate::str::contains("Lock date: 2030-06-03T08:00:00+0200\n"));
+
+	//=========================================================================
+	// Invalid settings
+	std::fs::write(dir.join("snailcrypt").join("config.toml"), "bogus = 1\n")?;
+    cli()?
+    	.env("XDG_CONFIG_HOME", &dir)
+    	.arg("config")
+		.assert()
+		.code(4)
+		.stderr(format!("Error: unknown setting \"bogus\" in the configuration file \"{}\"\n", config_file));
+
+	std::fs::remove_dir_all(&dir)?;
+
+    cli()?
+    	.env("SNAILCRYPT_RETRY_MAX", "soon")
+    	.arg("config")
+		.assert()
+		.code(4)
+		.stderr(predicate::str::contains("invalid value \"soon\" of the setting retry.max from SNAILCRYPT_RETRY_MAX"));
+
+    cli()?
+    	.env("SNAILCRYPT_LEDGER", "maybe")
+    	.arg("config")
+		.assert()
+		.code(4)
+		.stderr(predicate::str::contains("invalid value \"maybe\" of the setting ledger.enabled from SNAILCRYPT_LEDGER"));
+
+    Ok(())
+}
+
+#[test]
+fn config_output_defaults() -> Result<(), Box<dyn std::error::Error>> {
+	let server: MockServer = MockServer::start()?;
+
+	let dir: std::path::PathBuf = std::env::temp_dir().join(format!("snailcrypt-cli-config-defaults-{}", std::process::id()));
+	std::fs::create_dir_all(dir.join("snailcrypt"))?;
+	std::fs::write(dir.join("snailcrypt").join("config.toml"),
+				   "[output]\njson = true\n\n[url]\ngenerate = true\n")?;
+
+	//=========================================================================
+	// The configured URL and JSON output give way to an envelope container
+	let encrypted = server.command()?
+		.env("XDG_CONFIG_HOME", &dir)
+		.arg("-e")
+		.arg("2022-11-19T17:00:00+0100")
+		.arg("-f")
+		.arg("--envelope")
+		.write_stdin("Hello world")
+		.assert()
+		.success();
+	let envelope: Vec<u8> = encrypted.get_output().stdout.to_owned();
+
+	server.command()?
+		.env("XDG_CONFIG_HOME", &dir)
+		.arg("-d")
+		.write_stdin(envelope.clone())
+		.assert()
+		.success()
+		.stdout("Hello world");
+
+	//=========================================================================
+	// Options do not give way
+	server.command()?
+		.env("XDG_CONFIG_HOME", &dir)
+		.arg("-e")
+		.arg("2022-11-19T17:00:00+0100")
+		.arg("-f")
+		.arg("-u")
+		.arg("--envelope")
+		.write_stdin("Hello world")
+		.assert()
+		.code(2)
+		.stderr("Error: an envelope container cannot be embedded in a URL\n");
+	server.command()?
+		.env("XDG_CONFIG_HOME", &dir)
+		.arg("-d")
+		.arg("--json")
+		.write_stdin(envelope)
+		.assert()
+		.code(2)
+		.stderr(predicate::str::contains("an envelope container cannot be printed as JSON"));
+
+	std::fs::remove_dir_all(&dir)?;
+
+    Ok(())
+}
+
+#[test]
+fn decrypt_server_mismatch() -> Result<(), Box<dyn std::error::Error>> {
+	let armored: &str = "-----BEGIN SNAILCRYPT MESSAGE-----\nVersion: 2\nServer: https://staging.example/api\n\n2:MjAzMC0wMS0wMVQwMDowMDowMCswMDAw:AA+A:aGk=\n-----END SNAILCRYPT MESSAGE-----\n";
+
+	//=========================================================================
+	// Invalid servers
+	for (server, message) in [("ftp://staging.example", "the URL must use http or https"),
+							  ("https://staging.example/api?x=1", "the URL must not contain a query or a fragment"),
+							  ("staging", "relative URL without a base")] {
+	    cli()?
+	    	.arg("-d")
+	    	.arg("--server")
+			.arg(server)
+			.write_stdin(armored)
+			.assert()
+			.code(4)
+			.stderr(predicate::str::contains(format!("invalid value \"{}\" of the setting server from --server: {}", server, message)));
+	}
+
+	//=========================================================================
+	// The server is recorded in the header of an armored message
+    cli()?
+    	.arg("-d")
+    	.arg("-T")
+		.write_stdin(armored)
+		.assert()
+		.stderr(predicate::str::starts_with("Warning: the message has been encrypted with the server https://staging.example/api, but the server https://api.snailcrypt.com is used.\n"));
+    cli()?
+    	.env("SNAILCRYPT_SERVER", "https://staging.example/api/")
+    	.arg("-d")
+    	.arg("-T")
+		.write_stdin(armored)
+		.assert()
+		.stderr(predicate::str::contains("Warning:").not());
+
+	//=========================================================================
+	// Otherwise the server is looked up in the ledger
+	let ciphertext: &str = "2:MjAzMC0wMS0wMVQwMDowMDowMCswMDAw:AA+A:aGk=";
+	let fingerprint: String = openssl::sha::sha256(ciphertext.as_bytes())
+		.iter()
+		.map(|byte| format!("{:02x}", byte))
+		.collect();
+	let dir: std::path::PathBuf = std::env::temp_dir().join(format!("snailcrypt-cli-server-{}", std::process::id()));
+	std::fs::create_dir_all(dir.join("snailcrypt"))?;
+	std::fs::write(dir.join("snailcrypt").join("ledger.jsonl"),
+				   format!("{{\"created\":\"2024-01-01T00:00:00+0000\",\"fingerprint\":\"{}\",\"hint\":\"hi\",\"lockdate\":\"2030-01-01T00:00:00+0000\",\"server\":\"http://localhost:8080\"}}\n",
+						   fingerprint))?;
+
+    cli()?
+    	.env("XDG_DATA_HOME", &dir)
+    	.arg("-d")
+    	.arg("-T")
+		.write_stdin(ciphertext)
+		.assert()
+		.stderr(predicate::str::starts_with("Warning: the message has been encrypted with the server http://localhost:8080, but the server https://api.snailcrypt.com is used.\n"));
+    cli()?
+    	.env("XDG_DATA_HOME", &dir)
+    	.arg("ledger")
+    	.arg("show")
+    	.arg(&fingerprint[..12])
+		.assert()
+		.success()
+		.stdout(predicate::str::contains("Server:      http://localhost:8080\n"));
+
+	std::fs::remove_dir_all(&dir)?;
+
+    Ok(())
+}
+
+#[test]
+fn mock_lockdate_boundary() -> Result<(), Box<dyn std::error::Error>> {
+	let mut server: MockServer = MockServer::start()?;
+	server.control("clock 2030-01-01T00:00:00+0000")?;
+
+	//=========================================================================
+	// Perform encryption
+    let encrypted = server.command()?
+    	.arg("-e")
+		.arg("2030-01-01T00:00:01+0000")
+		.arg("-f")
+		.arg("-t")
+		.arg("hint")
+		.write_stdin("Hello world")
+		.assert()
+		.success();
+	let ciphertext: String = String::from_utf8(encrypted.get_output().stdout.to_owned())?;
+
+	//=========================================================================
+	// One second before the lock date the key is withheld
+    server.command()?
+    	.arg("-d")
+		.write_stdin(ciphertext.clone())
+		.assert()
+		.code(7)
+		.stderr("Error: the message is locked until 2030-01-01T00:00:01+0000\n");
+
+	//=========================================================================
+	// At the lock date it is released
+	server.control("advance 1s")?;
+    server.command()?
+    	.arg("-d")
+		.write_stdin(ciphertext)
+		.assert()
+		.success()
+		.stdout("Hello world");
+
+    Ok(())
+}
+
+#[test]
+fn mock_server_errors() -> Result<(), Box<dyn std::error::Error>> {
+	let mut server: MockServer = MockServer::start()?;
+
+	server.control("fault error db down")?;
+    server.command()?
+    	.arg("-e")
+		.arg("2022-11-19T17:00:00+0100")
+		.arg("-f")
+		.write_stdin("Hello world")
+		.assert()
+		.code(6)
+		.stderr("Error: db down\n");
+
+	server.control("fault 2 status 503")?;
+	for _ in 0..2 {
+	    server.command()?
+	    	.arg("-e")
+			.arg("2022-11-19T17:00:00+0100")
+			.arg("-f")
+			.write_stdin("Hello world")
+			.assert()
+			.code(6)
+			.stderr(predicate::str::starts_with("Error: unexpected response of the snailcrypt server: "));
+	}
+
+	server.control("fault malformed")?;
+    server.command()?
+    	.arg("-e")
+		.arg("2022-11-19T17:00:00+0100")
+		.arg("-f")
+		.write_stdin("Hello world")
+		.assert()
+		.code(6)
+		.stderr(predicate::str::starts_with("Error: unexpected response of the snailcrypt server: "));
+
+	server.control("fault drop")?;
+    server.command()?
+    	.arg("-e")
+		.arg("2022-11-19T17:00:00+0100")
+		.arg("-f")
+		.write_stdin("Hello world")
+		.assert()
+		.code(5)
+		.stderr(predicate::str::starts_with("Error: unable to reach the snailcrypt server: "));
+
+	//=========================================================================
+	// The faults are used up
+    let encrypted = server.command()?
+    	.arg("-e")
+		.arg("2022-11-19T17:00:00+0100")
+		.arg("-f")
+		.write_stdin("Hello world")
+		.assert()
+		.success();
+	let ciphertext: String = String::from_utf8(encrypted.get_output().stdout.to_owned())?;
+
+	//=========================================================================
+	// Waiting gives up on a server which keeps failing
+	server.control("fault 10 status 503")?;
+    server.command()?
+    	.env("SNAILCRYPT_RETRY_INITIAL", "1s")
+    	.env("SNAILCRYPT_RETRY_MAX", "1s")
+    	.arg("-d")
+		.arg("--wait")
+		.write_stdin(ciphertext)
+		.assert()
+		.code(6)
+		.stderr(predicate::str::starts_with("Error: unexpected response of the snailcrypt server: "));
+	server.control("reset")?;
+
+	//=========================================================================
+	// Invalid control commands are rejected
+	assert!(server.control("fault bogus").is_err());
+	assert!(server.control("advance 1x").is_err());
+	assert!(server.control("clock tomorrow").is_err());
+	server.control("reset")?;
+
+    Ok(())
+}
+
+#[test]
+fn mock_withheld_key() -> Result<(), Box<dyn std::error::Error>> {
+	let mut server: MockServer = MockServer::start()?;
+
+	//=========================================================================
+	// Perform encryption
+    let encrypted = server.command()?
+    	.arg("-e")
+		.arg("2022-11-19T17:00:00+0100")
+		.arg("-f")
+		.write_stdin("Hello world")
+		.assert()
+		.success();
+	let ciphertext: String = String::from_utf8(encrypted.get_output().stdout.to_owned())?;
+
+	//=========================================================================
+	// A key withheld beyond the lock date fails the decryption...
+	server.control("fault withhold")?;
+    server.command()?
+    	.arg("-d")
+		.write_stdin(ciphertext.clone())
+		.assert()
+		.code(7);
+
+	//=========================================================================
+	// ...unless the decryption waits for it
+	server.control("fault 2 withhold")?;
+    server.command()?
+    	.env("SNAILCRYPT_RETRY_INITIAL", "1s")
+    	.env("SNAILCRYPT_RETRY_MAX", "1s")
+    	.arg("-d")
+		.arg("--wait")
+		.write_stdin(ciphertext)
+		.assert()
+		.success()
+		.stdout("Hello world");
+
+    Ok(())
+}
+
+#[test]
+fn mock_large_payloads() -> Result<(), Box<dyn std::error::Error>> {
+	let server: MockServer = MockServer::start()?;
+
+	//=========================================================================
+	// A text of 100 KB
+	let plaintext: String = (0..100000).map(|i| (b'a' + (i % 26) as u8) as char).collect();
+    let encrypted = server.command()?
+    	.arg("-e")
+		.arg("2022-11-19T17:00:00+0100")
+		.arg("-f")
+		.write_stdin(plaintext.clone())
+		.assert()
+		.success();
+    server.command()?
+    	.arg("-d")
+		.write_stdin(encrypted.get_output().stdout.to_owned())
+		.assert()
+		.success()
+		.stdout(plaintext);
+
+	//=========================================================================
+	// An envelope of 8 MB
+	let plaintext: Vec<u8> = (0..8000000).map(|i| (i % 251) as u8).collect();
+    let encrypted = server.command()?
+    	.arg("-e")
+		.arg("2022-11-19T17:00:00+0100")
+		.arg("-f")
+		.arg("--envelope")
+		.write_stdin(plaintext.clone())
+		.assert()
+		.success();
+    server.command()?
+    	.arg("-d")
+		.write_stdin(encrypted.get_output().stdout.to_owned())
+		.assert()
+		.success()
+		.stdout(plaintext);
+
+    Ok(())
+}